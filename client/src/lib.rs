@@ -0,0 +1,391 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Typed client for the Patat-Protocol TA.
+//!
+//! ```no_run
+//! use patat_client::{AttestConfig, Context, PatatClient, Verdict};
+//!
+//! let mut context = Context::new()?;
+//! let mut client = PatatClient::open(&mut context)?;
+//! match client.attest(&AttestConfig::default())? {
+//!     Verdict::Attested { elapsed, .. } => println!("attested in {:?}", elapsed),
+//!     Verdict::Rejected => println!("the verifier rejected the TA"),
+//! }
+//! # Ok::<(), patat_client::Error>(())
+//! ```
+
+use optee_teec::{Operation, ParamNone, ParamTmpRef, ParamType, ParamValue, Session, Uuid};
+use proto::{Command, MAX_SIGNED_EVIDENCE_LEN, TIMINGS_LEN, UUID};
+use std::time::{Duration, Instant};
+
+pub use optee_teec::{Context, Error, ErrorKind, Result};
+pub use proto::{
+    DhBackend, DhFunction, HashFunction, Phase, Verifier, EVIDENCE_NONCE_LEN,
+    MAX_CERTIFICATE_CHAIN_LEN, MAX_MESSAGE_LEN,
+};
+
+/// Large enough for the public key of every `DhFunction`.
+const MAX_PUBLIC_KEY_LEN: usize = 256;
+
+/// How to attest to the verifier.
+#[derive(Clone)]
+pub struct AttestConfig {
+    pub verifier: Verifier,
+    pub hash: HashFunction,
+    pub dh: DhFunction,
+    /// Number of features the TA measures into the evidence.
+    pub features: u32,
+}
+
+impl Default for AttestConfig {
+    fn default() -> AttestConfig {
+        AttestConfig {
+            verifier: Verifier::default(),
+            hash: HashFunction::Sha256,
+            dh: DhFunction::Curve25519,
+            features: 15,
+        }
+    }
+}
+
+/// Outcome of an attestation.
+#[derive(Debug)]
+pub enum Verdict {
+    /// The TA completed the handshake and sent its evidence.
+    Attested {
+        /// Time spent in the TA as seen from the host.
+        elapsed: Duration,
+        /// Time per phase as measured by the TA.
+        phases: Vec<(Phase, Duration)>,
+    },
+    /// The TA aborted the handshake with the verifier.
+    Rejected,
+}
+
+/// What `PatatClient::benchmark` runs.
+pub enum Benchmark {
+    Attested(AttestConfig),
+    WithoutAttestation,
+}
+
+/// A single run of `PatatClient::benchmark`.
+pub struct Sample {
+    pub elapsed: Duration,
+    /// Time per phase, empty for `Benchmark::WithoutAttestation`.
+    pub phases: Vec<(Phase, Duration)>,
+}
+
+/// Result of `PatatClient::selftest`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SelfTestReport {
+    pub passed: u32,
+    pub failed: u32,
+}
+
+/// A session with the TA.
+pub struct PatatClient<'ctx> {
+    session: Session<'ctx>,
+}
+
+impl<'ctx> PatatClient<'ctx> {
+    /// Open a session with the TA in `context`.
+    pub fn open(context: &'ctx mut Context) -> Result<PatatClient<'ctx>> {
+        let uuid = Uuid::parse_str(UUID).unwrap();
+        Ok(PatatClient {
+            session: context.open_session(uuid)?,
+        })
+    }
+
+    /// Attest to the verifier in `config`.
+    ///
+    /// A verifier that aborts the handshake results in `Verdict::Rejected`.
+    /// Fails with `ErrorKind::Communication` if the verifier cannot be
+    /// reached or the connection breaks; any other failure of the TA is
+    /// returned as an error as well.
+    ///
+    /// After a successful attestation the TA keeps the channel to the
+    /// verifier open for `send_evidence` and `send_message`, until
+    /// `close_channel`, the next attestation or the end of the session.
+    pub fn attest(&mut self, config: &AttestConfig) -> Result<Verdict> {
+        self.verdict(Command::RunAttested, config)
+    }
+
+    /// Attest with a resumed handshake, based on the ticket the TA stored for
+    /// the verifier with `request_ticket`.
+    ///
+    /// Fails with `ErrorKind::ItemNotFound` if there is no usable ticket and
+    /// with `ErrorKind::Communication` if the verifier cannot be reached; a
+    /// verifier that rejects the ticket results in `Verdict::Rejected`. In
+    /// every case the ticket is used up, so the caller should fall back to
+    /// `attest`.
+    pub fn resume(&mut self, config: &AttestConfig) -> Result<Verdict> {
+        self.verdict(Command::RunResumed, config)
+    }
+
+    /// Ask the verifier for a resumption ticket over the attested channel.
+    ///
+    /// Returns the lifetime of the ticket, or `None` if the verifier did not
+    /// issue one.
+    pub fn request_ticket(&mut self) -> Result<Option<Duration>> {
+        let lifetime = ParamValue::new(0, 0, ParamType::ValueOutput);
+        let mut operation = Operation::new(0, lifetime, ParamNone, ParamNone, ParamNone);
+        self.session
+            .invoke_command(Command::RequestTicket as u32, &mut operation)?;
+        match operation.parameters().0.a() {
+            0 => Ok(None),
+            seconds => Ok(Some(Duration::from_secs(seconds as u64))),
+        }
+    }
+
+    /// Invoke `Command::RunWithoutAttestation` and return how long it took.
+    pub fn run_without_attestation(&mut self) -> Result<Duration> {
+        let mut operation = Operation::new(0, ParamNone, ParamNone, ParamNone, ParamNone);
+        let now = Instant::now();
+        self.session
+            .invoke_command(Command::RunWithoutAttestation as u32, &mut operation)?;
+        Ok(now.elapsed())
+    }
+
+    /// Run `benchmark` `iterations` times.
+    ///
+    /// Unlike `attest`, a rejected attestation is returned as an error, so
+    /// every sample measures a complete run.
+    pub fn benchmark(&mut self, benchmark: &Benchmark, iterations: u32) -> Result<Vec<Sample>> {
+        (0..iterations)
+            .map(|_| match benchmark {
+                Benchmark::Attested(config) => {
+                    let (elapsed, phases) = self.run_attested(Command::RunAttested, config)?;
+                    Ok(Sample { elapsed, phases })
+                }
+                Benchmark::WithoutAttestation => Ok(Sample {
+                    elapsed: self.run_without_attestation()?,
+                    phases: vec![],
+                }),
+            })
+            .collect()
+    }
+
+    /// Time `handshakes` XK handshakes inside the TA, from message 1 up to
+    /// the split, first with new TEE operations for every hash and HMAC, as
+    /// older TAs did, then with the operations allocated once per handshake.
+    pub fn bench_hmac(
+        &mut self,
+        hash: HashFunction,
+        handshakes: u32,
+    ) -> Result<(Duration, Duration)> {
+        let selection = ParamValue::new(hash as u32, handshakes, ParamType::ValueInput);
+        let result = ParamValue::new(0, 0, ParamType::ValueOutput);
+        let mut operation = Operation::new(0, selection, result, ParamNone, ParamNone);
+        self.session
+            .invoke_command(Command::BenchHmac as u32, &mut operation)?;
+        let result = operation.parameters().1;
+        Ok((
+            Duration::from_millis(result.a() as u64),
+            Duration::from_millis(result.b() as u64),
+        ))
+    }
+
+    /// Send another round of evidence over the attested channel, without a
+    /// new handshake.
+    ///
+    /// Fails with `ErrorKind::BadState` if there is no attested channel and
+    /// with `ErrorKind::Communication` if the connection to the verifier
+    /// broke, which also closes the channel.
+    pub fn send_evidence(&mut self, features: u32) -> Result<()> {
+        let features = ParamValue::new(features, 0, ParamType::ValueInput);
+        let mut operation = Operation::new(0, features, ParamNone, ParamNone, ParamNone);
+        self.session
+            .invoke_command(Command::SendEvidence as u32, &mut operation)
+    }
+
+    /// Append `features` measurements to the TA's evidence log and have it
+    /// report the new root to the verifier over the attested channel.
+    ///
+    /// Returns the number of measurements in the log, which the TA keeps per
+    /// hash function in secure storage. Fails like `send_evidence`.
+    pub fn append_evidence(&mut self, features: u32) -> Result<u32> {
+        let features = ParamValue::new(features, 0, ParamType::ValueInout);
+        let mut operation = Operation::new(0, features, ParamNone, ParamNone, ParamNone);
+        self.session
+            .invoke_command(Command::AppendEvidence as u32, &mut operation)?;
+        Ok(operation.parameters().0.b())
+    }
+
+    /// Send `message` over the attested channel and return the verifier's
+    /// reply.
+    ///
+    /// Fails like `send_evidence`, and with `ErrorKind::BadParameters` for
+    /// messages longer than `MAX_MESSAGE_LEN`.
+    pub fn send_message(&mut self, message: &[u8]) -> Result<Vec<u8>> {
+        let input = ParamTmpRef::new_input(message);
+        let mut reply = vec![0u8; MAX_MESSAGE_LEN];
+        let output = ParamTmpRef::new_output(&mut reply);
+        let mut operation = Operation::new(0, input, output, ParamNone, ParamNone);
+        self.session
+            .invoke_command(Command::SendMessage as u32, &mut operation)?;
+
+        let updated_size = operation.parameters().1.updated_size();
+        reply.truncate(updated_size);
+        Ok(reply)
+    }
+
+    /// Close the attested channel, if any.
+    pub fn close_channel(&mut self) -> Result<()> {
+        let mut operation = Operation::new(0, ParamNone, ParamNone, ParamNone, ParamNone);
+        self.session
+            .invoke_command(Command::CloseChannel as u32, &mut operation)
+    }
+
+    /// Public key of the TA's identity for `dh`, for the verifier to pin.
+    pub fn export_identity(&mut self, dh: DhFunction) -> Result<Vec<u8>> {
+        let selection = ParamValue::new(dh as u32, 0, ParamType::ValueInput);
+        let mut key = [0u8; MAX_PUBLIC_KEY_LEN];
+        let output = ParamTmpRef::new_output(&mut key);
+        let mut operation = Operation::new(0, selection, output, ParamNone, ParamNone);
+        self.session
+            .invoke_command(Command::ExportKey as u32, &mut operation)?;
+
+        let updated_size = operation.parameters().1.updated_size();
+        Ok(key[..updated_size].to_vec())
+    }
+
+    /// Install the certificate chain for the TA's identity for `dh`, which
+    /// the TA then sends to the verifier in every handshake.
+    ///
+    /// `chain` is an encoded `ta::certificate::CertificateChain` from the
+    /// manufacturer, for the key from `export_identity`. It may be at most
+    /// `MAX_CERTIFICATE_CHAIN_LEN` bytes. Fails with `ErrorKind::BadParameters`
+    /// if the chain does not parse or is for another key.
+    pub fn install_certificates(&mut self, dh: DhFunction, chain: &[u8]) -> Result<()> {
+        let selection = ParamValue::new(dh as u32, 0, ParamType::ValueInput);
+        let input = ParamTmpRef::new_input(chain);
+        let mut operation = Operation::new(0, selection, input, ParamNone, ParamNone);
+        self.session
+            .invoke_command(Command::InstallCertificates as u32, &mut operation)
+    }
+
+    /// Have the TA measure `features` features and sign the evidence with
+    /// `nonce`, without a verifier.
+    ///
+    /// The returned bundle can be checked offline with
+    /// `ta::signed_evidence::SignedEvidence` and the key from
+    /// `export_identity` for `DhFunction::Curve25519`.
+    pub fn sign_evidence(
+        &mut self,
+        hash: HashFunction,
+        features: u32,
+        nonce: &[u8; EVIDENCE_NONCE_LEN],
+    ) -> Result<Vec<u8>> {
+        let selection = ParamValue::new(hash as u32, features, ParamType::ValueInput);
+        let nonce = ParamTmpRef::new_input(nonce);
+        let mut bundle = vec![0u8; MAX_SIGNED_EVIDENCE_LEN];
+        let output = ParamTmpRef::new_output(&mut bundle);
+        let mut operation = Operation::new(0, selection, nonce, output, ParamNone);
+        self.session
+            .invoke_command(Command::SignEvidence as u32, &mut operation)?;
+
+        let updated_size = operation.parameters().2.updated_size();
+        bundle.truncate(updated_size);
+        Ok(bundle)
+    }
+
+    /// The implementation of X25519 the TA uses.
+    pub fn dh_backend(&mut self) -> Result<DhBackend> {
+        let backend = ParamValue::new(0, 0, ParamType::ValueOutput);
+        let mut operation = Operation::new(0, backend, ParamNone, ParamNone, ParamNone);
+        self.session
+            .invoke_command(Command::GetDhBackend as u32, &mut operation)?;
+        Ok(DhBackend::from(operation.parameters().0.a()))
+    }
+
+    pub fn selftest(&mut self) -> Result<SelfTestReport> {
+        let result = ParamValue::new(0, 0, ParamType::ValueOutput);
+        let mut operation = Operation::new(0, result, ParamNone, ParamNone, ParamNone);
+        self.session
+            .invoke_command(Command::SelfTest as u32, &mut operation)?;
+        let result = operation.parameters().0;
+        Ok(SelfTestReport {
+            passed: result.a(),
+            failed: result.b(),
+        })
+    }
+
+    fn verdict(&mut self, command: Command, config: &AttestConfig) -> Result<Verdict> {
+        match self.run_attested(command, config) {
+            Ok((elapsed, phases)) => Ok(Verdict::Attested { elapsed, phases }),
+            Err(e) if e.kind() == ErrorKind::Security => Ok(Verdict::Rejected),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn run_attested(
+        &mut self,
+        command: Command,
+        config: &AttestConfig,
+    ) -> Result<(Duration, Vec<(Phase, Duration)>)> {
+        let selection =
+            ParamValue::new(config.hash as u32, config.dh as u32, ParamType::ValueInput);
+        let features = ParamValue::new(config.features, 0, ParamType::ValueInput);
+        let mut timings = [0u8; TIMINGS_LEN];
+        let output = ParamTmpRef::new_output(&mut timings);
+        let verifier = config.verifier.to_bytes();
+        let verifier = ParamTmpRef::new_input(&verifier);
+        let mut operation = Operation::new(0, selection, features, output, verifier);
+
+        let now = Instant::now();
+        self.session
+            .invoke_command(command as u32, &mut operation)?;
+        let elapsed = now.elapsed();
+
+        let updated_size = operation.parameters().2.updated_size();
+        Ok((elapsed, phase_timings(&timings[..updated_size])))
+    }
+}
+
+/// Parse the per-phase timings returned by `Command::RunAttested`.
+fn phase_timings(bytes: &[u8]) -> Vec<(Phase, Duration)> {
+    Phase::ALL
+        .iter()
+        .zip(bytes.chunks_exact(4))
+        .map(|(phase, chunk)| {
+            let mut millis = [0u8; 4];
+            millis.copy_from_slice(chunk);
+            (
+                *phase,
+                Duration::from_millis(u32::from_le_bytes(millis) as u64),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phase_timings_are_little_endian_millis() {
+        let timings: Vec<Duration> = phase_timings(&[5, 0, 0, 0, 0, 1, 0, 0])
+            .into_iter()
+            .map(|(_, timing)| timing)
+            .collect();
+        assert_eq!(
+            timings,
+            vec![Duration::from_millis(5), Duration::from_millis(256)]
+        );
+    }
+}
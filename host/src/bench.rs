@@ -0,0 +1,305 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::cli::Options;
+use patat_client::{AttestConfig, Benchmark, PatatClient, Phase};
+use proto::Command;
+use std::time::Duration;
+
+/// Output format of the benchmark results.
+#[derive(Clone, Copy)]
+pub enum Format {
+    /// Comma separated values, one row per run.
+    Csv,
+    /// Org-mode tables, in the layout of `results.org`.
+    Org,
+}
+
+/// What to benchmark and how to report it.
+pub struct Config {
+    pub features: u32,
+    pub iterations: u32,
+    pub command: Command,
+    pub format: Format,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            features: 15,
+            iterations: 1000,
+            command: Command::RunAttested,
+            format: Format::Org,
+        }
+    }
+}
+
+impl Config {
+    /// Parse `--features N --iterations N --command attested|unattested|hmac
+    /// --format csv|org`; options that are left out keep their default.
+    ///
+    /// With `--command hmac` the iterations are handshakes that the TA runs
+    /// and times by itself, rather than runs of a command.
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<Config, String> {
+        let mut config = Config::default();
+        while let Some(arg) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("missing value for {}", arg))?;
+            match arg.as_str() {
+                "--features" => config.features = parse_features(&arg, &value)?,
+                "--iterations" => config.iterations = parse_number(&arg, &value)?,
+                "--command" => {
+                    config.command = match value.as_str() {
+                        "attested" => Command::RunAttested,
+                        "unattested" => Command::RunWithoutAttestation,
+                        "hmac" => Command::BenchHmac,
+                        _ => return Err(format!("unknown command {}", value)),
+                    }
+                }
+                "--format" => {
+                    config.format = match value.as_str() {
+                        "csv" => Format::Csv,
+                        "org" => Format::Org,
+                        _ => return Err(format!("unknown format {}", value)),
+                    }
+                }
+                _ => return Err(format!("unknown option {}", arg)),
+            }
+        }
+        if config.iterations == 0 {
+            return Err("--iterations must be at least 1".to_string());
+        }
+        Ok(config)
+    }
+}
+
+pub fn parse_number(option: &str, value: &str) -> Result<u32, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} expects a number, got {}", option, value))
+}
+
+/// Parse the number of features, of which the TA needs at least one to build
+/// its evidence log.
+pub fn parse_features(option: &str, value: &str) -> Result<u32, String> {
+    match parse_number(option, value)? {
+        0 => Err(format!("{} must be at least 1", option)),
+        features => Ok(features),
+    }
+}
+
+/// Summary of the timings of all runs.
+#[derive(Debug, PartialEq)]
+pub struct Summary {
+    pub min: Duration,
+    pub median: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+}
+
+impl Summary {
+    /// Summarize `timings`, which must not be empty.
+    pub fn new(timings: &[Duration]) -> Summary {
+        let mut sorted = timings.to_vec();
+        sorted.sort();
+        let n = sorted.len();
+        Summary {
+            min: sorted[0],
+            median: (sorted[(n - 1) / 2] + sorted[n / 2]) / 2,
+            p95: percentile(&sorted, 95),
+            p99: percentile(&sorted, 99),
+        }
+    }
+}
+
+/// Nearest-rank percentile of the already sorted `timings`.
+fn percentile(sorted: &[Duration], p: u32) -> Duration {
+    let rank = (p as usize * sorted.len() + 99) / 100;
+    sorted[rank.max(1) - 1]
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Run the benchmark described by `config` and print the results.
+pub fn run(
+    client: &mut PatatClient,
+    options: &Options,
+    config: &Config,
+) -> patat_client::Result<()> {
+    if let Command::BenchHmac = config.command {
+        let (fresh, reused) = client.bench_hmac(options.attest.hash, config.iterations)?;
+        match config.format {
+            Format::Csv => print_hmac_csv(config.iterations, fresh, reused),
+            Format::Org => print_hmac_org(config.iterations, fresh, reused),
+        }
+        return Ok(());
+    }
+
+    let benchmark = match config.command {
+        Command::RunAttested => Benchmark::Attested(AttestConfig {
+            features: config.features,
+            ..options.attest.clone()
+        }),
+        _ => Benchmark::WithoutAttestation,
+    };
+    let samples = client.benchmark(&benchmark, config.iterations)?;
+
+    let timings: Vec<Duration> = samples.iter().map(|sample| sample.elapsed).collect();
+    let mut phases = vec![Vec::with_capacity(samples.len()); Phase::ALL.len()];
+    for sample in samples {
+        for (phase, timing) in sample.phases {
+            phases[phase as usize].push(timing);
+        }
+    }
+
+    let summary = Summary::new(&timings);
+    // Only `Command::RunAttested` reports its phases
+    let phases: Vec<(Phase, Summary)> = Phase::ALL
+        .iter()
+        .zip(phases.iter())
+        .filter(|(_, timings)| !timings.is_empty())
+        .map(|(phase, timings)| (*phase, Summary::new(timings)))
+        .collect();
+    match config.format {
+        Format::Csv => print_csv(&timings, &summary, &phases),
+        Format::Org => print_org(config.features, &timings, &summary, &phases),
+    }
+    Ok(())
+}
+
+fn print_csv(timings: &[Duration], summary: &Summary, phases: &[(Phase, Summary)]) {
+    println!("run,timing_ms");
+    for (run, timing) in timings.iter().enumerate() {
+        println!("{},{:.3}", run + 1, millis(*timing));
+    }
+    println!();
+    println!("phase,min_ms,median_ms,p95_ms,p99_ms");
+    for (phase, summary) in phases {
+        print_csv_summary(phase.name(), summary);
+    }
+    print_csv_summary("Total", summary);
+}
+
+fn print_csv_summary(name: &str, summary: &Summary) {
+    println!(
+        "{},{:.3},{:.3},{:.3},{:.3}",
+        name,
+        millis(summary.min),
+        millis(summary.median),
+        millis(summary.p95),
+        millis(summary.p99)
+    );
+}
+
+fn print_org(features: u32, timings: &[Duration], summary: &Summary, phases: &[(Phase, Summary)]) {
+    println!("* {} features", features);
+    println!("| *Run* | Timing (ms) |");
+    println!("|-------+-------------|");
+    for (run, timing) in timings.iter().enumerate() {
+        println!("| {} | {:.3} |", run + 1, millis(*timing));
+    }
+    println!();
+    println!("| *Phase* | Min | Median | P95 | P99 |");
+    println!("|---------+-----+--------+-----+-----|");
+    for (phase, summary) in phases {
+        print_org_summary(phase.name(), summary);
+    }
+    print_org_summary("Total", summary);
+}
+
+fn print_org_summary(name: &str, summary: &Summary) {
+    println!(
+        "| {} | {:.3} | {:.3} | {:.3} | {:.3} |",
+        name,
+        millis(summary.min),
+        millis(summary.median),
+        millis(summary.p95),
+        millis(summary.p99)
+    );
+}
+
+/// The rows of the HMAC benchmark: how the TEE operations are allocated,
+/// and the total time.
+fn hmac_rows(fresh: Duration, reused: Duration) -> [(&'static str, Duration); 2] {
+    [("Per HMAC", fresh), ("Per handshake", reused)]
+}
+
+fn print_hmac_csv(handshakes: u32, fresh: Duration, reused: Duration) {
+    println!("operations,total_ms,per_handshake_ms");
+    for (name, total) in hmac_rows(fresh, reused) {
+        println!(
+            "{},{:.3},{:.3}",
+            name,
+            millis(total),
+            millis(total) / handshakes as f64
+        );
+    }
+}
+
+fn print_hmac_org(handshakes: u32, fresh: Duration, reused: Duration) {
+    println!("* {} handshakes", handshakes);
+    println!("| *Operations* | Total (ms) | Per handshake (ms) |");
+    println!("|--------------+------------+--------------------|");
+    for (name, total) in hmac_rows(fresh, reused) {
+        println!(
+            "| {} | {:.3} | {:.3} |",
+            name,
+            millis(total),
+            millis(total) / handshakes as f64
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(values: &[u64]) -> Vec<Duration> {
+        values.iter().map(|v| Duration::from_millis(*v)).collect()
+    }
+
+    #[test]
+    fn summary_of_hundred_runs() {
+        let timings: Vec<u64> = (1..=100).rev().collect();
+        assert_eq!(
+            Summary::new(&ms(&timings)),
+            Summary {
+                min: Duration::from_millis(1),
+                median: Duration::from_micros(50_500),
+                p95: Duration::from_millis(95),
+                p99: Duration::from_millis(99),
+            }
+        );
+    }
+
+    #[test]
+    fn summary_of_single_run() {
+        let timing = Duration::from_millis(172);
+        assert_eq!(
+            Summary::new(&[timing]),
+            Summary {
+                min: timing,
+                median: timing,
+                p95: timing,
+                p99: timing,
+            }
+        );
+    }
+}
@@ -0,0 +1,355 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::bench;
+use crate::daemon;
+use patat_client::{AttestConfig, DhFunction, HashFunction, EVIDENCE_NONCE_LEN};
+use std::fs;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+usage: patat-protocol-rs [options] <command> [command options]
+
+options:
+    --verifier HOST:PORT    verifier to attest to (default 10.0.2.2:65432)
+    --verifier-key FILE     raw public key of the verifier (default: demo key)
+    --hash sha256|sha512|blake2s
+    --dh 25519|ffdhe2048
+    --output human|json     (default human)
+
+commands:
+    attest [--features N] [--resume]
+                            attest once to the verifier; --resume resumes
+                            with a stored ticket and asks for a new one
+    bench [--features N] [--iterations N]
+          [--command attested|unattested|hmac] [--format csv|org]
+    daemon [--features N] [--interval SECS] [--heartbeat SECS] [--resume]
+                            keep attesting: re-attest every interval (default
+                            300 s) or when the verifier asks, and send a
+                            heartbeat in between (default every 30 s)
+    export-key [--out FILE]
+                            export the public key of the TA's identity
+    sign-evidence --nonce HEX [--features N] [--out FILE]
+                            have the TA sign its evidence with its 25519
+                            identity, for patat-verify to check offline
+    install-certificates FILE
+                            install the certificate chain for the identity
+                            of --dh, which the TA sends to the verifier
+    status                  show which DH backend the TA uses
+    selftest                run the TA's built-in self-tests";
+
+/// How results are printed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    Human,
+    Json,
+}
+
+/// Options that apply to every command.
+pub struct Options {
+    /// How to attest; the feature count is set by the command.
+    pub attest: AttestConfig,
+    pub output: Output,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            attest: AttestConfig::default(),
+            output: Output::Human,
+        }
+    }
+}
+
+pub enum Subcommand {
+    Attest {
+        features: u32,
+        resume: bool,
+    },
+    Bench(bench::Config),
+    Daemon(daemon::Config),
+    ExportKey {
+        out: Option<PathBuf>,
+    },
+    SignEvidence {
+        nonce: [u8; EVIDENCE_NONCE_LEN],
+        features: u32,
+        out: Option<PathBuf>,
+    },
+    InstallCertificates {
+        chain: PathBuf,
+    },
+    Status,
+    SelfTest,
+}
+
+/// Parse the command line, without the program name.
+pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<(Options, Subcommand), String> {
+    let mut options = Options::default();
+    let command = loop {
+        let arg = args.next().ok_or("missing command")?;
+        if !arg.starts_with("--") {
+            break arg;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {}", arg))?;
+        match arg.as_str() {
+            "--verifier" => {
+                let (host, port) = parse_address(&value)?;
+                options.attest.verifier.host = host;
+                options.attest.verifier.port = port;
+            }
+            "--verifier-key" => {
+                options.attest.verifier.public_key =
+                    fs::read(&value).map_err(|e| format!("cannot read {}: {}", value, e))?;
+            }
+            "--hash" => {
+                options.attest.hash = match value.as_str() {
+                    "sha256" => HashFunction::Sha256,
+                    "sha512" => HashFunction::Sha512,
+                    "blake2s" => HashFunction::Blake2s,
+                    _ => return Err(format!("unknown hash function {}", value)),
+                }
+            }
+            "--dh" => {
+                options.attest.dh = match value.as_str() {
+                    "25519" => DhFunction::Curve25519,
+                    "ffdhe2048" => DhFunction::Ffdhe2048,
+                    _ => return Err(format!("unknown DH function {}", value)),
+                }
+            }
+            "--output" => {
+                options.output = match value.as_str() {
+                    "human" => Output::Human,
+                    "json" => Output::Json,
+                    _ => return Err(format!("unknown output {}", value)),
+                }
+            }
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    };
+
+    let subcommand = match command.as_str() {
+        "attest" => {
+            let mut features = AttestConfig::default().features;
+            let mut resume = false;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--resume" => resume = true,
+                    "--features" => {
+                        let value = args
+                            .next()
+                            .ok_or_else(|| format!("missing value for {}", arg))?;
+                        features = bench::parse_features(&arg, &value)?;
+                    }
+                    _ => return Err(format!("unknown option {}", arg)),
+                }
+            }
+            Subcommand::Attest { features, resume }
+        }
+        "bench" => Subcommand::Bench(bench::Config::from_args(args)?),
+        "daemon" => Subcommand::Daemon(daemon::Config::from_args(args)?),
+        "export-key" => {
+            let mut out = None;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--out" => {
+                        let value = args
+                            .next()
+                            .ok_or_else(|| format!("missing value for {}", arg))?;
+                        out = Some(PathBuf::from(value));
+                    }
+                    _ => return Err(format!("unknown option {}", arg)),
+                }
+            }
+            Subcommand::ExportKey { out }
+        }
+        "sign-evidence" => {
+            let mut nonce = None;
+            let mut features = AttestConfig::default().features;
+            let mut out = None;
+            while let Some(arg) = args.next() {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("missing value for {}", arg))?;
+                match arg.as_str() {
+                    "--nonce" => nonce = Some(parse_nonce(&value)?),
+                    "--features" => features = bench::parse_features(&arg, &value)?,
+                    "--out" => out = Some(PathBuf::from(value)),
+                    _ => return Err(format!("unknown option {}", arg)),
+                }
+            }
+            Subcommand::SignEvidence {
+                nonce: nonce.ok_or("sign-evidence needs a --nonce")?,
+                features,
+                out,
+            }
+        }
+        "install-certificates" => {
+            let chain = args.next().ok_or("install-certificates needs a FILE")?;
+            no_options(
+                args,
+                Subcommand::InstallCertificates {
+                    chain: PathBuf::from(chain),
+                },
+            )?
+        }
+        "status" => no_options(args, Subcommand::Status)?,
+        "selftest" => no_options(args, Subcommand::SelfTest)?,
+        _ => return Err(format!("unknown command {}", command)),
+    };
+    Ok((options, subcommand))
+}
+
+fn no_options<I: Iterator<Item = String>>(
+    mut args: I,
+    subcommand: Subcommand,
+) -> Result<Subcommand, String> {
+    match args.next() {
+        Some(arg) => Err(format!("unknown option {}", arg)),
+        None => Ok(subcommand),
+    }
+}
+
+/// Parse a nonce of `EVIDENCE_NONCE_LEN` bytes written in hex.
+fn parse_nonce(value: &str) -> Result<[u8; EVIDENCE_NONCE_LEN], String> {
+    let invalid = || {
+        format!(
+            "--nonce expects {} hex digits, got {}",
+            2 * EVIDENCE_NONCE_LEN,
+            value
+        )
+    };
+    if value.len() != 2 * EVIDENCE_NONCE_LEN || !value.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let mut nonce = [0u8; EVIDENCE_NONCE_LEN];
+    for (byte, digits) in nonce.iter_mut().zip(value.as_bytes().chunks(2)) {
+        let digits = std::str::from_utf8(digits).map_err(|_| invalid())?;
+        *byte = u8::from_str_radix(digits, 16).map_err(|_| invalid())?;
+    }
+    Ok(nonce)
+}
+
+fn parse_address(address: &str) -> Result<(String, u16), String> {
+    let mut parts = address.rsplitn(2, ':');
+    let port = parts.next().unwrap_or_default();
+    let host = parts
+        .next()
+        .ok_or_else(|| format!("expected HOST:PORT, got {}", address))?;
+    let port = port
+        .parse()
+        .map_err(|_| format!("invalid port in {}", address))?;
+    Ok((host.to_string(), port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn args(line: &str) -> impl Iterator<Item = String> + '_ {
+        line.split_whitespace().map(String::from)
+    }
+
+    #[test]
+    fn global_options_precede_the_command() {
+        let (options, subcommand) = parse(args(
+            "--verifier 192.168.1.20:4433 --output json attest --features 25",
+        ))
+        .unwrap();
+        assert_eq!(options.attest.verifier.host, "192.168.1.20");
+        assert_eq!(options.attest.verifier.port, 4433);
+        assert!(options.output == Output::Json);
+        assert!(matches!(
+            subcommand,
+            Subcommand::Attest {
+                features: 25,
+                resume: false
+            }
+        ));
+
+        let (_, subcommand) = parse(args("attest --resume --features 3")).unwrap();
+        assert!(matches!(
+            subcommand,
+            Subcommand::Attest {
+                features: 3,
+                resume: true
+            }
+        ));
+    }
+
+    #[test]
+    fn daemon_options() {
+        let (_, subcommand) = parse(args("daemon --interval 60 --resume --heartbeat 5")).unwrap();
+        match subcommand {
+            Subcommand::Daemon(config) => {
+                assert_eq!(config.interval, Duration::from_secs(60));
+                assert_eq!(config.heartbeat, Duration::from_secs(5));
+                assert!(config.resume);
+            }
+            _ => panic!("expected the daemon command"),
+        }
+    }
+
+    #[test]
+    fn sign_evidence_options() {
+        let nonce = "00ff".repeat(EVIDENCE_NONCE_LEN / 2);
+        let line = format!("sign-evidence --features 4 --nonce {} --out e.bin", nonce);
+        match parse(args(&line)).unwrap().1 {
+            Subcommand::SignEvidence {
+                nonce,
+                features,
+                out,
+            } => {
+                assert_eq!(nonce[..2], [0x00, 0xff]);
+                assert_eq!(features, 4);
+                assert_eq!(out, Some(PathBuf::from("e.bin")));
+            }
+            _ => panic!("expected the sign-evidence command"),
+        }
+    }
+
+    #[test]
+    fn install_certificates_takes_a_file() {
+        match parse(args("--dh ffdhe2048 install-certificates chain.cbor")).unwrap() {
+            (options, Subcommand::InstallCertificates { chain }) => {
+                assert!(matches!(options.attest.dh, DhFunction::Ffdhe2048));
+                assert_eq!(chain, PathBuf::from("chain.cbor"));
+            }
+            _ => panic!("expected the install-certificates command"),
+        }
+    }
+
+    #[test]
+    fn invalid_command_lines_are_rejected() {
+        assert!(parse(args("")).is_err());
+        assert!(parse(args("frobnicate")).is_err());
+        assert!(parse(args("--verifier localhost attest")).is_err());
+        assert!(parse(args("status --verbose")).is_err());
+        assert!(parse(args("attest --features many")).is_err());
+        assert!(parse(args("attest --features 0")).is_err());
+        assert!(parse(args("bench --features 0")).is_err());
+        assert!(parse(args("daemon --features 0")).is_err());
+        assert!(parse(args("daemon --heartbeat 0")).is_err());
+        assert!(parse(args("sign-evidence")).is_err());
+        assert!(parse(args("sign-evidence --nonce 00ff")).is_err());
+        assert!(parse(args("install-certificates")).is_err());
+        assert!(parse(args("install-certificates a b")).is_err());
+    }
+}
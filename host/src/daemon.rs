@@ -0,0 +1,447 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Continuous attestation: keep the TA session open, re-attest on a schedule
+//! or when the verifier asks for it, and send heartbeats over the attested
+//! channel in between.
+
+use crate::bench::{parse_features, parse_number};
+use crate::cli::{Options, Output};
+use patat_client::{AttestConfig, ErrorKind, PatatClient, Verdict};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Prefix of every heartbeat, followed by a big-endian 64-bit sequence
+/// number.
+pub const HEARTBEAT: &[u8] = b"patat-heartbeat";
+/// Reply to a heartbeat with which the verifier asks for a re-attestation.
+/// Any other reply acknowledges the heartbeat.
+pub const REATTEST_REQUEST: &[u8] = b"patat-reattest";
+
+/// How often to attest and to send heartbeats.
+#[derive(Clone)]
+pub struct Config {
+    pub features: u32,
+    /// Resume with session tickets instead of running full handshakes.
+    pub resume: bool,
+    /// Time between scheduled re-attestations.
+    pub interval: Duration,
+    /// Time between heartbeats, and between retries while not attested.
+    pub heartbeat: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            features: 15,
+            resume: false,
+            interval: Duration::from_secs(300),
+            heartbeat: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Config {
+    /// Parse `--features N --interval SECS --heartbeat SECS --resume`;
+    /// options that are left out keep their default.
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<Config, String> {
+        let mut config = Config::default();
+        while let Some(arg) = args.next() {
+            if arg == "--resume" {
+                config.resume = true;
+                continue;
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| format!("missing value for {}", arg))?;
+            match arg.as_str() {
+                "--features" => config.features = parse_features(&arg, &value)?,
+                "--interval" => config.interval = parse_seconds(&arg, &value)?,
+                "--heartbeat" => config.heartbeat = parse_seconds(&arg, &value)?,
+                _ => return Err(format!("unknown option {}", arg)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn parse_seconds(option: &str, value: &str) -> Result<Duration, String> {
+    match parse_number(option, value)? {
+        0 => Err(format!("{} must be at least 1", option)),
+        seconds => Ok(Duration::from_secs(seconds as u64)),
+    }
+}
+
+/// Whether the verifier currently holds fresh evidence of the TA.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    /// The last attestation succeeded and heartbeats are acknowledged.
+    Attested,
+    /// The verifier aborted the last attestation.
+    Rejected,
+    /// The TA cannot reach the verifier.
+    Unreachable,
+}
+
+impl State {
+    pub fn name(self) -> &'static str {
+        match self {
+            State::Attested => "attested",
+            State::Rejected => "rejected",
+            State::Unreachable => "unreachable",
+        }
+    }
+}
+
+/// A change of `State`, reported by `Daemon::step`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Change {
+    pub from: Option<State>,
+    pub to: State,
+    /// What the daemon was doing: "start", "scheduled", "requested" or
+    /// "retry" for an attestation, "heartbeat" for a failed heartbeat.
+    pub reason: &'static str,
+}
+
+/// The TA as seen by the daemon.
+pub trait Device {
+    fn attest(&mut self, config: &AttestConfig, resume: bool) -> patat_client::Result<Verdict>;
+    fn send_message(&mut self, message: &[u8]) -> patat_client::Result<Vec<u8>>;
+}
+
+impl Device for PatatClient<'_> {
+    /// Attest, and with `resume` ask for a ticket for the next attestation.
+    fn attest(&mut self, config: &AttestConfig, resume: bool) -> patat_client::Result<Verdict> {
+        let (verdict, _) = crate::attest_or_resume(self, config, resume)?;
+        if resume {
+            if let Verdict::Attested { .. } = verdict {
+                self.request_ticket()?;
+            }
+        }
+        Ok(verdict)
+    }
+
+    fn send_message(&mut self, message: &[u8]) -> patat_client::Result<Vec<u8>> {
+        PatatClient::send_message(self, message)
+    }
+}
+
+/// Errors after which the daemon keeps retrying; any other error of the TA
+/// ends the daemon.
+fn is_unreachable(e: &patat_client::Error) -> bool {
+    matches!(e.kind(), ErrorKind::Communication | ErrorKind::BadState)
+}
+
+fn heartbeat(sequence: u64) -> Vec<u8> {
+    let mut message = HEARTBEAT.to_vec();
+    message.extend_from_slice(&sequence.to_be_bytes());
+    message
+}
+
+/// Schedule of attestations and heartbeats.
+pub struct Daemon {
+    attest: AttestConfig,
+    config: Config,
+    state: Option<State>,
+    /// Why the next attestation happens, for `Change::reason`.
+    due: &'static str,
+    next_attestation: Instant,
+    next_heartbeat: Instant,
+    sequence: u64,
+}
+
+impl Daemon {
+    /// A daemon that attests first thing at `now`.
+    pub fn new(attest: AttestConfig, config: Config, now: Instant) -> Daemon {
+        Daemon {
+            attest,
+            config,
+            state: None,
+            due: "start",
+            next_attestation: now,
+            next_heartbeat: now,
+            sequence: 0,
+        }
+    }
+
+    /// When `step` has something to do next.
+    pub fn next_wakeup(&self) -> Instant {
+        match self.state {
+            Some(State::Attested) => self.next_attestation.min(self.next_heartbeat),
+            _ => self.next_attestation,
+        }
+    }
+
+    /// Attest or send a heartbeat if one is due at `now`.
+    ///
+    /// Returns the change of state, if any. Errors other than losing the
+    /// verifier are returned, as the TA session is unlikely to recover.
+    pub fn step<D: Device>(
+        &mut self,
+        device: &mut D,
+        now: Instant,
+    ) -> patat_client::Result<Option<Change>> {
+        if now >= self.next_attestation {
+            let reason = self.due;
+            let state = match device.attest(&self.attest, self.config.resume) {
+                Ok(Verdict::Attested { .. }) => State::Attested,
+                Ok(Verdict::Rejected) => State::Rejected,
+                Err(e) if is_unreachable(&e) => State::Unreachable,
+                Err(e) => return Err(e),
+            };
+            if state == State::Attested {
+                self.next_attestation = now + self.config.interval;
+                self.due = "scheduled";
+            } else {
+                self.next_attestation = now + self.config.heartbeat;
+                self.due = "retry";
+            }
+            self.next_heartbeat = now + self.config.heartbeat;
+            return Ok(self.enter(state, reason));
+        }
+
+        if self.state == Some(State::Attested) && now >= self.next_heartbeat {
+            self.sequence += 1;
+            self.next_heartbeat = now + self.config.heartbeat;
+            match device.send_message(&heartbeat(self.sequence)) {
+                Ok(reply) if reply == REATTEST_REQUEST => {
+                    self.next_attestation = now;
+                    self.due = "requested";
+                }
+                Ok(_) => {}
+                Err(e) if is_unreachable(&e) => {
+                    self.next_attestation = now + self.config.heartbeat;
+                    self.due = "retry";
+                    return Ok(self.enter(State::Unreachable, "heartbeat"));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+
+    fn enter(&mut self, state: State, reason: &'static str) -> Option<Change> {
+        let from = self.state.replace(state);
+        if from == Some(state) {
+            return None;
+        }
+        Some(Change {
+            from,
+            to: state,
+            reason,
+        })
+    }
+}
+
+fn report(output: Output, started: Instant, change: &Change) {
+    match output {
+        Output::Human => println!(
+            "[{:>9.1} s] {} -> {} ({})",
+            started.elapsed().as_secs_f64(),
+            change.from.map_or("starting", State::name),
+            change.to.name(),
+            change.reason
+        ),
+        Output::Json => {
+            let time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs());
+            let from = change
+                .from
+                .map_or("null".to_string(), |from| format!("\"{}\"", from.name()));
+            println!(
+                "{{\"command\":\"daemon\",\"time\":{},\"from\":{},\"to\":\"{}\",\"reason\":\"{}\"}}",
+                time,
+                from,
+                change.to.name(),
+                change.reason
+            );
+        }
+    }
+}
+
+/// Run the daemon until the TA fails, reporting every change of state.
+pub fn run(
+    client: &mut PatatClient,
+    options: &Options,
+    config: &Config,
+) -> patat_client::Result<()> {
+    let attest = AttestConfig {
+        features: config.features,
+        ..options.attest.clone()
+    };
+    let started = Instant::now();
+    let mut daemon = Daemon::new(attest, config.clone(), started);
+    loop {
+        if let Some(change) = daemon.step(client, Instant::now())? {
+            report(options.output, started, &change);
+        }
+        thread::sleep(
+            daemon
+                .next_wakeup()
+                .saturating_duration_since(Instant::now()),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use patat_client::Error;
+    use std::collections::VecDeque;
+
+    /// Replays canned results and records the messages it was sent.
+    #[derive(Default)]
+    struct FakeDevice {
+        attestations: VecDeque<patat_client::Result<Verdict>>,
+        replies: VecDeque<patat_client::Result<Vec<u8>>>,
+        messages: Vec<Vec<u8>>,
+    }
+
+    impl Device for FakeDevice {
+        fn attest(&mut self, _: &AttestConfig, _: bool) -> patat_client::Result<Verdict> {
+            self.attestations
+                .pop_front()
+                .expect("unexpected attestation")
+        }
+
+        fn send_message(&mut self, message: &[u8]) -> patat_client::Result<Vec<u8>> {
+            self.messages.push(message.to_vec());
+            self.replies.pop_front().expect("unexpected heartbeat")
+        }
+    }
+
+    fn attested() -> patat_client::Result<Verdict> {
+        Ok(Verdict::Attested {
+            elapsed: Duration::from_millis(1),
+            phases: vec![],
+        })
+    }
+
+    fn daemon(start: Instant) -> Daemon {
+        let config = Config {
+            interval: Duration::from_secs(10),
+            heartbeat: Duration::from_secs(3),
+            ..Config::default()
+        };
+        Daemon::new(AttestConfig::default(), config, start)
+    }
+
+    fn secs(start: Instant, seconds: u64) -> Instant {
+        start + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn heartbeats_between_scheduled_attestations() {
+        let start = Instant::now();
+        let mut daemon = daemon(start);
+        let mut device = FakeDevice::default();
+        device.attestations.extend(vec![attested(), attested()]);
+        device
+            .replies
+            .extend(vec![Ok(vec![]), Ok(vec![]), Ok(vec![])]);
+
+        let change = daemon.step(&mut device, start).unwrap();
+        assert_eq!(
+            change,
+            Some(Change {
+                from: None,
+                to: State::Attested,
+                reason: "start",
+            })
+        );
+        let mut now = start;
+        while now < secs(start, 10) {
+            now = daemon.next_wakeup();
+            assert_eq!(daemon.step(&mut device, now).unwrap(), None);
+        }
+
+        assert_eq!(now, secs(start, 10));
+        assert!(device.attestations.is_empty());
+        assert_eq!(
+            device.messages,
+            vec![heartbeat(1), heartbeat(2), heartbeat(3)]
+        );
+        assert_eq!(daemon.next_wakeup(), secs(start, 13));
+    }
+
+    #[test]
+    fn verifier_can_request_a_reattestation() {
+        let start = Instant::now();
+        let mut daemon = daemon(start);
+        let mut device = FakeDevice::default();
+        device
+            .attestations
+            .extend(vec![attested(), Ok(Verdict::Rejected)]);
+        device.replies.push_back(Ok(REATTEST_REQUEST.to_vec()));
+
+        daemon.step(&mut device, start).unwrap();
+        assert_eq!(daemon.step(&mut device, secs(start, 3)).unwrap(), None);
+        assert_eq!(daemon.next_wakeup(), secs(start, 3));
+        let change = daemon.step(&mut device, secs(start, 3)).unwrap();
+        assert_eq!(
+            change,
+            Some(Change {
+                from: Some(State::Attested),
+                to: State::Rejected,
+                reason: "requested",
+            })
+        );
+        assert_eq!(daemon.next_wakeup(), secs(start, 6));
+    }
+
+    #[test]
+    fn lost_verifier_is_retried() {
+        let start = Instant::now();
+        let mut daemon = daemon(start);
+        let mut device = FakeDevice::default();
+        device.attestations.extend(vec![
+            attested(),
+            Err(Error::new(ErrorKind::Communication)),
+            attested(),
+        ]);
+        device
+            .replies
+            .push_back(Err(Error::new(ErrorKind::Communication)));
+
+        daemon.step(&mut device, start).unwrap();
+        let change = daemon.step(&mut device, secs(start, 3)).unwrap();
+        assert_eq!(change.unwrap().reason, "heartbeat");
+        assert_eq!(daemon.next_wakeup(), secs(start, 6));
+        assert_eq!(daemon.step(&mut device, secs(start, 6)).unwrap(), None);
+        let change = daemon.step(&mut device, secs(start, 9)).unwrap();
+        assert_eq!(
+            change,
+            Some(Change {
+                from: Some(State::Unreachable),
+                to: State::Attested,
+                reason: "retry",
+            })
+        );
+    }
+
+    #[test]
+    fn other_errors_end_the_daemon() {
+        let start = Instant::now();
+        let mut daemon = daemon(start);
+        let mut device = FakeDevice::default();
+        device
+            .attestations
+            .push_back(Err(Error::new(ErrorKind::TargetDead)));
+        assert!(daemon.step(&mut device, start).is_err());
+    }
+}
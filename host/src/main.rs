@@ -15,38 +15,316 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use optee_teec::{Context, Operation, ParamTmpRef, ParamType, Session, Uuid};
-use optee_teec::{ParamNone, ParamValue};
-use proto::{Command, UUID};
-use std::default::Default;
-use std::time::Instant;
+mod bench;
+mod cli;
+mod daemon;
 
-/// Run the Proof of Concept TEE.
+use cli::{Options, Output, Subcommand};
+use patat_client::{
+    AttestConfig, Context, DhBackend, ErrorKind, PatatClient, SelfTestReport, Verdict,
+    EVIDENCE_NONCE_LEN, MAX_CERTIFICATE_CHAIN_LEN,
+};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+
+/// Exit code when the TA reports an error or a failed check.
+const EXIT_FAILURE: i32 = 1;
+/// Exit code for an invalid command line.
+const EXIT_USAGE: i32 = 2;
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Resume with the stored ticket if `resume`, falling back to a full
+/// attestation if there is no usable ticket. Returns whether it resumed.
+fn attest_or_resume(
+    client: &mut PatatClient,
+    config: &AttestConfig,
+    resume: bool,
+) -> patat_client::Result<(Verdict, bool)> {
+    if resume {
+        match client.resume(config) {
+            Ok(Verdict::Rejected) => {}
+            Ok(verdict) => return Ok((verdict, true)),
+            Err(e) if e.kind() == ErrorKind::ItemNotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok((client.attest(config)?, false))
+}
+
+/// Attest once; a rejected attestation is reported as a failure.
 ///
-/// In the TEE, attest the application to an attestation server.
-fn run_tee(session: &mut Session) -> optee_teec::Result<()> {
-    let mut operation = Operation::new(0, ParamNone, ParamNone, ParamNone, ParamNone);
-    session.invoke_command(Command::RunWithoutAttestation as u32, &mut operation)?;
-    Ok(())
+/// With `resume` a new ticket is requested afterwards, for the next run.
+fn attest(
+    client: &mut PatatClient,
+    options: &Options,
+    features: u32,
+    resume: bool,
+) -> patat_client::Result<bool> {
+    let config = AttestConfig {
+        features,
+        ..options.attest.clone()
+    };
+    let (verdict, resumed) = attest_or_resume(client, &config, resume)?;
+    let (elapsed, phases) = match verdict {
+        Verdict::Attested { elapsed, phases } => (elapsed, phases),
+        Verdict::Rejected => {
+            match options.output {
+                Output::Human => println!("Attestation rejected"),
+                Output::Json => println!("{{\"command\":\"attest\",\"result\":\"rejected\"}}"),
+            }
+            return Ok(false);
+        }
+    };
+    let ticket = if resume {
+        client.request_ticket()?
+    } else {
+        None
+    };
+
+    match options.output {
+        Output::Human => {
+            println!(
+                "Attestation {} in {:.3} ms",
+                if resumed { "resumed" } else { "succeeded" },
+                millis(elapsed)
+            );
+            for (phase, timing) in phases {
+                println!("  {:<16} {} ms", phase.name(), timing.as_millis());
+            }
+            if let Some(lifetime) = ticket {
+                println!("Ticket valid for {} s", lifetime.as_secs());
+            }
+        }
+        Output::Json => {
+            let phases: Vec<String> = phases
+                .iter()
+                .map(|(phase, timing)| {
+                    format!("{}:{}", json_string(phase.name()), timing.as_millis())
+                })
+                .collect();
+            let ticket = match ticket {
+                Some(lifetime) => lifetime.as_secs().to_string(),
+                None => "null".to_string(),
+            };
+            println!(
+                "{{\"command\":\"attest\",\"result\":\"ok\",\"resumed\":{},\"elapsed_ms\":{:.3},\"phases_ms\":{{{}}},\"ticket_lifetime_s\":{}}}",
+                resumed,
+                millis(elapsed),
+                phases.join(","),
+                ticket
+            );
+        }
+    }
+    Ok(true)
+}
+
+/// Write `bytes` to `out`, reporting an error on stderr; returns whether
+/// that worked.
+fn write_output(out: &Path, bytes: &[u8]) -> bool {
+    match fs::write(out, bytes) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("cannot write {}: {}", out.display(), e);
+            false
+        }
+    }
+}
+
+fn export_key(
+    client: &mut PatatClient,
+    options: &Options,
+    out: Option<&Path>,
+) -> patat_client::Result<bool> {
+    let key = client.export_identity(options.attest.dh)?;
+    if let Some(out) = out {
+        if !write_output(out, &key) {
+            return Ok(false);
+        }
+    }
+    match options.output {
+        Output::Human => match out {
+            Some(out) => println!(
+                "Wrote the {}-byte public key to {}",
+                key.len(),
+                out.display()
+            ),
+            None => println!("{}", hex(&key)),
+        },
+        Output::Json => println!(
+            "{{\"command\":\"export-key\",\"result\":\"ok\",\"public_key\":\"{}\"}}",
+            hex(&key)
+        ),
+    }
+    Ok(true)
+}
+
+/// Have the TA sign its evidence, and write the bundle to `out` or print it.
+fn sign_evidence(
+    client: &mut PatatClient,
+    options: &Options,
+    nonce: &[u8; EVIDENCE_NONCE_LEN],
+    features: u32,
+    out: Option<&Path>,
+) -> patat_client::Result<bool> {
+    let bundle = client.sign_evidence(options.attest.hash, features, nonce)?;
+    if let Some(out) = out {
+        if !write_output(out, &bundle) {
+            return Ok(false);
+        }
+    }
+    match options.output {
+        Output::Human => match out {
+            Some(out) => println!(
+                "Wrote {} bytes of signed evidence to {}",
+                bundle.len(),
+                out.display()
+            ),
+            None => println!("{}", hex(&bundle)),
+        },
+        Output::Json => println!(
+            "{{\"command\":\"sign-evidence\",\"result\":\"ok\",\"evidence\":\"{}\"}}",
+            hex(&bundle)
+        ),
+    }
+    Ok(true)
+}
+
+/// Install the certificate chain in `chain` for the identity of the
+/// selected DH function.
+fn install_certificates(
+    client: &mut PatatClient,
+    options: &Options,
+    chain: &Path,
+) -> patat_client::Result<bool> {
+    let bytes = match fs::read(chain) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("cannot read {}: {}", chain.display(), e);
+            return Ok(false);
+        }
+    };
+    if bytes.len() > MAX_CERTIFICATE_CHAIN_LEN {
+        eprintln!(
+            "{} is larger than {} bytes",
+            chain.display(),
+            MAX_CERTIFICATE_CHAIN_LEN
+        );
+        return Ok(false);
+    }
+    client.install_certificates(options.attest.dh, &bytes)?;
+    match options.output {
+        Output::Human => println!(
+            "Installed {} bytes of certificates from {}",
+            bytes.len(),
+            chain.display()
+        ),
+        Output::Json => println!(
+            "{{\"command\":\"install-certificates\",\"result\":\"ok\",\"bytes\":{}}}",
+            bytes.len()
+        ),
+    }
+    Ok(true)
 }
 
-/// The main run method
-fn run() -> optee_teec::Result<()> {
-    let mut ctx = Context::new()?;
-    let uuid = Uuid::parse_str(UUID).unwrap();
-    let mut session = ctx.open_session(uuid)?;
+fn status(client: &mut PatatClient, options: &Options) -> patat_client::Result<bool> {
+    let backend = match client.dh_backend()? {
+        DhBackend::Dalek => "dalek",
+        DhBackend::OpteeNative => "optee-native",
+        DhBackend::Unknown => "unknown",
+    };
+    match options.output {
+        Output::Human => println!("DH backend: {}", backend),
+        Output::Json => println!(
+            "{{\"command\":\"status\",\"result\":\"ok\",\"dh_backend\":\"{}\"}}",
+            backend
+        ),
+    }
+    Ok(true)
+}
 
-    for _ in 0..1000 {
-        let now = Instant::now();
-        run_tee(&mut session)?;
-        let elapsed = now.elapsed();
-        println!("Elapsed: {} ms", elapsed.as_millis());
+/// Run the self-tests; any failed check is reported as a failure.
+fn selftest(client: &mut PatatClient, options: &Options) -> patat_client::Result<bool> {
+    let SelfTestReport { passed, failed } = client.selftest()?;
+    match options.output {
+        Output::Human => println!("Self-tests: {} passed, {} failed", passed, failed),
+        Output::Json => println!(
+            "{{\"command\":\"selftest\",\"result\":\"{}\",\"passed\":{},\"failed\":{}}}",
+            if failed == 0 { "ok" } else { "failed" },
+            passed,
+            failed
+        ),
     }
+    Ok(failed == 0)
+}
 
-    println!("Done");
-    Ok(())
+/// Run `subcommand` and return whether it succeeded.
+fn run(options: &Options, subcommand: &Subcommand) -> patat_client::Result<bool> {
+    let mut context = Context::new()?;
+    let mut client = PatatClient::open(&mut context)?;
+
+    match subcommand {
+        Subcommand::Attest { features, resume } => attest(&mut client, options, *features, *resume),
+        Subcommand::Bench(config) => bench::run(&mut client, options, config).map(|_| true),
+        Subcommand::Daemon(config) => daemon::run(&mut client, options, config).map(|_| true),
+        Subcommand::ExportKey { out } => export_key(&mut client, options, out.as_deref()),
+        Subcommand::SignEvidence {
+            nonce,
+            features,
+            out,
+        } => sign_evidence(&mut client, options, nonce, *features, out.as_deref()),
+        Subcommand::InstallCertificates { chain } => {
+            install_certificates(&mut client, options, chain)
+        }
+        Subcommand::Status => status(&mut client, options),
+        Subcommand::SelfTest => selftest(&mut client, options),
+    }
 }
 
-fn main() -> optee_teec::Result<()> {
-    run()
+fn main() {
+    let (options, subcommand) = match cli::parse(env::args().skip(1)) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            process::exit(EXIT_USAGE);
+        }
+    };
+
+    match run(&options, &subcommand) {
+        Ok(true) => {}
+        Ok(false) => process::exit(EXIT_FAILURE),
+        Err(e) => {
+            match options.output {
+                Output::Human => eprintln!("TA error: {}", e),
+                Output::Json => println!(
+                    "{{\"result\":\"error\",\"error\":{}}}",
+                    json_string(&e.to_string())
+                ),
+            }
+            process::exit(EXIT_FAILURE);
+        }
+    }
 }
@@ -15,9 +15,98 @@
 // specific language governing permissions and limitations
 // under the License.
 
+#[derive(Clone, Copy)]
 pub enum Command {
+    /// Attest to the verifier.
+    ///
+    /// The first parameter selects the `HashFunction` and `DhFunction`, value
+    /// `a` of the second parameter is the number of features to measure. The
+    /// third parameter is an output buffer of `TIMINGS_LEN` bytes and the
+    /// fourth an input buffer holding the encoded `Verifier`.
+    ///
+    /// The TA authenticates with its static identity for the `DhFunction`,
+    /// the key that `Command::ExportKey` returns.
+    ///
+    /// The attested channel stays open for the rest of the session, replacing
+    /// any channel opened before.
     RunAttested,
     RunWithoutAttestation,
+    GetDhBackend,
+    /// Return the public key of the TA's static identity.
+    ///
+    /// Value `a` of the first parameter selects the `DhFunction`, the key is
+    /// written to the output buffer in the second parameter.
+    ExportKey,
+    /// Run the TA's built-in self-tests.
+    ///
+    /// The number of passed and failed checks are returned in values `a` and
+    /// `b` of the first parameter.
+    SelfTest,
+    /// Send another round of evidence over the attested channel.
+    ///
+    /// Value `a` of the first parameter is the number of features to
+    /// measure. Fails with `TEE_ERROR_BAD_STATE` if the session has no
+    /// attested channel.
+    SendEvidence,
+    /// Send an application message over the attested channel and wait for
+    /// the verifier's reply.
+    ///
+    /// The first parameter is an input buffer with the message, the reply is
+    /// written to the output buffer in the second parameter.
+    SendMessage,
+    /// Close the attested channel of the session.
+    CloseChannel,
+    /// Ask the verifier for a resumption ticket over the attested channel
+    /// and keep it in secure storage for `Command::RunResumed`.
+    ///
+    /// The lifetime of the ticket in seconds is returned in value `a` of the
+    /// first parameter, 0 if the verifier did not issue one.
+    RequestTicket,
+    /// Like `Command::RunAttested`, with the same parameters, but with a
+    /// resumed handshake based on the ticket stored for the verifier.
+    ///
+    /// Tickets are used once. Fails with `TEE_ERROR_ITEM_NOT_FOUND` if there
+    /// is no ticket or it expired, with `TEE_ERROR_COMMUNICATION` if the
+    /// verifier cannot be reached and with `TEE_ERROR_SECURITY` if it
+    /// rejected the ticket. Message 3 and the confirmation are skipped, so
+    /// their timings are 0.
+    RunResumed,
+    /// Append a round of measurements to the TA's evidence log and report
+    /// its new root over the attested channel, with a proof that it extends
+    /// the previously reported root.
+    ///
+    /// Value `a` of the first parameter is the number of features to
+    /// measure; the size of the log is returned in value `b`. There is one
+    /// log per `HashFunction`, kept in secure storage across sessions. Fails
+    /// with `TEE_ERROR_BAD_STATE` if the session has no attested channel.
+    AppendEvidence,
+    /// Time a number of XK handshakes inside the TA, from message 1 up to the
+    /// split, once with new TEE operations for every hash and HMAC and once
+    /// with the operations allocated once per handshake.
+    ///
+    /// Value `a` of the first parameter selects the `HashFunction`, value `b`
+    /// is the number of handshakes. The milliseconds the two runs took are
+    /// returned in values `a` and `b` of the second parameter.
+    BenchHmac,
+    /// Measure the evidence and return it signed with the TA's X25519
+    /// identity key, using XEdDSA, so that it can be checked offline against
+    /// the key from `ExportKey`.
+    ///
+    /// Value `a` of the first parameter selects the `HashFunction`, value `b`
+    /// is the number of features to measure. The second parameter is an
+    /// input buffer with a nonce of `EVIDENCE_NONCE_LEN` bytes, and the
+    /// signed evidence is written to the output buffer in the third, which
+    /// should hold `MAX_SIGNED_EVIDENCE_LEN` bytes.
+    SignEvidence,
+    /// Store the certificate chain for the TA's static identity, which the
+    /// TA sends to the verifier in handshake message 3.
+    ///
+    /// Value `a` of the first parameter selects the `DhFunction`, the second
+    /// parameter is an input buffer with the encoded chain of at most
+    /// `MAX_CERTIFICATE_CHAIN_LEN` bytes. The TA rejects a chain that does
+    /// not parse or whose first certificate is not for its static key; the
+    /// signatures are left to the verifier.
+    InstallCertificates,
     Unknown,
 }
 
@@ -27,11 +116,197 @@ impl From<u32> for Command {
         match value {
             0 => Command::RunAttested,
             1 => Command::RunWithoutAttestation,
+            2 => Command::GetDhBackend,
+            3 => Command::ExportKey,
+            4 => Command::SelfTest,
+            5 => Command::SendEvidence,
+            6 => Command::SendMessage,
+            7 => Command::CloseChannel,
+            8 => Command::RequestTicket,
+            9 => Command::RunResumed,
+            10 => Command::AppendEvidence,
+            11 => Command::BenchHmac,
+            12 => Command::SignEvidence,
+            13 => Command::InstallCertificates,
             _ => Command::Unknown,
         }
     }
 }
 
+/// Hash function used for the Noise handshake and the evidence tree.
+///
+/// Passed as value `a` of the first parameter of `Command::RunAttested`.
+#[derive(Clone, Copy)]
+pub enum HashFunction {
+    Sha256,
+    Sha512,
+    Blake2s,
+    Unknown,
+}
+
+impl From<u32> for HashFunction {
+    #[inline]
+    fn from(value: u32) -> HashFunction {
+        match value {
+            0 => HashFunction::Sha256,
+            1 => HashFunction::Sha512,
+            2 => HashFunction::Blake2s,
+            _ => HashFunction::Unknown,
+        }
+    }
+}
+
+/// DH function used for the Noise handshake.
+///
+/// Passed as value `b` of the first parameter of `Command::RunAttested`.
+#[derive(Clone, Copy)]
+pub enum DhFunction {
+    Curve25519,
+    Ffdhe2048,
+    Unknown,
+}
+
+impl From<u32> for DhFunction {
+    #[inline]
+    fn from(value: u32) -> DhFunction {
+        match value {
+            0 => DhFunction::Curve25519,
+            1 => DhFunction::Ffdhe2048,
+            _ => DhFunction::Unknown,
+        }
+    }
+}
+
+/// Implementation of the DH function used by the TA.
+///
+/// Returned in value `a` of the first parameter of `Command::GetDhBackend`.
+pub enum DhBackend {
+    Dalek,
+    OpteeNative,
+    Unknown,
+}
+
+impl From<u32> for DhBackend {
+    #[inline]
+    fn from(value: u32) -> DhBackend {
+        match value {
+            0 => DhBackend::Dalek,
+            1 => DhBackend::OpteeNative,
+            _ => DhBackend::Unknown,
+        }
+    }
+}
+
+/// The verifier the TA attests to.
+///
+/// An empty `public_key` makes the TA fall back to the key of the demo
+/// verifier.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Verifier {
+    pub host: String,
+    pub port: u16,
+    pub public_key: Vec<u8>,
+}
+
+impl Default for Verifier {
+    fn default() -> Verifier {
+        // The address of the Host in QEMU
+        Verifier {
+            host: "10.0.2.2".to_string(),
+            port: 65432,
+            public_key: vec![],
+        }
+    }
+}
+
+impl Verifier {
+    /// Encode as the port and the length of the public key as big-endian
+    /// `u16`s, followed by the public key and the host.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.extend_from_slice(&self.port.to_be_bytes());
+        bytes.extend_from_slice(&(self.public_key.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.public_key);
+        bytes.extend_from_slice(self.host.as_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Verifier> {
+        if bytes.len() < 4 {
+            return None;
+        }
+        let port = u16::from_be_bytes([bytes[0], bytes[1]]);
+        let key_len = u16::from_be_bytes([bytes[2], bytes[3]]) as usize;
+        let public_key = bytes.get(4..4 + key_len)?.to_vec();
+        let host = String::from_utf8(bytes[4 + key_len..].to_vec()).ok()?;
+        Some(Verifier {
+            host,
+            port,
+            public_key,
+        })
+    }
+}
+
+/// Phase of `Command::RunAttested` that the TA times.
+///
+/// The TA returns how long each phase took in milliseconds, as little-endian
+/// `u32`s in the order of `Phase::ALL`.
+#[derive(Clone, Copy, Debug)]
+pub enum Phase {
+    KeyGeneration,
+    Connect,
+    Message1,
+    Message2,
+    Message3,
+    Confirmation,
+    EvidenceTree,
+    EvidenceSend,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 8] = [
+        Phase::KeyGeneration,
+        Phase::Connect,
+        Phase::Message1,
+        Phase::Message2,
+        Phase::Message3,
+        Phase::Confirmation,
+        Phase::EvidenceTree,
+        Phase::EvidenceSend,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::KeyGeneration => "Key generation",
+            Phase::Connect => "Connect",
+            Phase::Message1 => "Message 1",
+            Phase::Message2 => "Message 2",
+            Phase::Message3 => "Message 3",
+            Phase::Confirmation => "Confirmation",
+            Phase::EvidenceTree => "Evidence tree",
+            Phase::EvidenceSend => "Evidence send",
+        }
+    }
+}
+
+/// Size in bytes of the timings returned by `Command::RunAttested`.
+pub const TIMINGS_LEN: usize = 4 * Phase::ALL.len();
+
+/// Largest message or reply of `Command::SendMessage`: frames on the wire
+/// have a `u16` length and every transport message carries a 16-byte tag.
+pub const MAX_MESSAGE_LEN: usize = 65535 - 16;
+
+/// Length of the nonce `Command::SignEvidence` signs with the evidence.
+pub const EVIDENCE_NONCE_LEN: usize = 32;
+
+/// Largest evidence bundle `Command::SignEvidence` returns: the proof has a
+/// hash for every level of a tree of at most `5 * u32::MAX` leaves.
+pub const MAX_SIGNED_EVIDENCE_LEN: usize = 4096;
+
+/// Largest certificate chain `Command::InstallCertificates` accepts, so that
+/// it fits in handshake message 3 together with an FFDHE2048 static key.
+pub const MAX_CERTIFICATE_CHAIN_LEN: usize = 4096;
+
 /// [IETF RFC 3526](https://www.rfc-editor.org/rfc/rfc3526)
 pub const PRIME: [u8; 256] = [
     0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xC9, 0x0F, 0xDA, 0xA2, 0x21, 0x68, 0xC2, 0x34,
@@ -52,9 +327,40 @@ pub const PRIME: [u8; 256] = [
     0x15, 0x72, 0x8E, 0x5A, 0x8A, 0xAC, 0xAA, 0x68, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
 ];
 pub const BASE: [u8; 1] = [0x02];
-pub const HASHLEN: usize = 32;
+/// Length of an X25519 public key and DH output.
 pub const DHLEN: usize = 32;
 
-/// Key Size in bits
+/// Key Size in bits of the RFC 3526 group
 pub const KEY_SIZE: usize = 2048;
-pub const UUID: &str = &include_str!(concat!(env!("OUT_DIR"), "/uuid.txt"));
+pub const UUID: &str = include_str!(concat!(env!("OUT_DIR"), "/uuid.txt"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifier_round_trip() {
+        let verifier = Verifier {
+            host: "192.168.1.20".to_string(),
+            port: 4433,
+            public_key: vec![0xab; 32],
+        };
+        assert_eq!(Verifier::from_bytes(&verifier.to_bytes()), Some(verifier));
+        assert_eq!(
+            Verifier::from_bytes(&Verifier::default().to_bytes()),
+            Some(Verifier::default())
+        );
+    }
+
+    #[test]
+    fn truncated_verifier_is_rejected() {
+        let bytes = Verifier {
+            host: String::new(),
+            port: 1,
+            public_key: vec![1, 2, 3],
+        }
+        .to_bytes();
+        assert_eq!(Verifier::from_bytes(&bytes[..5]), None);
+        assert_eq!(Verifier::from_bytes(&bytes[..3]), None);
+    }
+}
@@ -23,6 +23,11 @@ use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
 fn main() -> std::io::Result<()> {
+    // The simulation runs as a normal process, without libutee.
+    if env::var_os("CARGO_FEATURE_SIM").is_some() {
+        return Ok(());
+    }
+
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
 
     let mut buffer = File::create(out.join("user_ta_header.rs"))?;
@@ -0,0 +1,155 @@
+//! Check evidence that the TA signed with `sign-evidence`, without the TA or
+//! the verifier:
+//!
+//! ```shell
+//! patat-verify BUNDLE KEY --nonce HEX [--max-age SECS]
+//! ```
+//!
+//! `KEY` is the raw X25519 identity key from `export-key --dh 25519`. Exits
+//! with 0 if the evidence is valid, 1 if it is not and 2 for an invalid
+//! command line.
+//!
+//! The bundle is fresh only if `--nonce` is one the verifier chose for this
+//! request. The timestamp is the REE time, which the normal world sets, so
+//! `--max-age` merely rejects bundles that claim to be old.
+
+use std::convert::TryInto;
+use std::env;
+use std::fs;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ta::hasher::{Blake2s, NoiseHash, Sha256, Sha512};
+use ta::signed_evidence::{hash_name, SignedEvidence, NONCE_LEN};
+use ta::x25519::PublicKey;
+
+const USAGE: &str = "usage: patat-verify BUNDLE KEY --nonce HEX [--max-age SECS]
+
+The nonce makes the bundle fresh. The timestamp that --max-age checks is the
+REE time and is not authenticated.";
+
+/// Exit code for evidence that does not check out.
+const EXIT_INVALID: i32 = 1;
+/// Exit code for an invalid command line.
+const EXIT_USAGE: i32 = 2;
+
+/// What the bundle must match besides its signature and proof.
+struct Expected {
+    nonce: [u8; NONCE_LEN],
+    /// Largest age of the bundle in seconds, by its unauthenticated
+    /// timestamp.
+    max_age: Option<u64>,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn parse_nonce(value: &str) -> Option<[u8; NONCE_LEN]> {
+    if value.len() != 2 * NONCE_LEN || !value.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut nonce = [0u8; NONCE_LEN];
+    for (byte, digits) in nonce.iter_mut().zip(value.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+    Some(nonce)
+}
+
+/// Check `bundle` with `key`, returning a description of the evidence.
+fn check<H: NoiseHash>(
+    bundle: &[u8],
+    key: &PublicKey,
+    expected: &Expected,
+    now: u64,
+) -> Result<String, String> {
+    let signed = SignedEvidence::<H>::from_bytes(bundle).ok_or("malformed bundle")?;
+    if signed.nonce() != &expected.nonce {
+        return Err("the nonce does not match".to_string());
+    }
+    let timestamp = signed.timestamp();
+    if let Some(max_age) = expected.max_age {
+        let age = now.saturating_sub(timestamp);
+        if age > max_age {
+            return Err(format!("signed {} seconds ago", age));
+        }
+    }
+    let measurement = hex(signed.proof().measurement().unwrap_or_default());
+    if !signed.verify::<Sha512>(key) {
+        return Err("the signature or the proof is invalid".to_string());
+    }
+    Ok(format!(
+        "{} evidence signed at {}, first measurement {}",
+        H::NAME,
+        timestamp,
+        measurement
+    ))
+}
+
+fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<(String, String, Expected), String> {
+    let bundle = args.next().ok_or("missing BUNDLE")?;
+    let key = args.next().ok_or("missing KEY")?;
+    let mut nonce = None;
+    let mut max_age = None;
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {}", arg))?;
+        match arg.as_str() {
+            "--nonce" => {
+                let value = parse_nonce(&value)
+                    .ok_or_else(|| format!("--nonce expects {} hex digits", 2 * NONCE_LEN))?;
+                nonce = Some(value);
+            }
+            "--max-age" => {
+                let seconds = value
+                    .parse()
+                    .map_err(|_| format!("--max-age expects a number, got {}", value))?;
+                max_age = Some(seconds);
+            }
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    let expected = Expected {
+        nonce: nonce.ok_or("missing --nonce")?,
+        max_age,
+    };
+    Ok((bundle, key, expected))
+}
+
+fn read(path: &str) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|e| {
+        eprintln!("cannot read {}: {}", path, e);
+        process::exit(EXIT_USAGE);
+    })
+}
+
+fn main() {
+    let (bundle, key, expected) = parse(env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n{}", e, USAGE);
+        process::exit(EXIT_USAGE);
+    });
+    let bundle = read(&bundle);
+    let key: [u8; 32] = read(&key)[..].try_into().unwrap_or_else(|_| {
+        eprintln!("KEY is not an X25519 public key");
+        process::exit(EXIT_USAGE);
+    });
+    let key = PublicKey::from(key);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs());
+
+    let result = match hash_name(&bundle) {
+        Some(Sha256::NAME) => check::<Sha256>(&bundle, &key, &expected, now),
+        Some(Sha512::NAME) => check::<Sha512>(&bundle, &key, &expected, now),
+        Some(Blake2s::NAME) => check::<Blake2s>(&bundle, &key, &expected, now),
+        _ => Err("unknown hash function".to_string()),
+    };
+    match result {
+        Ok(description) => println!("Valid: {}", description),
+        Err(reason) => {
+            println!("Invalid: {}", reason);
+            process::exit(EXIT_INVALID);
+        }
+    }
+}
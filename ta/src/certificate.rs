@@ -0,0 +1,435 @@
+//! Device certificates that bind the static key of the TA to a manufacturer
+//! CA, so that a verifier can trust a TA it has not enrolled.
+//!
+//! A certificate is a CBOR ([RFC 8949](https://www.rfc-editor.org/rfc/rfc8949))
+//! array:
+//!
+//! ```text
+//! [key_type: tstr, public_key: bstr, subject: tstr,
+//!  not_before: uint, not_after: uint, signature: bstr]
+//! ```
+//!
+//! `key_type` is `CA_KEY_TYPE` for the Ed25519 key of a CA and the name of
+//! the DH function, as in `Dh::name`, for the static key of a TA. The times
+//! are in seconds since the Unix epoch. The issuer signs the array of the
+//! first five fields with `CONTEXT`, see `SigningKey::sign_with_context`.
+//!
+//! A chain is a CBOR array of certificates, starting with the certificate
+//! of the TA. Each certificate is issued by the key in the next one, and the
+//! last one by a trust anchor of the verifier, which is not in the chain.
+//! The TA sends its chain as the payload of handshake message 3, after its
+//! static key, so the verifier can check both together.
+
+use std::convert::TryInto;
+
+use crate::ed25519::{Sha512Hash, SigningKey, VerifyingKey, SIGNATURE_LEN};
+
+/// Key type of the certificate of a CA, which may issue certificates.
+pub const CA_KEY_TYPE: &str = "Ed25519";
+
+/// Largest number of certificates in a chain.
+pub const MAX_CHAIN_LEN: usize = 4;
+
+/// Context of the signed certificates.
+const CONTEXT: &[u8] = b"patat-certificate";
+
+const MAJOR_UINT: u8 = 0;
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+
+/// Why a certificate chain was rejected. Certificates are counted from the
+/// certificate of the TA, which is 0.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CertificateError {
+    /// The chain is not valid CBOR of the expected shape.
+    Malformed,
+    /// The chain is empty or has more than `MAX_CHAIN_LEN` certificates.
+    Length,
+    /// The first certificate is not for the static key of the handshake.
+    WrongKey,
+    /// The certificate is not valid at the time of the check.
+    Expired(usize),
+    /// The certificate is issued by a key that is not a CA.
+    NotCa(usize),
+    /// The signature on the certificate is invalid.
+    BadSignature(usize),
+    /// The last certificate is not signed by any trust anchor.
+    UntrustedRoot,
+}
+
+fn write_head(out: &mut Vec<u8>, major: u8, value: u64) {
+    let major = major << 5;
+    if value < 24 {
+        out.push(major | value as u8);
+    } else if value <= u8::MAX as u64 {
+        out.push(major | 24);
+        out.push(value as u8);
+    } else if value <= u16::MAX as u64 {
+        out.push(major | 25);
+        out.extend_from_slice(&(value as u16).to_be_bytes());
+    } else if value <= u32::MAX as u64 {
+        out.push(major | 26);
+        out.extend_from_slice(&(value as u32).to_be_bytes());
+    } else {
+        out.push(major | 27);
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+fn write_bytes(out: &mut Vec<u8>, major: u8, bytes: &[u8]) {
+    write_head(out, major, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// Reads the subset of CBOR that certificates use. Only the shortest
+/// encoding of each head is accepted, so every certificate has exactly one
+/// encoding.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], CertificateError> {
+        if self.bytes.len() < len {
+            return Err(CertificateError::Malformed);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn head(&mut self, major: u8) -> Result<u64, CertificateError> {
+        let initial = self.take(1)?[0];
+        if initial >> 5 != major {
+            return Err(CertificateError::Malformed);
+        }
+        let (value, smallest) = match initial & 0x1f {
+            info @ 0..=23 => return Ok(info as u64),
+            24 => (self.take(1)?[0] as u64, 24),
+            25 => (
+                u16::from_be_bytes(self.take(2)?.try_into().unwrap()) as u64,
+                0x100,
+            ),
+            26 => (
+                u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as u64,
+                0x10000,
+            ),
+            27 => (
+                u64::from_be_bytes(self.take(8)?.try_into().unwrap()),
+                0x1_0000_0000,
+            ),
+            _ => return Err(CertificateError::Malformed),
+        };
+        if value < smallest {
+            return Err(CertificateError::Malformed);
+        }
+        Ok(value)
+    }
+
+    fn bytes(&mut self, major: u8) -> Result<&'a [u8], CertificateError> {
+        let len = self.head(major)?;
+        self.take(len.try_into().map_err(|_| CertificateError::Malformed)?)
+    }
+
+    fn text(&mut self) -> Result<&'a str, CertificateError> {
+        std::str::from_utf8(self.bytes(MAJOR_TEXT)?).map_err(|_| CertificateError::Malformed)
+    }
+}
+
+/// A certificate for one key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Certificate {
+    key_type: String,
+    public_key: Vec<u8>,
+    subject: String,
+    not_before: u64,
+    not_after: u64,
+    signature: [u8; SIGNATURE_LEN],
+}
+
+impl Certificate {
+    /// Issue a certificate for `public_key` with the CA key `issuer`, valid
+    /// from `not_before` up to and including `not_after`.
+    pub fn issue<D: Sha512Hash>(
+        key_type: &str,
+        public_key: &[u8],
+        subject: &str,
+        not_before: u64,
+        not_after: u64,
+        issuer: &SigningKey<D>,
+    ) -> Self {
+        let mut certificate = Certificate {
+            key_type: key_type.to_string(),
+            public_key: public_key.to_vec(),
+            subject: subject.to_string(),
+            not_before,
+            not_after,
+            signature: [0; SIGNATURE_LEN],
+        };
+        certificate.signature = issuer.sign_with_context(CONTEXT, &certificate.signed_message());
+        certificate
+    }
+
+    pub fn key_type(&self) -> &str {
+        &self.key_type
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    /// Whether the certificate is valid at `now`, in seconds.
+    pub fn valid_at(&self, now: u64) -> bool {
+        self.not_before <= now && now <= self.not_after
+    }
+
+    fn write_fields(&self, out: &mut Vec<u8>) {
+        write_bytes(out, MAJOR_TEXT, self.key_type.as_bytes());
+        write_bytes(out, MAJOR_BYTES, &self.public_key);
+        write_bytes(out, MAJOR_TEXT, self.subject.as_bytes());
+        write_head(out, MAJOR_UINT, self.not_before);
+        write_head(out, MAJOR_UINT, self.not_after);
+    }
+
+    fn signed_message(&self) -> Vec<u8> {
+        let mut message = vec![];
+        write_head(&mut message, MAJOR_ARRAY, 5);
+        self.write_fields(&mut message);
+        message
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        write_head(out, MAJOR_ARRAY, 6);
+        self.write_fields(out);
+        write_bytes(out, MAJOR_BYTES, &self.signature);
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, CertificateError> {
+        if reader.head(MAJOR_ARRAY)? != 6 {
+            return Err(CertificateError::Malformed);
+        }
+        Ok(Certificate {
+            key_type: reader.text()?.to_string(),
+            public_key: reader.bytes(MAJOR_BYTES)?.to_vec(),
+            subject: reader.text()?.to_string(),
+            not_before: reader.head(MAJOR_UINT)?,
+            not_after: reader.head(MAJOR_UINT)?,
+            signature: reader
+                .bytes(MAJOR_BYTES)?
+                .try_into()
+                .map_err(|_| CertificateError::Malformed)?,
+        })
+    }
+
+    /// Whether `issuer` signed this certificate.
+    fn signed_by<D: Sha512Hash>(&self, issuer: &VerifyingKey<D>) -> bool {
+        issuer.verify_with_context(CONTEXT, &self.signed_message(), &self.signature)
+    }
+}
+
+/// The certificate of the TA followed by the CA certificates that lead to a
+/// trust anchor.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CertificateChain {
+    certificates: Vec<Certificate>,
+}
+
+impl CertificateChain {
+    pub fn new(certificates: Vec<Certificate>) -> Self {
+        CertificateChain { certificates }
+    }
+
+    pub fn certificates(&self) -> &[Certificate] {
+        &self.certificates
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CertificateError> {
+        let mut reader = Reader { bytes };
+        let len = reader.head(MAJOR_ARRAY)?;
+        if len == 0 || len > MAX_CHAIN_LEN as u64 {
+            return Err(CertificateError::Length);
+        }
+        let certificates = (0..len)
+            .map(|_| Certificate::read(&mut reader))
+            .collect::<Result<_, _>>()?;
+        if !reader.bytes.is_empty() {
+            return Err(CertificateError::Malformed);
+        }
+        Ok(CertificateChain { certificates })
+    }
+
+    /// Check the chain for the static key `static_key` of DH function
+    /// `dh_name` at time `now`, against the CA keys in `anchors`.
+    ///
+    /// Returns the subject of the TA's certificate.
+    pub fn validate<D: Sha512Hash>(
+        &self,
+        anchors: &[VerifyingKey<D>],
+        dh_name: &str,
+        static_key: &[u8],
+        now: u64,
+    ) -> Result<&str, CertificateError> {
+        let device = match self.certificates.first() {
+            Some(device) if self.certificates.len() <= MAX_CHAIN_LEN => device,
+            _ => return Err(CertificateError::Length),
+        };
+        if device.key_type != dh_name || device.public_key != static_key {
+            return Err(CertificateError::WrongKey);
+        }
+
+        for (i, certificate) in self.certificates.iter().enumerate() {
+            if !certificate.valid_at(now) {
+                return Err(CertificateError::Expired(i));
+            }
+            let issuer = match self.certificates.get(i + 1) {
+                Some(issuer) => issuer,
+                None => break,
+            };
+            let issuer_key = match VerifyingKey::<D>::from_bytes(&issuer.public_key) {
+                Some(key) if issuer.key_type == CA_KEY_TYPE => key,
+                _ => return Err(CertificateError::NotCa(i)),
+            };
+            if !certificate.signed_by(&issuer_key) {
+                return Err(CertificateError::BadSignature(i));
+            }
+        }
+
+        let root = self.certificates.last().unwrap();
+        if anchors.iter().any(|anchor| root.signed_by(anchor)) {
+            Ok(&device.subject)
+        } else {
+            Err(CertificateError::UntrustedRoot)
+        }
+    }
+}
+
+impl From<CertificateChain> for Vec<u8> {
+    fn from(value: CertificateChain) -> Self {
+        let mut bytes = vec![];
+        write_head(&mut bytes, MAJOR_ARRAY, value.certificates.len() as u64);
+        for certificate in &value.certificates {
+            certificate.write(&mut bytes);
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ed25519::SEED_LEN;
+    use crate::test_support::SoftSha512;
+
+    const NOW: u64 = 1_700_000_000;
+    const YEAR: u64 = 365 * 24 * 3600;
+    const DEVICE_KEY: [u8; 32] = [9; 32];
+
+    fn key(seed: u8) -> SigningKey<SoftSha512> {
+        SigningKey::from_seed(&[seed; SEED_LEN])
+    }
+
+    /// A chain from the device key through an intermediate CA to the root
+    /// with seed 1, as the TA would send it.
+    fn chain() -> Vec<u8> {
+        let (root, intermediate) = (key(1), key(2));
+        let device = Certificate::issue(
+            "25519",
+            &DEVICE_KEY,
+            "device 42",
+            NOW - YEAR,
+            NOW + YEAR,
+            &intermediate,
+        );
+        let ca = Certificate::issue(
+            CA_KEY_TYPE,
+            intermediate.public(),
+            "factory",
+            NOW - 2 * YEAR,
+            NOW + 2 * YEAR,
+            &root,
+        );
+        CertificateChain::new(vec![device, ca]).into()
+    }
+
+    fn validate(chain: &[u8], anchor: u8, now: u64) -> Result<String, CertificateError> {
+        let anchors = [VerifyingKey::from(&key(anchor))];
+        let chain = CertificateChain::from_bytes(chain)?;
+        chain
+            .validate(&anchors, "25519", &DEVICE_KEY, now)
+            .map(str::to_string)
+    }
+
+    #[test]
+    fn chains_validate_against_trust_anchors() {
+        let chain = chain();
+        assert_eq!(validate(&chain, 1, NOW), Ok("device 42".to_string()));
+        assert_eq!(
+            validate(&chain, 3, NOW),
+            Err(CertificateError::UntrustedRoot)
+        );
+        assert_eq!(
+            validate(&chain, 1, NOW + YEAR + 1),
+            Err(CertificateError::Expired(0))
+        );
+
+        let parsed = CertificateChain::from_bytes(&chain).unwrap();
+        let anchors = [VerifyingKey::<SoftSha512>::from(&key(1))];
+        assert_eq!(
+            parsed.validate(&anchors, "25519", &[8; 32], NOW),
+            Err(CertificateError::WrongKey)
+        );
+        assert_eq!(
+            parsed.validate(&anchors, "FFDHE2048", &DEVICE_KEY, NOW),
+            Err(CertificateError::WrongKey)
+        );
+        assert_eq!(Vec::<u8>::from(parsed), chain);
+    }
+
+    #[test]
+    fn only_cas_issue_certificates() {
+        // A device certificate signed by the key of another device.
+        let device = key(4);
+        let issued = Certificate::issue("25519", &DEVICE_KEY, "fake", 0, u64::MAX, &device);
+        let other = Certificate::issue("25519", device.public(), "device", 0, u64::MAX, &key(1));
+        let chain: Vec<u8> = CertificateChain::new(vec![issued, other]).into();
+        assert_eq!(validate(&chain, 1, NOW), Err(CertificateError::NotCa(0)));
+    }
+
+    #[test]
+    fn tampered_chains_are_rejected() {
+        let chain = chain();
+        for i in 1..chain.len() {
+            let mut tampered = chain.clone();
+            tampered[i] ^= 1;
+            assert!(validate(&tampered, 1, NOW).is_err(), "byte {}", i);
+        }
+        assert_eq!(
+            validate(&chain[..chain.len() - 1], 1, NOW),
+            Err(CertificateError::Malformed)
+        );
+        let mut longer = chain.clone();
+        longer.push(0);
+        assert_eq!(validate(&longer, 1, NOW), Err(CertificateError::Malformed));
+        assert_eq!(validate(&[0x80], 1, NOW), Err(CertificateError::Length));
+    }
+
+    #[test]
+    fn heads_use_the_shortest_encoding() {
+        let mut bytes = vec![];
+        for value in [0, 23, 24, 255, 256, 65535, 65536, u32::MAX as u64 + 1] {
+            bytes.clear();
+            write_head(&mut bytes, MAJOR_UINT, value);
+            assert_eq!(Reader { bytes: &bytes }.head(MAJOR_UINT), Ok(value));
+        }
+        // 23 in two bytes.
+        assert_eq!(
+            Reader { bytes: &[0x18, 23] }.head(MAJOR_UINT),
+            Err(CertificateError::Malformed)
+        );
+    }
+}
@@ -0,0 +1,244 @@
+// OP-TEE
+#[cfg(not(feature = "sim"))]
+mod optee;
+#[cfg(not(feature = "sim"))]
+pub use self::optee::{Ffdhe2048, X25519Native};
+
+// Protocol
+use proto::{DhBackend, DHLEN, PRIME};
+
+// std
+use std::convert::TryInto;
+
+// libraries
+use rand_core::CryptoRngCore;
+use zeroize::{Zeroize, Zeroizing};
+
+// TA Code
+use crate::x25519::{PublicKey, StaticSecret};
+
+/// A key pair for a [`Dh`] function, both halves in their wire encoding.
+#[derive(Clone)]
+pub struct Keypair {
+    secret: Vec<u8>,
+    public: Vec<u8>,
+}
+
+impl Keypair {
+    pub fn new(secret: Vec<u8>, public: Vec<u8>) -> Self {
+        Keypair { secret, public }
+    }
+
+    pub fn public(&self) -> &[u8] {
+        &self.public
+    }
+
+    pub(crate) fn secret(&self) -> &[u8] {
+        &self.secret
+    }
+}
+
+impl Drop for Keypair {
+    fn drop(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DhError {
+    /// The peer's public key is invalid or of low order, so the DH output
+    /// does not depend on our private key.
+    NonContributory,
+}
+
+/// A DH function as described in [section 4.1 of the Noise
+/// specification](https://noiseprotocol.org/noise.html#dh-functions).
+pub trait Dh {
+    /// Name of the DH function as used in the Noise protocol name.
+    fn name(&self) -> &'static str;
+
+    /// The implementation that computes this DH function.
+    fn backend(&self) -> DhBackend;
+
+    /// Number of bytes in a public key and in a DH output.
+    fn dh_len(&self) -> usize;
+
+    /// Generate a fresh key pair from `rng`.
+    ///
+    /// Implementations backed by the TEE use its internal RNG instead.
+    fn generate_keypair(&self, rng: &mut dyn CryptoRngCore) -> Keypair;
+
+    fn keypair_from_secret(&self, secret: &[u8]) -> Keypair;
+
+    /// Perform a DH between `keypair` and `public_key`, rejecting results
+    /// that the peer could have forced to a known value.
+    fn dh(&self, keypair: &Keypair, public_key: &[u8]) -> Result<Zeroizing<Vec<u8>>, DhError>;
+}
+
+/// Pick the fastest available X25519 implementation.
+///
+/// OP-TEE 4.0 and later implement `TEE_ALG_X25519`; older versions fall back
+/// to the vendored x25519-dalek code.
+#[cfg(not(feature = "sim"))]
+pub fn x25519() -> Box<dyn Dh> {
+    match X25519Native::probe() {
+        Some(native) => Box::new(native),
+        None => Box::new(X25519Dalek),
+    }
+}
+
+/// Outside of OP-TEE only the vendored x25519-dalek code is available.
+#[cfg(feature = "sim")]
+pub fn x25519() -> Box<dyn Dh> {
+    Box::new(X25519Dalek)
+}
+
+pub struct X25519Dalek;
+
+impl Dh for X25519Dalek {
+    fn name(&self) -> &'static str {
+        "25519"
+    }
+
+    fn backend(&self) -> DhBackend {
+        DhBackend::Dalek
+    }
+
+    fn dh_len(&self) -> usize {
+        DHLEN
+    }
+
+    fn generate_keypair(&self, rng: &mut dyn CryptoRngCore) -> Keypair {
+        let secret = StaticSecret::new(rng);
+        let public = PublicKey::from(&secret);
+        let secret_bytes = Zeroizing::new(secret.to_bytes());
+        Keypair::new(secret_bytes.to_vec(), public.to_bytes().to_vec())
+    }
+
+    fn keypair_from_secret(&self, secret: &[u8]) -> Keypair {
+        let secret_bytes: Zeroizing<[u8; DHLEN]> = Zeroizing::new(secret.try_into().unwrap());
+        let secret = StaticSecret::from(*secret_bytes);
+        let public = PublicKey::from(&secret);
+        let secret_bytes = Zeroizing::new(secret.to_bytes());
+        Keypair::new(secret_bytes.to_vec(), public.to_bytes().to_vec())
+    }
+
+    fn dh(&self, keypair: &Keypair, public_key: &[u8]) -> Result<Zeroizing<Vec<u8>>, DhError> {
+        let secret_bytes: Zeroizing<[u8; DHLEN]> =
+            Zeroizing::new(keypair.secret().try_into().unwrap());
+        let public_bytes: [u8; DHLEN] = public_key.try_into().unwrap();
+        let secret = StaticSecret::from(*secret_bytes);
+        let shared_secret = secret.diffie_hellman(&PublicKey::from(public_bytes));
+        if !shared_secret.was_contributory() {
+            return Err(DhError::NonContributory);
+        }
+        Ok(Zeroizing::new(shared_secret.as_bytes().to_vec()))
+    }
+}
+
+/// Only `1 < y < p - 1` are valid FFDHE2048 public values; 0, 1 and `p - 1`
+/// would force the shared secret to 0 or ±1.
+///
+/// Plain byte comparisons, so that it is tested outside OP-TEE too.
+#[cfg_attr(feature = "sim", allow(dead_code))]
+pub(crate) fn ffdhe_valid_public(public_key: &[u8]) -> bool {
+    if public_key.len() != PRIME.len() {
+        return false;
+    }
+    let mut p_minus_one = PRIME;
+    p_minus_one[PRIME.len() - 1] -= 1;
+    let mut one = [0u8; PRIME.len()];
+    one[PRIME.len() - 1] = 1;
+    public_key > &one[..] && public_key < &p_minus_one[..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Curve25519 points of small order, plus their non-canonical encodings
+    /// (see [RFC 7748, section 7](https://www.rfc-editor.org/rfc/rfc7748#section-7)).
+    const LOW_ORDER_POINTS: [[u8; 32]; 7] = [
+        // 0 (order 4)
+        [
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+        ],
+        // 1 (order 1)
+        [
+            0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+        ],
+        // order 8
+        [
+            0xe0, 0xeb, 0x7a, 0x7c, 0x3b, 0x41, 0xb8, 0xae, 0x16, 0x56, 0xe3, 0xfa, 0xf1, 0x9f,
+            0xc4, 0x6a, 0xda, 0x09, 0x8d, 0xeb, 0x9c, 0x32, 0xb1, 0xfd, 0x86, 0x62, 0x05, 0x16,
+            0x5f, 0x49, 0xb8, 0x00,
+        ],
+        // order 8
+        [
+            0x5f, 0x9c, 0x95, 0xbc, 0xa3, 0x50, 0x8c, 0x24, 0xb1, 0xd0, 0xb1, 0x55, 0x9c, 0x83,
+            0xef, 0x5b, 0x04, 0x44, 0x5c, 0xc4, 0x58, 0x1c, 0x8e, 0x86, 0xd8, 0x22, 0x4e, 0xdd,
+            0xd0, 0x9f, 0x11, 0x57,
+        ],
+        // p - 1 (order 2)
+        [
+            0xec, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0xff, 0xff, 0xff, 0x7f,
+        ],
+        // p, i.e. 0 again
+        [
+            0xed, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0xff, 0xff, 0xff, 0x7f,
+        ],
+        // p + 1, i.e. 1 again
+        [
+            0xee, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0xff, 0xff, 0xff, 0x7f,
+        ],
+    ];
+
+    #[test]
+    fn x25519_rejects_low_order_points() {
+        let keypair = X25519Dalek.keypair_from_secret(&[0x42; DHLEN]);
+        for point in LOW_ORDER_POINTS.iter() {
+            assert_eq!(
+                X25519Dalek.dh(&keypair, point).err(),
+                Some(DhError::NonContributory)
+            );
+        }
+    }
+
+    #[test]
+    fn x25519_accepts_valid_points() {
+        let alice = X25519Dalek.keypair_from_secret(&[0x42; DHLEN]);
+        let bob = X25519Dalek.keypair_from_secret(&[0x24; DHLEN]);
+        let alice_shared = X25519Dalek.dh(&alice, bob.public()).unwrap();
+        let bob_shared = X25519Dalek.dh(&bob, alice.public()).unwrap();
+        assert_eq!(alice_shared, bob_shared);
+    }
+
+    #[test]
+    fn ffdhe_rejects_degenerate_public_values() {
+        let zero = [0u8; PRIME.len()];
+        let mut one = zero;
+        one[PRIME.len() - 1] = 1;
+        let mut p_minus_one = PRIME;
+        p_minus_one[PRIME.len() - 1] -= 1;
+        let mut two = zero;
+        two[PRIME.len() - 1] = 2;
+
+        assert!(!ffdhe_valid_public(&zero));
+        assert!(!ffdhe_valid_public(&one));
+        assert!(!ffdhe_valid_public(&p_minus_one));
+        assert!(!ffdhe_valid_public(&PRIME));
+        assert!(!ffdhe_valid_public(&two[1..]));
+        assert!(!ffdhe_valid_public(&[0xff; PRIME.len()]));
+        assert!(ffdhe_valid_public(&two));
+    }
+}
@@ -0,0 +1,259 @@
+// OP-TEE
+use optee_utee::{
+    AlgorithmId, AttributeId, AttributeMemref, DeriveKey, Error, TransientObject,
+    TransientObjectType,
+};
+use optee_utee_sys::{
+    TEE_AllocateOperation, TEE_AllocateTransientObject, TEE_Attribute, TEE_DeriveKey,
+    TEE_FreeOperation, TEE_FreeTransientObject, TEE_GenerateKey, TEE_GetObjectBufferAttribute,
+    TEE_InitRefAttribute, TEE_ObjectHandle, TEE_OperationHandle, TEE_PopulateTransientObject,
+    TEE_Result, TEE_SetOperationKey,
+};
+use proto::{DhBackend, BASE, DHLEN, KEY_SIZE, PRIME};
+
+// std
+use std::ptr;
+
+// libraries
+use rand_core::CryptoRngCore;
+use zeroize::Zeroizing;
+
+// TA Code
+use super::{ffdhe_valid_public, Dh, DhError, Keypair, X25519Dalek};
+
+// GlobalPlatform identifiers for X25519, which the optee_utee wrappers do not
+// expose (yet).
+const TEE_SUCCESS: TEE_Result = 0;
+const TEE_ALG_X25519: u32 = 0x8000_0044;
+const TEE_MODE_DERIVE: u32 = 6;
+const TEE_TYPE_X25519_KEYPAIR: u32 = 0xA100_0044;
+const TEE_TYPE_GENERIC_SECRET: u32 = 0xA000_0000;
+const TEE_ATTR_SECRET_VALUE: u32 = 0xC000_0000;
+const TEE_ATTR_X25519_PUBLIC_VALUE: u32 = 0xD000_0944;
+const TEE_ATTR_X25519_PRIVATE_VALUE: u32 = 0xC000_0A44;
+const X25519_KEY_SIZE: u32 = 256;
+
+pub struct X25519Native;
+
+impl X25519Native {
+    /// Returns `None` if the TEE does not implement `TEE_ALG_X25519`.
+    pub fn probe() -> Option<Self> {
+        Operation::allocate(TEE_ALG_X25519, TEE_MODE_DERIVE, X25519_KEY_SIZE)
+            .ok()
+            .map(|_| X25519Native)
+    }
+}
+
+impl Dh for X25519Native {
+    fn name(&self) -> &'static str {
+        "25519"
+    }
+
+    fn backend(&self) -> DhBackend {
+        DhBackend::OpteeNative
+    }
+
+    fn dh_len(&self) -> usize {
+        DHLEN
+    }
+
+    fn generate_keypair(&self, _rng: &mut dyn CryptoRngCore) -> Keypair {
+        let key = Object::allocate(TEE_TYPE_X25519_KEYPAIR, X25519_KEY_SIZE);
+        check(unsafe { TEE_GenerateKey(key.0, X25519_KEY_SIZE, ptr::null(), 0) });
+        Keypair::new(
+            key.buffer_attribute(TEE_ATTR_X25519_PRIVATE_VALUE, DHLEN),
+            key.buffer_attribute(TEE_ATTR_X25519_PUBLIC_VALUE, DHLEN),
+        )
+    }
+
+    /// OP-TEE cannot import a private key without its public key, so the
+    /// public key is computed in software. This is only done for long-term keys.
+    fn keypair_from_secret(&self, secret: &[u8]) -> Keypair {
+        X25519Dalek.keypair_from_secret(secret)
+    }
+
+    fn dh(&self, keypair: &Keypair, public_key: &[u8]) -> Result<Zeroizing<Vec<u8>>, DhError> {
+        let mut key = Object::allocate(TEE_TYPE_X25519_KEYPAIR, X25519_KEY_SIZE);
+        key.populate(&[
+            ref_attribute(TEE_ATTR_X25519_PRIVATE_VALUE, keypair.secret()),
+            ref_attribute(TEE_ATTR_X25519_PUBLIC_VALUE, keypair.public()),
+        ]);
+
+        let operation =
+            Operation::allocate(TEE_ALG_X25519, TEE_MODE_DERIVE, X25519_KEY_SIZE).unwrap();
+        check(unsafe { TEE_SetOperationKey(operation.0, key.0) });
+
+        let shared_secret = Object::allocate(TEE_TYPE_GENERIC_SECRET, X25519_KEY_SIZE);
+        let params = [ref_attribute(TEE_ATTR_X25519_PUBLIC_VALUE, public_key)];
+        unsafe { TEE_DeriveKey(operation.0, params.as_ptr(), params.len() as u32, shared_secret.0) };
+        let shared_secret =
+            Zeroizing::new(shared_secret.buffer_attribute(TEE_ATTR_SECRET_VALUE, DHLEN));
+
+        // A low-order public key results in the all-zero output, the same
+        // check as `SharedSecret::was_contributory`.
+        if shared_secret.iter().fold(0u8, |acc, byte| acc | byte) == 0 {
+            return Err(DhError::NonContributory);
+        }
+        Ok(shared_secret)
+    }
+}
+
+/// Finite-field Diffie-Hellman over the 2048-bit MODP group of
+/// [IETF RFC 3526](https://www.rfc-editor.org/rfc/rfc3526), for deployments
+/// that may not use Curve25519.
+///
+/// Public keys and DH outputs are big-endian and left-padded to `dh_len()`
+/// bytes.
+pub struct Ffdhe2048;
+
+impl Ffdhe2048 {
+    fn key_object(keypair: &Keypair) -> TransientObject {
+        let mut key = TransientObject::allocate(TransientObjectType::DhKeypair, KEY_SIZE).unwrap();
+        key.populate(&[
+            AttributeMemref::from_ref(AttributeId::DhPrime, &PRIME).into(),
+            AttributeMemref::from_ref(AttributeId::DhBase, &BASE).into(),
+            AttributeMemref::from_ref(AttributeId::DhPublicValue, keypair.public()).into(),
+            AttributeMemref::from_ref(AttributeId::DhPrivateValue, keypair.secret()).into(),
+        ])
+        .unwrap();
+        key
+    }
+
+    fn read_attribute(key: &TransientObject, id: AttributeId) -> Vec<u8> {
+        let mut buffer = vec![0u8; KEY_SIZE / 8];
+        let length = key.ref_attribute(id, &mut buffer).unwrap();
+        buffer.truncate(length);
+        buffer
+    }
+
+    fn derive(keypair: &Keypair, public_key: &[u8]) -> Zeroizing<Vec<u8>> {
+        let key = Self::key_object(keypair);
+        let operation = DeriveKey::allocate(AlgorithmId::DhDeriveSharedSecret, KEY_SIZE).unwrap();
+        operation.set_key(&key).unwrap();
+
+        let mut shared_secret =
+            TransientObject::allocate(TransientObjectType::GenericSecret, KEY_SIZE).unwrap();
+        operation.derive(
+            &[AttributeMemref::from_ref(AttributeId::DhPublicValue, public_key).into()],
+            &mut shared_secret,
+        );
+        let secret = Zeroizing::new(Self::read_attribute(&shared_secret, AttributeId::SecretValue));
+        Zeroizing::new(Self::left_pad(&secret))
+    }
+
+    fn left_pad(value: &[u8]) -> Vec<u8> {
+        let mut padded = vec![0u8; KEY_SIZE / 8];
+        padded[KEY_SIZE / 8 - value.len()..].copy_from_slice(value);
+        padded
+    }
+}
+
+impl Dh for Ffdhe2048 {
+    fn name(&self) -> &'static str {
+        "FFDHE2048"
+    }
+
+    fn backend(&self) -> DhBackend {
+        DhBackend::OpteeNative
+    }
+
+    fn dh_len(&self) -> usize {
+        KEY_SIZE / 8
+    }
+
+    fn generate_keypair(&self, _rng: &mut dyn CryptoRngCore) -> Keypair {
+        let key = TransientObject::allocate(TransientObjectType::DhKeypair, KEY_SIZE).unwrap();
+        key.generate_key(
+            KEY_SIZE,
+            &[
+                AttributeMemref::from_ref(AttributeId::DhPrime, &PRIME).into(),
+                AttributeMemref::from_ref(AttributeId::DhBase, &BASE).into(),
+            ],
+        )
+        .unwrap();
+        Keypair::new(
+            Self::read_attribute(&key, AttributeId::DhPrivateValue),
+            Self::left_pad(&Self::read_attribute(&key, AttributeId::DhPublicValue)),
+        )
+    }
+
+    /// OP-TEE has no way to compute a public key from an imported private
+    /// key, but deriving a shared secret with the generator as the peer's
+    /// public value gives exactly `g^x mod p`. The public value is not used
+    /// by the derivation, so the generator stands in for it as well.
+    fn keypair_from_secret(&self, secret: &[u8]) -> Keypair {
+        let placeholder = Keypair::new(secret.to_vec(), BASE.to_vec());
+        let public = Self::derive(&placeholder, &BASE);
+        Keypair::new(secret.to_vec(), public.to_vec())
+    }
+
+    fn dh(&self, keypair: &Keypair, public_key: &[u8]) -> Result<Zeroizing<Vec<u8>>, DhError> {
+        if !ffdhe_valid_public(public_key) {
+            return Err(DhError::NonContributory);
+        }
+        Ok(Self::derive(keypair, public_key))
+    }
+}
+
+fn check(result: TEE_Result) {
+    if result != TEE_SUCCESS {
+        panic!("{}", Error::from_raw_error(result));
+    }
+}
+
+/// The returned attribute borrows `buffer`, so it must not outlive it.
+fn ref_attribute(id: u32, buffer: &[u8]) -> TEE_Attribute {
+    let mut attribute: TEE_Attribute = unsafe { std::mem::zeroed() };
+    unsafe { TEE_InitRefAttribute(&mut attribute, id, buffer.as_ptr() as *const _, buffer.len()) };
+    attribute
+}
+
+struct Operation(TEE_OperationHandle);
+
+impl Operation {
+    fn allocate(algorithm: u32, mode: u32, max_key_size: u32) -> Result<Self, Error> {
+        let mut handle: TEE_OperationHandle = ptr::null_mut();
+        match unsafe { TEE_AllocateOperation(&mut handle, algorithm, mode, max_key_size) } {
+            TEE_SUCCESS => Ok(Operation(handle)),
+            code => Err(Error::from_raw_error(code)),
+        }
+    }
+}
+
+impl Drop for Operation {
+    fn drop(&mut self) {
+        unsafe { TEE_FreeOperation(self.0) };
+    }
+}
+
+struct Object(TEE_ObjectHandle);
+
+impl Object {
+    fn allocate(object_type: u32, max_object_size: u32) -> Self {
+        let mut handle: TEE_ObjectHandle = ptr::null_mut();
+        check(unsafe { TEE_AllocateTransientObject(object_type, max_object_size, &mut handle) });
+        Object(handle)
+    }
+
+    fn populate(&mut self, attributes: &[TEE_Attribute]) {
+        check(unsafe {
+            TEE_PopulateTransientObject(self.0, attributes.as_ptr(), attributes.len() as u32)
+        });
+    }
+
+    fn buffer_attribute(&self, id: u32, length: usize) -> Vec<u8> {
+        let mut buffer = vec![0u8; length];
+        let mut size = buffer.len();
+        check(unsafe {
+            TEE_GetObjectBufferAttribute(self.0, id, buffer.as_mut_ptr() as *mut _, &mut size)
+        });
+        buffer.truncate(size);
+        buffer
+    }
+}
+
+impl Drop for Object {
+    fn drop(&mut self) {
+        unsafe { TEE_FreeTransientObject(self.0) };
+    }
+}
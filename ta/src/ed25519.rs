@@ -0,0 +1,237 @@
+//! Ed25519 signatures as specified in [IETF RFC
+//! 8032](https://www.rfc-editor.org/rfc/rfc8032), on top of the same
+//! `curve25519_dalek` arithmetic as `crate::x25519`.
+//!
+//! SHA-512 is taken as a `NoiseHash`, so that the TA uses the one in OP-TEE.
+//! Only hash functions marked with `Sha512Hash` can be used.
+
+use std::convert::TryInto;
+use std::marker::PhantomData;
+
+use curve25519_dalek::constants::ED25519_BASEPOINT_TABLE;
+use curve25519_dalek::edwards::{CompressedEdwardsY, EdwardsPoint};
+use curve25519_dalek::scalar::Scalar;
+use rand_core::{CryptoRng, RngCore};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::hasher::{NoiseHash, Sha512};
+
+/// Length of the secret seed a signing key is derived from.
+pub const SEED_LEN: usize = 32;
+pub const PUBLIC_KEY_LEN: usize = 32;
+pub const SIGNATURE_LEN: usize = 64;
+
+/// A `NoiseHash` that computes SHA-512, the hash function Ed25519 and XEdDSA
+/// are defined with.
+pub trait Sha512Hash: NoiseHash {}
+
+impl Sha512Hash for Sha512 {}
+
+pub(crate) fn sha512<D: Sha512Hash>(parts: &[&[u8]]) -> Zeroizing<[u8; 64]> {
+    let mut hasher = D::default();
+    for part in parts {
+        hasher.input(part);
+    }
+    let mut out = Zeroizing::new([0u8; 64]);
+    hasher.result(&mut *out);
+    out
+}
+
+/// An Ed25519 key pair, derived from a 32-byte seed.
+pub struct SigningKey<D: Sha512Hash = Sha512> {
+    seed: Zeroizing<[u8; SEED_LEN]>,
+    scalar: Scalar,
+    prefix: Zeroizing<[u8; 32]>,
+    public: [u8; PUBLIC_KEY_LEN],
+    hash: PhantomData<D>,
+}
+
+impl<D: Sha512Hash> SigningKey<D> {
+    pub fn from_seed(seed: &[u8; SEED_LEN]) -> Self {
+        let h = sha512::<D>(&[seed]);
+        let mut bits: [u8; 32] = h[..32].try_into().unwrap();
+        bits[0] &= 248;
+        bits[31] &= 127;
+        bits[31] |= 64;
+        let scalar = Scalar::from_bits(bits);
+        bits.zeroize();
+        let public = (&ED25519_BASEPOINT_TABLE * &scalar).compress().to_bytes();
+
+        SigningKey {
+            seed: Zeroizing::new(*seed),
+            scalar,
+            prefix: Zeroizing::new(h[32..].try_into().unwrap()),
+            public,
+            hash: PhantomData,
+        }
+    }
+
+    pub fn generate<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        let mut seed = Zeroizing::new([0u8; SEED_LEN]);
+        rng.fill_bytes(&mut *seed);
+        Self::from_seed(&seed)
+    }
+
+    /// The seed, which is all there is to store of the key.
+    pub fn seed(&self) -> &[u8; SEED_LEN] {
+        &self.seed
+    }
+
+    pub fn public(&self) -> &[u8; PUBLIC_KEY_LEN] {
+        &self.public
+    }
+
+    pub fn sign(&self, message: &[u8]) -> [u8; SIGNATURE_LEN] {
+        let r = Scalar::from_bytes_mod_order_wide(&sha512::<D>(&[&*self.prefix, message]));
+        let big_r = (&ED25519_BASEPOINT_TABLE * &r).compress();
+        let k = Scalar::from_bytes_mod_order_wide(&sha512::<D>(&[
+            big_r.as_bytes(),
+            &self.public,
+            message,
+        ]));
+        let s = r + k * self.scalar;
+
+        let mut signature = [0u8; SIGNATURE_LEN];
+        signature[..32].copy_from_slice(big_r.as_bytes());
+        signature[32..].copy_from_slice(s.as_bytes());
+        signature
+    }
+
+    /// Sign `message` prefixed with `context`.
+    ///
+    /// Each kind of message signed with a key has its own context, so that a
+    /// signature over one kind cannot be passed off as another, e.g. signed
+    /// evidence as a certificate. No context may be a prefix of another.
+    pub fn sign_with_context(&self, context: &[u8], message: &[u8]) -> [u8; SIGNATURE_LEN] {
+        self.sign(&[context, message].concat())
+    }
+}
+
+impl<D: Sha512Hash> Drop for SigningKey<D> {
+    fn drop(&mut self) {
+        self.scalar.zeroize();
+    }
+}
+
+/// An Ed25519 public key, to check signatures with.
+pub struct VerifyingKey<D: Sha512Hash = Sha512> {
+    public: [u8; PUBLIC_KEY_LEN],
+    hash: PhantomData<D>,
+}
+
+impl<D: Sha512Hash> VerifyingKey<D> {
+    /// `None` if `public` does not have the length of a public key.
+    pub fn from_bytes(public: &[u8]) -> Option<Self> {
+        Some(VerifyingKey {
+            public: public.try_into().ok()?,
+            hash: PhantomData,
+        })
+    }
+
+    /// Check `signature` on `message`, rejecting points that do not decode
+    /// and scalars that are not reduced.
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        if signature.len() != SIGNATURE_LEN {
+            return false;
+        }
+        let a: EdwardsPoint = match CompressedEdwardsY(self.public).decompress() {
+            Some(a) => a,
+            None => return false,
+        };
+        let s = match Scalar::from_canonical_bytes(signature[32..].try_into().unwrap()) {
+            Some(s) => s,
+            None => return false,
+        };
+        let k = Scalar::from_bytes_mod_order_wide(&sha512::<D>(&[
+            &signature[..32],
+            &self.public,
+            message,
+        ]));
+        // [s]B - [k]A must equal R.
+        let big_r = EdwardsPoint::vartime_double_scalar_mul_basepoint(&-k, &a, &s);
+        big_r.compress().as_bytes()[..] == signature[..32]
+    }
+
+    /// Check a signature made with `SigningKey::sign_with_context`.
+    pub fn verify_with_context(&self, context: &[u8], message: &[u8], signature: &[u8]) -> bool {
+        self.verify(&[context, message].concat(), signature)
+    }
+}
+
+impl<D: Sha512Hash> From<&SigningKey<D>> for VerifyingKey<D> {
+    fn from(key: &SigningKey<D>) -> Self {
+        VerifyingKey {
+            public: key.public,
+            hash: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{hex, tampered_signatures, SoftSha512};
+    use rand_chacha::ChaCha20Rng;
+    use rand_core::SeedableRng;
+
+    /// Tests 1 to 3 of section 7.1 of RFC 8032.
+    #[test]
+    fn rfc8032_test_vectors() {
+        let vectors = [
+            (
+                "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+                "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+                "",
+                "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+            ),
+            (
+                "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+                "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+                "72",
+                "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+            ),
+            (
+                "c5aa8df43f9f837bedb7442f31dcb7b166d38535076f094b85ce3a2e0b4458f7",
+                "fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025",
+                "af82",
+                "6291d657deec24024827e69c3abe01a30ce548a284743a445e3680d7db5ac3ac18ff9b538d16f290ae67f760984dc6594a7c15e9716ed28dc027beceea1ec40a",
+            ),
+        ];
+        for (seed, public, message, signature) in vectors.iter() {
+            let key = SigningKey::<SoftSha512>::from_seed(&hex(seed).try_into().unwrap());
+            assert_eq!(key.public()[..], hex(public)[..]);
+            assert_eq!(key.sign(&hex(message))[..], hex(signature)[..]);
+
+            let verifying = VerifyingKey::<SoftSha512>::from_bytes(&hex(public)).unwrap();
+            assert!(verifying.verify(&hex(message), &hex(signature)));
+        }
+    }
+
+    #[test]
+    fn tampered_signatures_are_rejected() {
+        let key = SigningKey::<SoftSha512>::generate(&mut ChaCha20Rng::from_seed([7; 32]));
+        let verifying = VerifyingKey::from(&key);
+        let signature = key.sign(b"evidence");
+        assert!(verifying.verify(b"evidence", &signature));
+        assert!(!verifying.verify(b"evidencf", &signature));
+        assert!(!verifying.verify(b"evidence", &signature[..63]));
+
+        for tampered in tampered_signatures(signature) {
+            assert!(!verifying.verify(b"evidence", &tampered));
+        }
+
+        // s + l encodes the same scalar, but is not reduced.
+        let mut malleated = signature;
+        let l = hex("edd3f55c1a631258d69cf7a2def9de1400000000000000000000000000000010");
+        let mut carry = 0u16;
+        for (byte, l) in malleated[32..].iter_mut().zip(l.iter()) {
+            let sum = *byte as u16 + *l as u16 + carry;
+            *byte = sum as u8;
+            carry = sum >> 8;
+        }
+        assert!(!verifying.verify(b"evidence", &malleated));
+
+        let other = SigningKey::<SoftSha512>::from_seed(&[1; SEED_LEN]);
+        assert!(!VerifyingKey::from(&other).verify(b"evidence", &signature));
+    }
+}
@@ -1,11 +1,11 @@
-use crate::hasher::PatatHashAlgorithm;
+use crate::hasher::{NoiseHash, PatatHashAlgorithm, Sha256, LEAF_PREFIX, NODE_PREFIX};
 use merkle_light::hash::Algorithm;
-use merkle_light::merkle::MerkleTree;
 use merkle_light::proof::Proof;
+use rand_core::{CryptoRng, RngCore};
+use std::collections::BTreeMap;
 use std::convert::TryInto;
-use std::hash::Hasher;
-use std::iter::FromIterator;
-use optee_utee::trace_println;
+use std::marker::PhantomData;
+use crate::tee::trace_println;
 
 pub struct EvidencePath(Vec<u8>);
 
@@ -18,7 +18,7 @@ impl EvidencePath {
         let message_length = ((self.0[0] as usize) << 8) + (self.0[1] as usize);
         let mut b = vec![];
         'outer: for (_, bit) in self.0[2..].into_iter().enumerate() {
-            for i in (0..7).map(|i| 7 - i) {
+            for i in (0..8).map(|i| 7 - i) {
                 b.push((bit & (2_u8.pow(i))) >> i != 0);
                 if b.len() == message_length {
                     break 'outer;
@@ -32,13 +32,13 @@ impl EvidencePath {
 impl From<Vec<bool>> for EvidencePath {
     fn from(value: Vec<bool>) -> Self {
         let mut b = vec![];
-        b.insert(0, (value.len() >> 8).try_into().unwrap());
-        b.insert(0, (value.len() & 0xff).try_into().unwrap());
+        b.push((value.len() >> 8).try_into().unwrap());
+        b.push((value.len() & 0xff).try_into().unwrap());
         for (idx, bit) in value.into_iter().enumerate() {
             let byte = idx / 8;
             let shift = 7 - idx % 8;
             if idx % 8 == 0 {
-                b.insert(0, 0);
+                b.push(0);
             }
             b[byte + 2] |= (bit as u8) << shift;
         }
@@ -52,9 +52,14 @@ impl From<&[u8]> for EvidencePath {
     }
 }
 
-pub struct EvidenceLemma(Vec<[u8; 32]>);
+pub struct EvidenceLemma(Vec<Vec<u8>>);
 
 impl EvidenceLemma {
+    /// Parse a lemma made of hashes of `hash_len` bytes each.
+    pub fn from_bytes(value: &[u8], hash_len: usize) -> Self {
+        EvidenceLemma(value.chunks(hash_len).map(|hash| hash.to_vec()).collect())
+    }
+
     pub fn to_bytes(self) -> Vec<u8> {
         let mut b: Vec<u8> = vec![];
         for (_, hash) in self.0.into_iter().enumerate() {
@@ -63,72 +68,255 @@ impl EvidenceLemma {
         b
     }
 
-    pub fn lemma(self) -> Vec<[u8; 32]> {
+    pub fn lemma(self) -> Vec<Vec<u8>> {
         self.0
     }
 }
 
-impl From<Vec<[u8; 32]>> for EvidenceLemma {
-    fn from(value: Vec<[u8; 32]>) -> Self {
+impl From<Vec<Vec<u8>>> for EvidenceLemma {
+    fn from(value: Vec<Vec<u8>>) -> Self {
         EvidenceLemma(value)
     }
 }
 
-impl From<Vec<u8>> for EvidenceLemma {
-    fn from(value: Vec<u8>) -> Self {
-        let mut b: Vec<[u8; 32]> = vec![];
-        let mut h = vec![];
-        for (index, byte) in value.into_iter().enumerate() {
-            if index % 32 == 0 && index != 0 {
-                b.push(h.try_into().unwrap());
-                h = vec![];
-            }
-            h.push(byte);
-        }
-        b.push(h.try_into().unwrap());
-        EvidenceLemma(b)
-    }
+/// Version of the evidence format written by `EvidenceProof`.
+///
+/// Version 1 proofs start with this byte and carry the measurement itself,
+/// so that the verifier computes the leaf hash. Version 0 proofs only carry
+/// the leaf hash; they start with the high byte of the path length, which is
+/// always 0.
+pub const EVIDENCE_VERSION: u8 = 1;
+/// First byte of a proof that a claim is in a `SparseEvidenceTree`.
+pub const SPARSE_MEMBERSHIP: u8 = 2;
+/// First byte of a proof that a claim is not in a `SparseEvidenceTree`.
+pub const SPARSE_ABSENCE: u8 = 3;
+/// First byte of a proof from `get_evidence`, which is like a version 1
+/// proof but for a salted leaf.
+pub const SALTED_EVIDENCE: u8 = 4;
+
+/// Number of random bytes each leaf is salted with.
+///
+/// Measurements such as a version number have little entropy, so without a
+/// salt the measurements behind the sibling hashes in a lemma could be found
+/// by trying every candidate.
+pub const SALT_LEN: usize = 32;
+
+fn salt<R: RngCore + CryptoRng>(rng: &mut R) -> Vec<u8> {
+    let mut salt = vec![0u8; SALT_LEN];
+    rng.fill_bytes(&mut salt);
+    salt
 }
 
-impl From<&[u8]> for EvidenceLemma {
-    fn from(value: &[u8]) -> Self {
-        let mut b: Vec<[u8; 32]> = vec![];
-        let mut h = vec![];
-        for (index, byte) in value.into_iter().enumerate() {
-            if index % 32 == 0 && index != 0 {
-                b.push(h.try_into().unwrap());
-                h = vec![];
-            }
-            h.push(*byte);
+/// A measurement together with the salt it is committed with.
+struct Opening {
+    /// Empty for unsalted leaves.
+    salt: Vec<u8>,
+    measurement: Vec<u8>,
+}
+
+impl Opening {
+    /// What the leaf hashes: `salt || measurement`.
+    fn to_bytes(&self) -> Vec<u8> {
+        [self.salt.as_slice(), &self.measurement].concat()
+    }
+
+    /// Split `bytes` after a salt of `salt_len` bytes.
+    fn from_bytes(mut bytes: Vec<u8>, salt_len: usize) -> Self {
+        let measurement = bytes.split_off(salt_len.min(bytes.len()));
+        Opening {
+            salt: bytes,
+            measurement,
         }
-        b.push(h.try_into().unwrap());
-        EvidenceLemma(b)
     }
 }
 
-pub struct EvidenceProof {
+/// What an `EvidenceProof` is about.
+enum Claim {
+    /// A version 0 proof, which only has the leaf hash.
+    Legacy,
+    /// The first measurement of a positional tree.
+    Leaf(Opening),
+    /// The key of a claim in a sparse tree, with its measurement or `None`
+    /// if the claim is absent.
+    Sparse {
+        key: Vec<u8>,
+        opening: Option<Opening>,
+    },
+}
+
+/// Proof of a measurement in an evidence tree.
+///
+/// For positional trees the lemma runs from the leaf hash to the root and
+/// the path tells for each level whether the node is the left child. For
+/// sparse trees the path has one entry per level from the bottom, telling
+/// whether the sibling is a non-empty subtree; the lemma holds only those
+/// siblings, followed by the root.
+pub struct EvidenceProof<H: NoiseHash = Sha256> {
+    claim: Claim,
     lemma: EvidenceLemma,
     path: EvidencePath,
+    hash: PhantomData<H>,
 }
 
-impl EvidenceProof {
-    pub fn new(path: Vec<bool>, lemma: Vec<[u8; 32]>) -> Self {
+impl<H: NoiseHash> EvidenceProof<H> {
+    /// A proof for the leaf `salt || measurement` of a positional tree; with
+    /// an empty `salt` this is a version 1 proof.
+    pub fn new(salt: Vec<u8>, measurement: Vec<u8>, path: Vec<bool>, lemma: Vec<Vec<u8>>) -> Self {
         EvidenceProof {
+            claim: Claim::Leaf(Opening { salt, measurement }),
             lemma: lemma.into(),
             path: path.into(),
+            hash: PhantomData,
+        }
+    }
+
+    /// The first byte of the encoded proof: 0, `EVIDENCE_VERSION`,
+    /// `SPARSE_MEMBERSHIP`, `SPARSE_ABSENCE` or `SALTED_EVIDENCE`.
+    pub fn version(&self) -> u8 {
+        match &self.claim {
+            Claim::Legacy => 0,
+            Claim::Leaf(opening) if opening.salt.is_empty() => EVIDENCE_VERSION,
+            Claim::Leaf(_) => SALTED_EVIDENCE,
+            Claim::Sparse {
+                opening: Some(_), ..
+            } => SPARSE_MEMBERSHIP,
+            Claim::Sparse { opening: None, .. } => SPARSE_ABSENCE,
         }
     }
 
+    fn opening(&self) -> Option<&Opening> {
+        match &self.claim {
+            Claim::Legacy => None,
+            Claim::Leaf(opening) => Some(opening),
+            Claim::Sparse { opening, .. } => opening.as_ref(),
+        }
+    }
+
+    /// The proven measurement, `None` for version 0 proofs and proofs of
+    /// absence.
+    pub fn measurement(&self) -> Option<&[u8]> {
+        self.opening().map(|opening| opening.measurement.as_slice())
+    }
+
+    /// The salt of the proven measurement, empty for version 1 proofs.
+    pub fn salt(&self) -> Option<&[u8]> {
+        self.opening().map(|opening| opening.salt.as_slice())
+    }
+
+    /// The root the proof leads to, which the verifier compares with the
+    /// root it expects.
+    pub fn root(&self) -> Option<&[u8]> {
+        self.lemma.0.last().map(|root| root.as_slice())
+    }
+
+    /// The key of the claim for proofs from a sparse tree, see `claim_key`.
+    pub fn key(&self) -> Option<&[u8]> {
+        match &self.claim {
+            Claim::Sparse { key, .. } => Some(key),
+            _ => None,
+        }
+    }
+
+    /// Check that the measurement is a leaf of the tree in the lemma, or for
+    /// sparse trees that the claim is present or absent as stated.
+    ///
+    /// Version 0 proofs are rejected: as they start from a leaf hash, an
+    /// interior node can be passed off as a leaf with a shorter path.
     pub fn valid(self) -> bool {
-        let proof: Proof<[u8; 32]> = Proof::new(self.lemma.lemma(), self.path.path());
-        proof.validate::<PatatHashAlgorithm>()
+        match &self.claim {
+            Claim::Legacy => false,
+            Claim::Leaf(opening) => {
+                let leaf = PatatHashAlgorithm::<H>::new().leaf(opening.to_bytes());
+                self.lemma.0.first() == Some(&leaf) && self.valid_legacy()
+            }
+            Claim::Sparse { key, opening } => {
+                let mut hasher = PatatHashAlgorithm::<H>::new();
+                let leaf = sparse_leaf(&mut hasher, key, opening.as_ref());
+                valid_sparse(&mut hasher, key, leaf, self.path.path(), self.lemma.lemma())
+            }
+        }
+    }
+
+    /// Check the path from the leaf hash to the root, without looking at the
+    /// measurement. This is all a version 0 proof allows for, so only use it
+    /// for TAs that cannot be updated.
+    pub fn valid_legacy(self) -> bool {
+        let (lemma, path) = (self.lemma.lemma(), self.path.path());
+        if lemma.len() < 3 || lemma.len() != path.len() + 2 {
+            return false;
+        }
+        let proof: Proof<Vec<u8>> = Proof::new(lemma, path);
+        proof.validate::<PatatHashAlgorithm<H>>()
+    }
+
+    /// Parse a proof encoded by `Vec::from`, `None` if its version is
+    /// unknown, a length runs past the end of `value` or bytes are left over.
+    pub fn from_bytes(value: &[u8]) -> Option<Self> {
+        let version = *value.first()?;
+        let mut claim = Claim::Legacy;
+        let mut rest = value;
+        if version != 0 {
+            let (bytes, after) = length_prefixed(&value[1..])?;
+            let mut bytes = bytes.to_vec();
+            rest = after;
+            claim = match version {
+                SPARSE_MEMBERSHIP | SPARSE_ABSENCE => {
+                    let opening = bytes.split_off(H::HASHLEN.min(bytes.len()));
+                    Claim::Sparse {
+                        key: bytes,
+                        opening: Some(Opening::from_bytes(opening, SALT_LEN))
+                            .filter(|_| version == SPARSE_MEMBERSHIP),
+                    }
+                }
+                SALTED_EVIDENCE => Claim::Leaf(Opening::from_bytes(bytes, SALT_LEN)),
+                EVIDENCE_VERSION => Claim::Leaf(Opening::from_bytes(bytes, 0)),
+                _ => return None,
+            };
+        }
+
+        let (path_bytes, rest) = length_prefixed(rest)?;
+        let (lemma_bytes, rest) = length_prefixed(rest)?;
+        if !rest.is_empty() || lemma_bytes.len() % H::HASHLEN != 0 {
+            return None;
+        }
+        // The path starts with its length in bits.
+        let path_length = u16::from_be_bytes(path_bytes.get(..2)?.try_into().unwrap()) as usize;
+        if path_bytes.len() != 2 + (path_length + 7) / 8 {
+            return None;
+        }
+        Some(EvidenceProof {
+            claim,
+            lemma: EvidenceLemma::from_bytes(lemma_bytes, H::HASHLEN),
+            path: path_bytes.into(),
+            hash: PhantomData,
+        })
     }
 }
 
-impl From<EvidenceProof> for Vec<u8> {
-    fn from(value: EvidenceProof) -> Self {
+impl<H: NoiseHash> From<EvidenceProof<H>> for Vec<u8> {
+    fn from(value: EvidenceProof<H>) -> Self {
         let mut buffer = vec![];
 
+        let version = value.version();
+        let claim = match value.claim {
+            Claim::Legacy => None,
+            Claim::Leaf(opening) => Some(opening.to_bytes()),
+            Claim::Sparse { mut key, opening } => {
+                key.extend(
+                    opening
+                        .map(|opening| opening.to_bytes())
+                        .unwrap_or_default(),
+                );
+                Some(key)
+            }
+        };
+        if let Some(mut claim) = claim {
+            buffer.push(version);
+            buffer.push((claim.len() >> 8).try_into().unwrap());
+            buffer.push((claim.len() & 0xff).try_into().unwrap());
+            buffer.append(&mut claim);
+        }
+
         let mut path_bytes = value.path.to_bytes();
         let path_length = path_bytes.len();
         let mut lemma_bytes = value.lemma.to_bytes();
@@ -145,38 +333,1037 @@ impl From<EvidenceProof> for Vec<u8> {
     }
 }
 
-impl From<Vec<u8>> for EvidenceProof {
-    fn from(value: Vec<u8>) -> Self {
-        let path_length = ((value[0] as usize) << 8) + (value[1] as usize);
+/// Split a field that starts with its length as a big-endian 16-bit number
+/// off the front of `value`.
+fn length_prefixed(value: &[u8]) -> Option<(&[u8], &[u8])> {
+    let length = u16::from_be_bytes(value.get(..2)?.try_into().unwrap()) as usize;
+    let field = value.get(2..2 + length)?;
+    Some((field, &value[2 + length..]))
+}
 
-        let path_bytes = &value[2..2 + path_length];
-        let path: EvidencePath = path_bytes.into();
+/// An inclusion proof in the evidence tree, as produced by `MerkleTree`.
+pub type TreeProof = Proof<Vec<u8>>;
 
-        let lemma_length =
-            ((value[2 + path_length] as usize) << 8) + (value[3 + path_length] as usize);
-        let lemma_bytes = &value[4 + path_length..4 + path_length + lemma_length];
-        let lemma: EvidenceLemma = lemma_bytes.into();
-        EvidenceProof { lemma, path }
+/// Builds an evidence tree one leaf at a time.
+///
+/// The root and proofs are those of the `MerkleTree` over the same leaves,
+/// including its duplication of the last node of odd-width levels, but only
+/// the left node of every level that still waits for its sibling is kept, so
+/// memory grows with the height of the tree rather than with the number of
+/// leaves. A single hash state is reused for every node. Proofs can only be
+/// produced for the leaves asked for when the builder is created.
+pub struct TreeBuilder<H: NoiseHash = Sha256> {
+    hasher: H,
+    levels: Vec<Level>,
+    proofs: Vec<PartialProof>,
+}
+
+#[derive(Default)]
+struct Level {
+    /// The number of nodes added to this level so far.
+    count: usize,
+    /// The last node, if it has no sibling yet.
+    pending: Option<Vec<u8>>,
+}
+
+struct PartialProof {
+    index: usize,
+    leaf: Option<Vec<u8>>,
+    /// The sibling of the leaf or its ancestor at every level, as they come by.
+    siblings: Vec<Option<Vec<u8>>>,
+}
+
+impl<H: NoiseHash> TreeBuilder<H> {
+    /// Create a builder that will also prove the leaves at `prove`.
+    pub fn new(prove: &[usize]) -> Self {
+        TreeBuilder {
+            hasher: H::default(),
+            levels: vec![],
+            proofs: prove
+                .iter()
+                .map(|&index| PartialProof {
+                    index,
+                    leaf: None,
+                    siblings: vec![],
+                })
+                .collect(),
+        }
+    }
+
+    /// Add the next leaf.
+    pub fn push(&mut self, leaf: &[u8]) {
+        let hash = self.hash(&[&[LEAF_PREFIX], leaf]);
+        let index = self.count(0);
+        for proof in self.proofs.iter_mut().filter(|proof| proof.index == index) {
+            proof.leaf = Some(hash.clone());
+        }
+        self.add(0, hash);
+    }
+
+    /// Finish the tree and return its root and the proofs asked for, or
+    /// `None` if, as for `MerkleTree`, there were fewer than two leaves, or a
+    /// proof was asked for a leaf that was never added.
+    pub fn finish(mut self) -> Option<(Vec<u8>, Vec<TreeProof>)> {
+        let leaves = self.count(0);
+        if leaves < 2 || self.proofs.iter().any(|proof| proof.index >= leaves) {
+            return None;
+        }
+
+        // Complete the right edge of the tree from the bottom up. `carry` is
+        // the last node of the current level, if it only now became known.
+        let mut width = leaves;
+        let mut level = 0;
+        let mut carry: Option<Vec<u8>> = None;
+        let root = loop {
+            let pending = self.levels.get_mut(level).and_then(|l| l.pending.take());
+            if width == 1 {
+                break carry.or(pending)?;
+            }
+            let count = self.count(level);
+            carry = match (pending, carry) {
+                (Some(left), Some(right)) => {
+                    self.record(level, count, &right);
+                    Some(self.node(&left, &right))
+                }
+                (Some(last), None) => {
+                    self.record(level, count, &last);
+                    Some(self.node(&last, &last))
+                }
+                (None, Some(last)) => {
+                    self.record(level, count, &last);
+                    self.record(level, count + 1, &last);
+                    Some(self.node(&last, &last))
+                }
+                (None, None) => None,
+            };
+            width = (width + 1) / 2;
+            level += 1;
+        };
+
+        let proofs = self
+            .proofs
+            .into_iter()
+            .map(|proof| {
+                let index = proof.index;
+                let mut lemma = vec![proof.leaf.unwrap()];
+                lemma.extend(proof.siblings.into_iter().take(level).map(Option::unwrap));
+                lemma.push(root.clone());
+                let path = (0..level).map(|l| (index >> l) & 1 == 0).collect();
+                Proof::new(lemma, path)
+            })
+            .collect();
+        Some((root, proofs))
+    }
+
+    fn count(&self, level: usize) -> usize {
+        self.levels.get(level).map_or(0, |l| l.count)
+    }
+
+    /// Add the next node of `level`, joining it with the one before it.
+    fn add(&mut self, level: usize, hash: Vec<u8>) {
+        if self.levels.len() == level {
+            self.levels.push(Level::default());
+        }
+        let index = self.levels[level].count;
+        self.levels[level].count += 1;
+        self.record(level, index, &hash);
+        match self.levels[level].pending.take() {
+            Some(left) => {
+                let parent = self.node(&left, &hash);
+                self.add(level + 1, parent);
+            }
+            None => self.levels[level].pending = Some(hash),
+        }
+    }
+
+    /// Keep the node at `index` of `level` for the proofs it is a sibling in.
+    fn record(&mut self, level: usize, index: usize, hash: &[u8]) {
+        for proof in &mut self.proofs {
+            if (proof.index >> level) ^ 1 == index {
+                if proof.siblings.len() <= level {
+                    proof.siblings.resize(level + 1, None);
+                }
+                proof.siblings[level] = Some(hash.to_vec());
+            }
+        }
+    }
+
+    fn node(&mut self, left: &[u8], right: &[u8]) -> Vec<u8> {
+        self.hash(&[&[NODE_PREFIX], left, right])
+    }
+
+    fn hash(&mut self, parts: &[&[u8]]) -> Vec<u8> {
+        for part in parts {
+            self.hasher.input(part);
+        }
+        let mut out = vec![0u8; H::HASHLEN];
+        self.hasher.result(&mut out);
+        out
     }
 }
 
-impl From<&[u8]> for EvidenceProof {
-    fn from(value: &[u8]) -> Self {
-        let path_length = ((value[0] as usize) << 8) + (value[1] as usize);
-        let path_bytes = &value[2..2 + path_length];
-        let path: EvidencePath = path_bytes.into();
+/// Build the evidence tree and return the proof for the first measurement.
+///
+/// Measurements are expected to be `HASHLEN` bytes long, as they are digests
+/// produced with the same hash function as the tree. Every leaf is salted
+/// with fresh random bytes from `rng`; only the salt of the first
+/// measurement is disclosed. The measurements are consumed one at a time, so
+/// they need not all be in memory at once.
+pub fn get_evidence<H: NoiseHash, I: IntoIterator<Item = Vec<u8>>, R: RngCore + CryptoRng>(
+    evidence: I,
+    rng: &mut R,
+) -> EvidenceProof<H> {
+    let mut tree = TreeBuilder::<H>::new(&[0]);
+    let mut first = None;
+    let mut count = 0;
+    for measurement in evidence {
+        count += 1;
+        let opening = Opening {
+            salt: salt(rng),
+            measurement,
+        };
+        tree.push(&opening.to_bytes());
+        first.get_or_insert(opening);
+    }
+    // Only the count: the salts are there to keep the measurements hidden.
+    trace_println!("Evidence: {} measurements", count);
+    let first = first.expect("no evidence to prove");
+    let (_, mut proofs) = tree.finish().unwrap();
+    let p = proofs.pop().unwrap();
+    EvidenceProof::new(
+        first.salt,
+        first.measurement,
+        p.path().to_vec(),
+        p.lemma().to_vec(),
+    )
+}
+
+/// Prefix of the transport message with which the TA reports an
+/// `EvidenceUpdate`; the encoded update follows it.
+pub const EVIDENCE_UPDATE: &[u8] = b"patat-evidence-update";
+
+/// Heights of the peaks of a mountain range with `size` leaves, highest
+/// first.
+fn peak_heights(size: u64) -> Vec<u32> {
+    (0..64)
+        .rev()
+        .filter(|height| size >> height & 1 == 1)
+        .collect()
+}
+
+/// Heights of the aligned perfect trees that cover the leaves `from..to`.
+///
+/// Each of them is a node of every mountain range with at least `to` leaves,
+/// which is what makes a range of `from` leaves a prefix of one of `to`.
+fn subtree_heights(mut from: u64, to: u64) -> Vec<u32> {
+    let mut heights = vec![];
+    while from < to {
+        let mut height = from.trailing_zeros().min(63);
+        while 1 << height > to - from {
+            height -= 1;
+        }
+        heights.push(height);
+        from += 1 << height;
+    }
+    heights
+}
+
+/// Push a perfect tree of `height` onto `peaks` and merge the peaks of equal
+/// height, as appending its leaves one by one would.
+fn push_subtree<H: NoiseHash>(
+    hasher: &mut PatatHashAlgorithm<H>,
+    peaks: &mut Vec<(u32, Vec<u8>)>,
+    height: u32,
+    hash: Vec<u8>,
+) {
+    peaks.push((height, hash));
+    while peaks.len() > 1 && peaks[peaks.len() - 2].0 == peaks[peaks.len() - 1].0 {
+        let (height, right) = peaks.pop().unwrap();
+        let (_, left) = peaks.pop().unwrap();
+        peaks.push((height + 1, hasher.node(left, right)));
+    }
+}
+
+/// Fold the peaks into a single root, from right to left.
+fn bag<H: NoiseHash>(hasher: &mut PatatHashAlgorithm<H>, peaks: &[Vec<u8>]) -> Option<Vec<u8>> {
+    peaks
+        .iter()
+        .rev()
+        .cloned()
+        .reduce(|right, left| hasher.node(left, right))
+}
+
+/// Append-only log of measurements, kept as a Merkle Mountain Range.
+///
+/// Unlike `get_evidence`, which builds a new tree for every round, the log
+/// commits to all measurements appended so far. Each `report` comes with a
+/// proof that the new root extends the previously reported one.
+pub struct EvidenceLog<H: NoiseHash = Sha256> {
+    /// Heights and hashes of the peaks, highest first.
+    peaks: Vec<(u32, Vec<u8>)>,
+    len: u64,
+    /// Size and peaks at the last report.
+    reported: (u64, Vec<Vec<u8>>),
+    /// Heights and hashes of the perfect trees over the measurements
+    /// appended since the last report, merged only where they are aligned, so
+    /// that they are the subtrees of `subtree_heights` that `report` proves.
+    pending: Vec<(u32, Vec<u8>)>,
+    hasher: PatatHashAlgorithm<H>,
+}
+
+impl<H: NoiseHash> Default for EvidenceLog<H> {
+    fn default() -> Self {
+        EvidenceLog {
+            peaks: vec![],
+            len: 0,
+            reported: (0, vec![]),
+            pending: vec![],
+            hasher: PatatHashAlgorithm::new(),
+        }
+    }
+}
+
+impl<H: NoiseHash> EvidenceLog<H> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of measurements in the log.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn append(&mut self, measurement: &[u8]) {
+        let leaf = self.hasher.leaf(measurement.to_vec());
+        push_subtree(&mut self.hasher, &mut self.peaks, 0, leaf.clone());
+        self.len += 1;
+
+        // Two trees of equal height merge if the left one starts at a
+        // multiple of the merged width, which is where the leaves end.
+        self.pending.push((0, leaf));
+        while self.pending.len() > 1 {
+            let height = self.pending[self.pending.len() - 1].0;
+            if self.pending[self.pending.len() - 2].0 != height
+                || self.len.trailing_zeros() <= height
+            {
+                break;
+            }
+            let (_, right) = self.pending.pop().unwrap();
+            let (_, left) = self.pending.pop().unwrap();
+            let node = self.hasher.node(left, right);
+            self.pending.push((height + 1, node));
+        }
+    }
+
+    /// Root over all measurements, `None` while the log is empty.
+    pub fn root(&mut self) -> Option<Vec<u8>> {
+        let peaks = self.peak_hashes();
+        bag(&mut self.hasher, &peaks)
+    }
+
+    /// The current root, with a consistency proof against the root of the
+    /// previous report. `None` while the log is empty.
+    pub fn report(&mut self) -> Option<EvidenceUpdate<H>> {
+        let root = self.root()?;
+        let (old_size, mut proof) = std::mem::take(&mut self.reported);
+        proof.extend(self.pending.drain(..).map(|(_, hash)| hash));
+
+        self.reported = (self.len, self.peak_hashes());
+        Some(EvidenceUpdate {
+            old_size,
+            size: self.len,
+            root,
+            proof,
+            hash: PhantomData,
+        })
+    }
+
+    fn peak_hashes(&self) -> Vec<Vec<u8>> {
+        self.peaks.iter().map(|(_, hash)| hash.clone()).collect()
+    }
+
+    /// Restore a log at the state of its last report, as encoded by
+    /// `Vec::from(&log)`: the size as a big-endian 64-bit number followed by
+    /// the peaks.
+    pub fn from_bytes(value: &[u8]) -> Option<Self> {
+        let size = u64::from_be_bytes(value.get(..8)?.try_into().unwrap());
+        let heights = peak_heights(size);
+        if value.len() != 8 + heights.len() * H::HASHLEN {
+            return None;
+        }
+        let peaks: Vec<Vec<u8>> = value[8..]
+            .chunks(H::HASHLEN)
+            .map(|hash| hash.to_vec())
+            .collect();
+        Some(EvidenceLog {
+            peaks: heights.into_iter().zip(peaks.clone()).collect(),
+            len: size,
+            reported: (size, peaks),
+            pending: vec![],
+            hasher: PatatHashAlgorithm::new(),
+        })
+    }
+}
+
+/// Only the state at the last report is encoded, so measurements appended
+/// since then are dropped when the log is restored. The verifier has not seen
+/// them, so its next update still extends the root it accepted last.
+impl<H: NoiseHash> From<&EvidenceLog<H>> for Vec<u8> {
+    fn from(value: &EvidenceLog<H>) -> Self {
+        let (size, peaks) = &value.reported;
+        let mut buffer = size.to_be_bytes().to_vec();
+        for hash in peaks {
+            buffer.extend_from_slice(hash);
+        }
+        buffer
+    }
+}
+
+/// A root of an `EvidenceLog` with the proof that it extends an earlier root.
+pub struct EvidenceUpdate<H: NoiseHash = Sha256> {
+    old_size: u64,
+    size: u64,
+    root: Vec<u8>,
+    /// The peaks at `old_size`, followed by the subtrees of the measurements
+    /// appended since.
+    proof: Vec<Vec<u8>>,
+    hash: PhantomData<H>,
+}
+
+impl<H: NoiseHash> EvidenceUpdate<H> {
+    /// Size of the log at the previous report, 0 for the first one.
+    pub fn old_size(&self) -> u64 {
+        self.old_size
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn root(&self) -> &[u8] {
+        &self.root
+    }
+
+    /// Check that the log was only appended to since `previous`, the size
+    /// and root of the last update the verifier accepted, or `None` if this
+    /// is the first one.
+    pub fn valid(&self, previous: Option<(u64, &[u8])>) -> bool {
+        let (old_size, old_root) = previous.unwrap_or((0, &[]));
+        if self.old_size != old_size || old_size > self.size || self.size == 0 {
+            return false;
+        }
+        let old_heights = peak_heights(old_size);
+        let new_heights = subtree_heights(old_size, self.size);
+        if self.proof.len() != old_heights.len() + new_heights.len() {
+            return false;
+        }
+
+        let mut hasher = PatatHashAlgorithm::<H>::new();
+        let (old_peaks, subtrees) = self.proof.split_at(old_heights.len());
+        if old_size > 0 && bag(&mut hasher, old_peaks).as_deref() != Some(old_root) {
+            return false;
+        }
+        let mut peaks: Vec<(u32, Vec<u8>)> =
+            old_heights.into_iter().zip(old_peaks.to_vec()).collect();
+        for (height, subtree) in new_heights.into_iter().zip(subtrees) {
+            push_subtree(&mut hasher, &mut peaks, height, subtree.clone());
+        }
+        let peaks: Vec<Vec<u8>> = peaks.into_iter().map(|(_, hash)| hash).collect();
+        bag(&mut hasher, &peaks).as_deref() == Some(self.root.as_slice())
+    }
+
+    /// Parse an update encoded as the old size and size as big-endian 64-bit
+    /// numbers, followed by the root and the proof.
+    pub fn from_bytes(value: &[u8]) -> Option<Self> {
+        let hashes = value.len().checked_sub(16)? / H::HASHLEN;
+        if hashes == 0 || value.len() != 16 + hashes * H::HASHLEN {
+            return None;
+        }
+        let old_size = u64::from_be_bytes(value[..8].try_into().unwrap());
+        let size = u64::from_be_bytes(value[8..16].try_into().unwrap());
+        let mut hashes = value[16..].chunks(H::HASHLEN).map(|hash| hash.to_vec());
+        Some(EvidenceUpdate {
+            old_size,
+            size,
+            root: hashes.next()?,
+            proof: hashes.collect(),
+            hash: PhantomData,
+        })
+    }
+}
+
+impl<H: NoiseHash> From<EvidenceUpdate<H>> for Vec<u8> {
+    fn from(value: EvidenceUpdate<H>) -> Self {
+        let mut buffer = vec![];
+        buffer.extend_from_slice(&value.old_size.to_be_bytes());
+        buffer.extend_from_slice(&value.size.to_be_bytes());
+        buffer.extend_from_slice(&value.root);
+        for hash in value.proof {
+            buffer.extend_from_slice(&hash);
+        }
+        buffer
+    }
+}
+
+/// Key of `claim` in a `SparseEvidenceTree`: its hash, read one bit per
+/// level from the most significant bit.
+pub fn claim_key<H: NoiseHash>(claim: &[u8]) -> Vec<u8> {
+    let mut hasher = H::default();
+    hasher.input(claim);
+    let mut key = vec![0u8; H::HASHLEN];
+    hasher.result(&mut key);
+    key
+}
+
+fn key_bit(key: &[u8], depth: usize) -> bool {
+    key[depth / 8] >> (7 - depth % 8) & 1 == 1
+}
+
+/// Leaf of a sparse tree: the hash of the key, salt and measurement, or all
+/// zeros for an absent claim.
+fn sparse_leaf<H: NoiseHash>(
+    hasher: &mut PatatHashAlgorithm<H>,
+    key: &[u8],
+    opening: Option<&Opening>,
+) -> Vec<u8> {
+    match opening {
+        Some(opening) => {
+            let mut leaf = key.to_vec();
+            leaf.append(&mut opening.to_bytes());
+            hasher.leaf(leaf)
+        }
+        None => vec![0u8; H::HASHLEN],
+    }
+}
+
+/// Roots of the empty subtrees of a sparse tree, by height.
+fn empty_subtrees<H: NoiseHash>(hasher: &mut PatatHashAlgorithm<H>) -> Vec<Vec<u8>> {
+    let mut empty = vec![vec![0u8; H::HASHLEN]];
+    for height in 0..H::HASHLEN * 8 {
+        let below = empty[height].clone();
+        empty.push(hasher.node(below.clone(), below));
+    }
+    empty
+}
+
+fn valid_sparse<H: NoiseHash>(
+    hasher: &mut PatatHashAlgorithm<H>,
+    key: &[u8],
+    leaf: Vec<u8>,
+    path: Vec<bool>,
+    lemma: Vec<Vec<u8>>,
+) -> bool {
+    let depth = H::HASHLEN * 8;
+    let siblings = path.iter().filter(|non_empty| **non_empty).count();
+    if key.len() != H::HASHLEN || path.len() != depth || lemma.len() != siblings + 1 {
+        return false;
+    }
 
-        let lemma_length =
-            ((value[2 + path_length] as usize) << 8) + (value[3 + path_length] as usize);
-        let lemma_bytes = &value[4 + path_length..4 + path_length + lemma_length];
-        let lemma: EvidenceLemma = lemma_bytes.into();
-        EvidenceProof { lemma, path }
+    let empty = empty_subtrees(hasher);
+    let mut lemma = lemma.into_iter();
+    let mut hash = leaf;
+    for (height, non_empty) in path.into_iter().enumerate() {
+        let sibling = if non_empty {
+            lemma.next().unwrap()
+        } else {
+            empty[height].clone()
+        };
+        hash = if key_bit(key, depth - 1 - height) {
+            hasher.node(sibling, hash)
+        } else {
+            hasher.node(hash, sibling)
+        };
     }
+    lemma.next() == Some(hash)
+}
+
+/// Evidence tree keyed by claim rather than by position.
+///
+/// Every possible `claim_key` has a leaf, so besides proving that a claim
+/// was measured, the TA can prove that it was not, e.g. that there is no
+/// "debug enabled" claim. Proofs go through `EvidenceProof` like those of the
+/// positional tree.
+pub struct SparseEvidenceTree<H: NoiseHash = Sha256> {
+    /// Salted measurement and leaf hash by key.
+    claims: BTreeMap<Vec<u8>, (Opening, Vec<u8>)>,
+    empty: Vec<Vec<u8>>,
+    hash: PhantomData<H>,
 }
 
-pub fn get_evidence(evidence: Vec<[u8; 32]>) -> EvidenceProof {
-    trace_println!("Evidence: {:?}", evidence);
-    let t: MerkleTree<[u8; 32], PatatHashAlgorithm> = MerkleTree::from_iter(evidence);
-    let p = t.gen_proof(0);
-    EvidenceProof::new(p.path().to_vec(), p.lemma().to_vec())
+impl<H: NoiseHash> Default for SparseEvidenceTree<H> {
+    fn default() -> Self {
+        SparseEvidenceTree {
+            claims: BTreeMap::new(),
+            empty: empty_subtrees(&mut PatatHashAlgorithm::<H>::new()),
+            hash: PhantomData,
+        }
+    }
+}
+
+impl<H: NoiseHash> SparseEvidenceTree<H> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.claims.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.claims.is_empty()
+    }
+
+    /// Set the measurement of `claim`, replacing an earlier one, and salt it
+    /// with fresh random bytes from `rng`.
+    pub fn insert<R: RngCore + CryptoRng>(
+        &mut self,
+        claim: &[u8],
+        measurement: Vec<u8>,
+        rng: &mut R,
+    ) {
+        let key = claim_key::<H>(claim);
+        let opening = Opening {
+            salt: salt(rng),
+            measurement,
+        };
+        let leaf = sparse_leaf(&mut PatatHashAlgorithm::<H>::new(), &key, Some(&opening));
+        self.claims.insert(key, (opening, leaf));
+    }
+
+    pub fn root(&self) -> Vec<u8> {
+        self.subtree(&mut PatatHashAlgorithm::new(), &self.leaves(), 0)
+    }
+
+    /// Prove that `claim` is in the tree with its measurement, or that it is
+    /// absent.
+    pub fn prove(&self, claim: &[u8]) -> EvidenceProof<H> {
+        let key = claim_key::<H>(claim);
+        let mut hasher = PatatHashAlgorithm::<H>::new();
+        let leaves = self.leaves();
+        let mut range = &leaves[..];
+        let mut siblings = vec![];
+        for depth in 0..H::HASHLEN * 8 {
+            let (left, right) = range.split_at(range.partition_point(|(k, _)| !key_bit(k, depth)));
+            let (own, other) = if key_bit(&key, depth) {
+                (right, left)
+            } else {
+                (left, right)
+            };
+            siblings.push(
+                Some(other)
+                    .filter(|other| !other.is_empty())
+                    .map(|other| self.subtree(&mut hasher, other, depth + 1)),
+            );
+            range = own;
+        }
+        siblings.reverse();
+
+        let path: Vec<bool> = siblings.iter().map(Option::is_some).collect();
+        let mut lemma: Vec<Vec<u8>> = siblings.into_iter().flatten().collect();
+        lemma.push(self.subtree(&mut hasher, &leaves, 0));
+        let opening = self.claims.get(&key).map(|(opening, _)| Opening {
+            salt: opening.salt.clone(),
+            measurement: opening.measurement.clone(),
+        });
+        EvidenceProof {
+            claim: Claim::Sparse { key, opening },
+            lemma: lemma.into(),
+            path: path.into(),
+            hash: PhantomData,
+        }
+    }
+
+    fn leaves(&self) -> Vec<(&[u8], &[u8])> {
+        self.claims
+            .iter()
+            .map(|(key, (_, leaf))| (key.as_slice(), leaf.as_slice()))
+            .collect()
+    }
+
+    /// Root of the subtree at `depth` holding `leaves`, which share the
+    /// first `depth` bits of their keys.
+    fn subtree(
+        &self,
+        hasher: &mut PatatHashAlgorithm<H>,
+        leaves: &[(&[u8], &[u8])],
+        depth: usize,
+    ) -> Vec<u8> {
+        let height = H::HASHLEN * 8 - depth;
+        if leaves.is_empty() {
+            return self.empty[height].clone();
+        }
+        if height == 0 {
+            return leaves[0].1.to_vec();
+        }
+        let (left, right) =
+            leaves.split_at(leaves.partition_point(|(key, _)| !key_bit(key, depth)));
+        let left = self.subtree(hasher, left, depth + 1);
+        let right = self.subtree(hasher, right, depth + 1);
+        hasher.node(left, right)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Blake2s;
+    use merkle_light::merkle::MerkleTree;
+    use rand_chacha::ChaCha20Rng;
+    use rand_core::SeedableRng;
+    use std::iter::FromIterator;
+
+    fn rng() -> ChaCha20Rng {
+        ChaCha20Rng::from_seed([7; 32])
+    }
+
+    fn measurement(i: u64) -> Vec<u8> {
+        let mut hasher = Blake2s::default();
+        hasher.input(&i.to_be_bytes());
+        let mut out = vec![0u8; Blake2s::HASHLEN];
+        hasher.result(&mut out);
+        out
+    }
+
+    fn log(size: u64) -> EvidenceLog<Blake2s> {
+        let mut log = EvidenceLog::new();
+        for i in 0..size {
+            log.append(&measurement(i));
+        }
+        log
+    }
+
+    fn algorithm() -> PatatHashAlgorithm<Blake2s> {
+        PatatHashAlgorithm::new()
+    }
+
+    fn tree(leaves: u64) -> MerkleTree<Vec<u8>, PatatHashAlgorithm<Blake2s>> {
+        MerkleTree::from_iter((0..leaves).map(measurement))
+    }
+
+    #[test]
+    fn leaves_and_nodes_are_domain_separated() {
+        let (left, right) = (measurement(0), measurement(1));
+        let mut both = left.clone();
+        both.extend_from_slice(&right);
+        assert_ne!(
+            algorithm().leaf(both),
+            algorithm().node(left.clone(), right.clone())
+        );
+
+        let mut hasher = Blake2s::default();
+        hasher.input(&[NODE_PREFIX]);
+        hasher.input(&left);
+        hasher.input(&right);
+        let mut expected = vec![0u8; Blake2s::HASHLEN];
+        hasher.result(&mut expected);
+        assert_eq!(algorithm().node(left, right), expected);
+    }
+
+    #[test]
+    fn streamed_trees_match_merkle_trees() {
+        for leaves in 2..=33 {
+            let mut builder =
+                TreeBuilder::<Blake2s>::new(&(0..leaves as usize).collect::<Vec<_>>());
+            for i in 0..leaves {
+                builder.push(&measurement(i));
+            }
+            let (root, proofs) = builder.finish().unwrap();
+
+            let tree = tree(leaves);
+            assert_eq!(root, tree.root());
+            for (i, proof) in proofs.into_iter().enumerate() {
+                assert_eq!(proof, tree.gen_proof(i));
+                assert!(proof.validate::<PatatHashAlgorithm<Blake2s>>());
+            }
+        }
+    }
+
+    #[test]
+    fn streamed_trees_keep_only_the_frontier() {
+        let mut builder = TreeBuilder::<Blake2s>::new(&[700]);
+        for i in 0..1000 {
+            builder.push(&measurement(i));
+            let pending = builder.levels.iter().filter(|l| l.pending.is_some());
+            assert_eq!(pending.count(), (i + 1).count_ones() as usize);
+        }
+        assert_eq!(builder.levels.len(), 10);
+
+        let (root, proofs) = builder.finish().unwrap();
+        assert_eq!(root, tree(1000).root());
+        assert_eq!(proofs, vec![tree(1000).gen_proof(700)]);
+
+        assert!(TreeBuilder::<Blake2s>::new(&[]).finish().is_none());
+        let mut builder = TreeBuilder::<Blake2s>::new(&[2]);
+        builder.push(&measurement(0));
+        builder.push(&measurement(1));
+        assert!(builder.finish().is_none());
+    }
+
+    #[test]
+    fn proofs_carry_the_measurement() {
+        let proof = get_evidence::<Blake2s, _, _>((0..8).map(measurement), &mut rng());
+        let proof = EvidenceProof::<Blake2s>::from_bytes(&Vec::from(proof)).unwrap();
+        assert_eq!(proof.version(), SALTED_EVIDENCE);
+        assert_eq!(proof.salt().map(<[u8]>::len), Some(SALT_LEN));
+        assert_eq!(proof.measurement(), Some(measurement(0).as_slice()));
+        assert!(proof.valid());
+
+        let evidence = (0..8).map(measurement);
+        let mut bytes = Vec::from(get_evidence::<Blake2s, _, _>(evidence, &mut rng()));
+        bytes[3] ^= 1;
+        let tampered = EvidenceProof::<Blake2s>::from_bytes(&bytes).unwrap();
+        assert!(!tampered.valid());
+
+        // Version 1 proofs have no salt.
+        let leaf = algorithm().leaf(measurement(0));
+        let root = algorithm().node(leaf.clone(), measurement(1));
+        let lemma = vec![leaf, measurement(1), root];
+        let unsalted = round_trip(EvidenceProof::new(
+            vec![],
+            measurement(0),
+            vec![true],
+            lemma,
+        ));
+        assert_eq!(unsalted.version(), EVIDENCE_VERSION);
+        assert!(unsalted.valid());
+    }
+
+    #[test]
+    fn salts_hide_undisclosed_leaves() {
+        // All measurements but the disclosed one are equal and guessable.
+        let mut evidence = vec![vec![0u8; Blake2s::HASHLEN]; 8];
+        evidence[0] = measurement(0);
+        let mut rng = rng();
+        let first = get_evidence::<Blake2s, _, _>(evidence.clone(), &mut rng);
+        let second = get_evidence::<Blake2s, _, _>(evidence, &mut rng);
+
+        let guess = algorithm().leaf(vec![0u8; Blake2s::HASHLEN]);
+        assert_ne!(first.lemma.0[1], guess);
+        assert_ne!(first.lemma.0[1], second.lemma.0[1]);
+        assert_ne!(first.salt(), second.salt());
+        assert!(first.valid() && second.valid());
+    }
+
+    /// A version 0 proof, sent over the wire and parsed again.
+    fn legacy(path: Vec<bool>, lemma: Vec<Vec<u8>>) -> EvidenceProof<Blake2s> {
+        let proof = EvidenceProof::<Blake2s> {
+            claim: Claim::Legacy,
+            lemma: lemma.into(),
+            path: path.into(),
+            hash: PhantomData,
+        };
+        EvidenceProof::from_bytes(&Vec::from(proof)).unwrap()
+    }
+
+    #[test]
+    fn interior_node_cannot_pass_as_a_leaf() {
+        let tree = tree(4);
+        let proof = tree.gen_proof(0);
+        let interior = algorithm().node(proof.lemma()[0].clone(), proof.lemma()[1].clone());
+        let lemma = vec![interior.clone(), proof.lemma()[2].clone(), tree.root()];
+
+        // Starting from the interior node, the shortened path checks out...
+        let forged = legacy(vec![true], lemma.clone());
+        assert_eq!(forged.version(), 0);
+        assert!(forged.valid_legacy());
+        assert!(!legacy(vec![true], lemma.clone()).valid());
+
+        // ...but no measurement hashes to it.
+        let forged = EvidenceProof::<Blake2s>::new(vec![], interior, vec![true], lemma);
+        assert!(!forged.valid());
+    }
+
+    #[test]
+    fn long_paths_round_trip() {
+        for len in [1usize, 7, 8, 9, 256, 512].iter().copied() {
+            let path: Vec<bool> = (0..len).map(|i| i % 3 == 0).collect();
+            let bytes = EvidencePath::from(path.clone()).to_bytes();
+            assert_eq!(bytes.len(), 2 + (len + 7) / 8);
+            assert_eq!(EvidencePath::from(bytes.as_slice()).path(), path);
+        }
+    }
+
+    fn sparse_tree() -> SparseEvidenceTree<Blake2s> {
+        let mut tree = SparseEvidenceTree::new();
+        let mut rng = rng();
+        for (i, claim) in ["firmware", "bootloader", "secure boot", "rollback"]
+            .iter()
+            .enumerate()
+        {
+            tree.insert(claim.as_bytes(), measurement(i as u64), &mut rng);
+        }
+        tree
+    }
+
+    fn round_trip(proof: EvidenceProof<Blake2s>) -> EvidenceProof<Blake2s> {
+        EvidenceProof::from_bytes(&Vec::from(proof)).unwrap()
+    }
+
+    #[test]
+    fn sparse_membership() {
+        let tree = sparse_tree();
+        for claim in ["firmware", "rollback"].iter() {
+            let proof = round_trip(tree.prove(claim.as_bytes()));
+            assert_eq!(proof.version(), SPARSE_MEMBERSHIP);
+            assert_eq!(
+                proof.key(),
+                Some(claim_key::<Blake2s>(claim.as_bytes()).as_slice())
+            );
+            assert_eq!(proof.root(), Some(tree.root().as_slice()));
+            assert!(proof.measurement().is_some());
+            assert!(proof.valid());
+        }
+    }
+
+    #[test]
+    fn sparse_absence() {
+        let mut tree = sparse_tree();
+        let proof = round_trip(tree.prove(b"debug enabled"));
+        assert_eq!(proof.version(), SPARSE_ABSENCE);
+        assert_eq!(proof.measurement(), None);
+        let old_root = tree.root();
+        assert_eq!(proof.root(), Some(old_root.as_slice()));
+        assert!(proof.valid());
+
+        // Once the claim is in the tree, the old proof is for another root
+        // and no proof of absence checks out against the new one.
+        tree.insert(b"debug enabled", measurement(9), &mut rng());
+        assert_ne!(old_root, tree.root());
+        let mut bytes = Vec::from(tree.prove(b"debug enabled"));
+        bytes[0] = SPARSE_ABSENCE;
+        let forged = EvidenceProof::<Blake2s>::from_bytes(&bytes).unwrap();
+        assert_eq!(forged.version(), SPARSE_ABSENCE);
+        assert!(!forged.valid());
+    }
+
+    #[test]
+    fn malformed_proofs_are_rejected() {
+        let tree = sparse_tree();
+        let evidence = (0..8).map(measurement);
+        let proofs = vec![
+            Vec::from(get_evidence::<Blake2s, _, _>(evidence, &mut rng())),
+            Vec::from(tree.prove(b"firmware")),
+            Vec::from(tree.prove(b"debug enabled")),
+        ];
+        for bytes in proofs {
+            for length in 0..bytes.len() {
+                assert!(EvidenceProof::<Blake2s>::from_bytes(&bytes[..length]).is_none());
+            }
+            let mut longer = bytes.clone();
+            longer.push(0);
+            assert!(EvidenceProof::<Blake2s>::from_bytes(&longer).is_none());
+
+            // Whatever a corrupted byte turns the proof into, checking it
+            // must not panic. It may still check out, e.g. as the absence of
+            // another key below the same empty subtree.
+            for i in 0..bytes.len() {
+                let mut corrupted = bytes.clone();
+                corrupted[i] ^= 0xff;
+                if let Some(proof) = EvidenceProof::<Blake2s>::from_bytes(&corrupted) {
+                    let _ = proof.valid();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_sparse_tree() {
+        let tree = SparseEvidenceTree::<Blake2s>::new();
+        assert_eq!(
+            tree.root(),
+            empty_subtrees(&mut algorithm())[Blake2s::HASHLEN * 8]
+        );
+        assert!(round_trip(tree.prove(b"firmware")).valid());
+    }
+
+    #[test]
+    fn perfect_range_matches_merkle_tree() {
+        assert_eq!(log(8).root(), Some(tree(8).root()));
+    }
+
+    #[test]
+    fn updates_extend_the_previous_root() {
+        let mut log = log(0);
+        assert!(log.report().is_none());
+        let mut previous: Option<(u64, Vec<u8>)> = None;
+        for (round, appended) in [1, 2, 5, 0, 8, 13, 1].iter().enumerate() {
+            for i in 0..*appended {
+                log.append(&measurement(100 * round as u64 + i));
+            }
+            let update = log.report().unwrap();
+            let update = EvidenceUpdate::<Blake2s>::from_bytes(&Vec::from(update)).unwrap();
+            let expected = previous
+                .as_ref()
+                .map(|(size, root)| (*size, root.as_slice()));
+            assert!(update.valid(expected));
+            assert_eq!(Some(update.root().to_vec()), log.root());
+            previous = Some((update.size(), update.root().to_vec()));
+        }
+    }
+
+    #[test]
+    fn appended_measurements_are_folded_into_subtrees() {
+        let mut log = log(3);
+        let old = log.report().unwrap();
+        for i in 3..1003 {
+            log.append(&measurement(i));
+        }
+        let heights: Vec<u32> = log.pending.iter().map(|(height, _)| *height).collect();
+        assert_eq!(heights, subtree_heights(3, 1003));
+        assert!(log.report().unwrap().valid(Some((old.size(), old.root()))));
+    }
+
+    #[test]
+    fn rewritten_history_is_rejected() {
+        let mut honest = log(5);
+        let old = honest.report().unwrap();
+        let previous = Some((old.size(), old.root()));
+
+        let mut rewritten = EvidenceLog::<Blake2s>::new();
+        rewritten.append(&measurement(42));
+        for i in 1..5 {
+            rewritten.append(&measurement(i));
+        }
+        rewritten.report();
+        rewritten.append(&measurement(5));
+        assert!(!rewritten.report().unwrap().valid(previous));
+
+        honest.append(&measurement(5));
+        let update = honest.report().unwrap();
+        assert!(update.valid(previous));
+        assert!(!update.valid(Some((4, old.root()))));
+        assert!(!update.valid(None));
+
+        let mut bytes = Vec::from(update);
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let tampered = EvidenceUpdate::<Blake2s>::from_bytes(&bytes).unwrap();
+        assert!(!tampered.valid(previous));
+        assert!(EvidenceUpdate::<Blake2s>::from_bytes(&bytes[..last]).is_none());
+    }
+
+    #[test]
+    fn restored_logs_extend_the_reported_root() {
+        let mut log = log(11);
+        let old = log.report().unwrap();
+        log.append(&measurement(11));
+        let bytes = Vec::from(&log);
+
+        let mut restored = EvidenceLog::<Blake2s>::from_bytes(&bytes).unwrap();
+        assert_eq!(restored.len(), 11);
+        assert_eq!(Some(old.root().to_vec()), restored.root());
+        restored.append(&measurement(12));
+        let update = restored.report().unwrap();
+        assert!(update.valid(Some((old.size(), old.root()))));
+
+        assert!(EvidenceLog::<Blake2s>::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+        assert!(EvidenceLog::<Blake2s>::from_bytes(&bytes[..7]).is_none());
+        let empty = Vec::from(&EvidenceLog::<Blake2s>::new());
+        let restored = EvidenceLog::<Blake2s>::from_bytes(&empty).unwrap();
+        assert!(restored.is_empty());
+    }
 }
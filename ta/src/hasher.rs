@@ -1,54 +1,272 @@
-use optee_utee::{AlgorithmId, Digest};
-use proto::HASHLEN;
+use crate::tee::{
+    AlgorithmId, AttributeId, AttributeMemref, Digest, Mac, Result, TransientObject,
+    TransientObjectType,
+};
 
-use std::convert::TryInto;
 use std::default::Default;
 use std::hash::Hasher;
 
+use blake2::Digest as _;
 use merkle_light::hash::Algorithm;
+use zeroize::Zeroizing;
 
-pub struct PatatHashAlgorithm {
-    op: Digest,
+/// A hash function as described in [section 4.3 of the Noise
+/// specification](https://noiseprotocol.org/noise.html#hash-functions).
+///
+/// The same hash function is used for `HASH`, `HMAC` and `HKDF` in the
+/// handshake and for the nodes of the evidence tree.
+pub trait NoiseHash: Default {
+    /// Name of the hash function as used in the Noise protocol name.
+    const NAME: &'static str;
+    /// Number of bytes in a hash output.
+    const HASHLEN: usize;
+    /// Number of bytes the hash function uses internally to divide its input.
+    const BLOCKLEN: usize;
+
+    fn input(&mut self, data: &[u8]);
+
+    /// Write the hash of all input so far into `out` and start over.
+    fn result(&mut self, out: &mut [u8]);
+
+    /// HMAC as specified in [IETF RFC 2104](https://www.rfc-editor.org/rfc/rfc2104).
+    ///
+    /// Noise only ever uses keys of `HASHLEN` bytes, so keys longer than
+    /// `BLOCKLEN` are not supported. There must be no input pending.
+    fn hmac(&mut self, key: &[u8], data: &[u8], out: &mut [u8]) {
+        self.try_hmac(key, data, out).unwrap();
+    }
+
+    /// `hmac`, but returning the error if the TEE fails to compute it.
+    fn try_hmac(&mut self, key: &[u8], data: &[u8], out: &mut [u8]) -> Result<()> {
+        assert!(key.len() <= Self::BLOCKLEN);
+
+        let mut ipad = Zeroizing::new(vec![0x36u8; Self::BLOCKLEN]);
+        let mut opad = Zeroizing::new(vec![0x5cu8; Self::BLOCKLEN]);
+        for (i, byte) in key.iter().enumerate() {
+            ipad[i] ^= byte;
+            opad[i] ^= byte;
+        }
+
+        let mut inner_hash = Zeroizing::new(vec![0u8; Self::HASHLEN]);
+        self.input(&ipad);
+        self.input(data);
+        self.result(&mut inner_hash);
+
+        self.input(&opad);
+        self.input(&inner_hash);
+        self.result(out);
+        Ok(())
+    }
+}
+
+/// An OP-TEE HMAC operation with its key object.
+///
+/// Allocating them costs far more than computing a MAC over the few bytes
+/// HKDF passes in, so they are allocated once and only rekeyed for every MAC.
+struct TeeHmac {
+    mac: Mac,
+    key: TransientObject,
+    max_key_len: usize,
 }
 
-impl PatatHashAlgorithm {
-    pub fn new() -> PatatHashAlgorithm {
-        PatatHashAlgorithm {
-            op: Digest::allocate(AlgorithmId::Sha256).unwrap(),
+impl TeeHmac {
+    fn allocate(
+        algorithm: AlgorithmId,
+        object_type: TransientObjectType,
+        max_key_len: usize,
+    ) -> Self {
+        TeeHmac {
+            mac: Mac::allocate(algorithm, max_key_len * 8).unwrap(),
+            key: TransientObject::allocate(object_type, max_key_len * 8).unwrap(),
+            max_key_len,
         }
     }
+
+    fn compute(&mut self, key: &[u8], data: &[u8], out: &mut [u8]) -> Result<()> {
+        assert!(key.len() <= self.max_key_len);
+
+        let attr = AttributeMemref::from_ref(AttributeId::SecretValue, key);
+        self.key.populate(&[attr.into()])?;
+        let keyed = self.mac.set_key(&self.key);
+        // The operation keeps its own copy of the key.
+        self.key.reset();
+        keyed?;
+        self.mac.init(&[0u8; 0]);
+        self.mac.compute_final(data, out)?;
+        Ok(())
+    }
+}
+
+/// SHA-256 in OP-TEE. The digest and HMAC operations are allocated on first
+/// use and then reused, so keep one instance around rather than creating one
+/// per hash.
+#[derive(Default)]
+pub struct Sha256 {
+    op: Option<Digest>,
+    mac: Option<TeeHmac>,
+}
+
+impl Sha256 {
+    fn op(&mut self) -> &Digest {
+        self.op
+            .get_or_insert_with(|| Digest::allocate(AlgorithmId::Sha256).unwrap())
+    }
+}
+
+impl NoiseHash for Sha256 {
+    const NAME: &'static str = "SHA256";
+    const HASHLEN: usize = 32;
+    const BLOCKLEN: usize = 64;
+
+    #[inline]
+    fn input(&mut self, data: &[u8]) {
+        self.op().update(data);
+    }
+
+    #[inline]
+    fn result(&mut self, out: &mut [u8]) {
+        self.op().do_final(&[], out).unwrap();
+    }
+
+    fn try_hmac(&mut self, key: &[u8], data: &[u8], out: &mut [u8]) -> Result<()> {
+        self.mac
+            .get_or_insert_with(|| {
+                TeeHmac::allocate(
+                    AlgorithmId::HmacSha256,
+                    TransientObjectType::HmacSha256,
+                    Self::BLOCKLEN,
+                )
+            })
+            .compute(key, data, out)
+    }
+}
+
+/// SHA-512 in OP-TEE, with operations reused like those of `Sha256`.
+#[derive(Default)]
+pub struct Sha512 {
+    op: Option<Digest>,
+    mac: Option<TeeHmac>,
+}
+
+impl Sha512 {
+    fn op(&mut self) -> &Digest {
+        self.op
+            .get_or_insert_with(|| Digest::allocate(AlgorithmId::Sha512).unwrap())
+    }
 }
 
-impl Hasher for PatatHashAlgorithm {
+impl NoiseHash for Sha512 {
+    const NAME: &'static str = "SHA512";
+    const HASHLEN: usize = 64;
+    const BLOCKLEN: usize = 128;
+
+    #[inline]
+    fn input(&mut self, data: &[u8]) {
+        self.op().update(data);
+    }
+
+    #[inline]
+    fn result(&mut self, out: &mut [u8]) {
+        self.op().do_final(&[], out).unwrap();
+    }
+
+    fn try_hmac(&mut self, key: &[u8], data: &[u8], out: &mut [u8]) -> Result<()> {
+        self.mac
+            .get_or_insert_with(|| {
+                TeeHmac::allocate(
+                    AlgorithmId::HmacSha512,
+                    TransientObjectType::HmacSha512,
+                    Self::BLOCKLEN,
+                )
+            })
+            .compute(key, data, out)
+    }
+}
+
+/// OP-TEE has no BLAKE2 support, so this one is computed in software.
+#[derive(Default)]
+pub struct Blake2s {
+    state: blake2::Blake2s256,
+}
+
+impl NoiseHash for Blake2s {
+    const NAME: &'static str = "BLAKE2s";
+    const HASHLEN: usize = 32;
+    const BLOCKLEN: usize = 64;
+
+    #[inline]
+    fn input(&mut self, data: &[u8]) {
+        self.state.update(data);
+    }
+
+    #[inline]
+    fn result(&mut self, out: &mut [u8]) {
+        let state = std::mem::take(&mut self.state);
+        out.copy_from_slice(&state.finalize());
+    }
+}
+
+/// Prefix of the hash of a leaf of the evidence tree, as in [section 2.1 of
+/// RFC 6962](https://www.rfc-editor.org/rfc/rfc6962#section-2.1).
+pub const LEAF_PREFIX: u8 = 0x00;
+/// Prefix of the hash of an interior node of the evidence tree.
+pub const NODE_PREFIX: u8 = 0x01;
+
+pub struct PatatHashAlgorithm<H: NoiseHash = Sha256> {
+    hash: H,
+}
+
+impl<H: NoiseHash> PatatHashAlgorithm<H> {
+    pub fn new() -> PatatHashAlgorithm<H> {
+        PatatHashAlgorithm { hash: H::default() }
+    }
+}
+
+impl<H: NoiseHash> Hasher for PatatHashAlgorithm<H> {
     #[inline]
     fn write(&mut self, msg: &[u8]) {
-        self.op.update(msg);
+        self.hash.input(msg);
     }
 
     #[inline]
     fn finish(&self) -> u64 {
-        let mut hash = [0u8; HASHLEN];
-        let length = self.op.do_final(&[], &mut hash).unwrap();
-        length.try_into().unwrap()
+        H::HASHLEN as u64
     }
 }
 
-impl Default for PatatHashAlgorithm {
-    fn default() -> PatatHashAlgorithm {
+impl<H: NoiseHash> Default for PatatHashAlgorithm<H> {
+    fn default() -> PatatHashAlgorithm<H> {
         PatatHashAlgorithm::new()
     }
 }
 
-impl Algorithm<[u8; HASHLEN]> for PatatHashAlgorithm {
+impl<H: NoiseHash> Algorithm<Vec<u8>> for PatatHashAlgorithm<H> {
     #[inline]
-    fn hash(&mut self) -> [u8; HASHLEN] {
-        let mut h = [0u8; HASHLEN];
-        self.op.do_final(&[], &mut h);
+    fn hash(&mut self) -> Vec<u8> {
+        let mut h = vec![0u8; H::HASHLEN];
+        self.hash.result(&mut h);
         h
     }
 
+    /// `result` already starts over, so the hasher, and with it the TEE
+    /// operation, is kept for the next hash.
+    #[inline]
+    fn reset(&mut self) {}
+
+    /// Spelled out rather than left to `merkle_light`, so that a leaf can
+    /// never hash to the same value as an interior node.
+    #[inline]
+    fn leaf(&mut self, leaf: Vec<u8>) -> Vec<u8> {
+        self.write(&[LEAF_PREFIX]);
+        self.write(&leaf);
+        self.hash()
+    }
+
     #[inline]
-    fn reset(&mut self) {
-        self.op = Digest::allocate(AlgorithmId::Sha256).unwrap();
+    fn node(&mut self, left: Vec<u8>, right: Vec<u8>) -> Vec<u8> {
+        self.write(&[NODE_PREFIX]);
+        self.write(&left);
+        self.write(&right);
+        self.hash()
     }
 }
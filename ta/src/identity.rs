@@ -0,0 +1,128 @@
+// TEE
+use crate::tee::{DataFlag, ObjectStorageConstants, PersistentObject};
+use crate::tee::{Error, ErrorKind, Result};
+
+// Protocol
+use proto::DHLEN;
+
+// libraries
+use std::convert::TryInto;
+use zeroize::Zeroizing;
+
+// TA Code
+use crate::certificate::CertificateChain;
+use crate::dh::{self, Dh, Keypair};
+use crate::random::PatatRng;
+use crate::x25519::StaticSecret;
+
+/// Load the static key pair of the TA for `dh` from secure storage,
+/// generating and storing one on first use.
+///
+/// Every DH function has its own identity, so the verifier can pin the
+/// public key that `Command::ExportKey` returns.
+pub fn load_or_create(dh: &dyn Dh) -> Result<Keypair> {
+    let secret = load_or_store(&format!("patat-identity-{}", dh.name()), || {
+        Zeroizing::new(dh.generate_keypair(&mut PatatRng).secret().to_vec())
+    })?;
+    Ok(dh.keypair_from_secret(&secret))
+}
+
+/// The secret of the X25519 identity, which the TA also signs evidence with
+/// using XEdDSA.
+///
+/// A verifier checks the signatures with the key that `Command::ExportKey`
+/// returns for X25519, so there is no second key to enroll.
+pub fn load_or_create_signing_secret() -> Result<StaticSecret> {
+    let keypair = load_or_create(dh::x25519().as_ref())?;
+    let secret: Zeroizing<[u8; DHLEN]> = match keypair.secret().try_into() {
+        Ok(secret) => Zeroizing::new(secret),
+        Err(_) => return Err(Error::new(ErrorKind::Generic)),
+    };
+    Ok(StaticSecret::from(*secret))
+}
+
+/// Store the certificate chain for the static key of `dh`, replacing any
+/// stored before.
+///
+/// Fails with `ErrorKind::BadParameters` if the chain does not parse or its
+/// first certificate is not for the static key of `dh`, so the TA never
+/// sends a chain that cannot be for it. The signatures are left to the
+/// verifier, which has the trust anchors.
+pub fn store_certificates(dh: &dyn Dh, chain: &[u8]) -> Result<()> {
+    let parsed =
+        CertificateChain::from_bytes(chain).map_err(|_| Error::new(ErrorKind::BadParameters))?;
+    let device = &parsed.certificates()[0];
+    let keypair = load_or_create(dh)?;
+    if device.key_type() != dh.name() || device.public_key() != keypair.public() {
+        return Err(Error::new(ErrorKind::BadParameters));
+    }
+
+    PersistentObject::create(
+        ObjectStorageConstants::Private,
+        &mut certificates_id(dh),
+        DataFlag::ACCESS_READ
+            | DataFlag::ACCESS_WRITE
+            | DataFlag::ACCESS_WRITE_META
+            | DataFlag::OVERWRITE,
+        None,
+        chain,
+    )?;
+    Ok(())
+}
+
+/// The certificate chain stored for the static key of `dh`, empty if there
+/// is none.
+pub fn load_certificates(dh: &dyn Dh) -> Result<Vec<u8>> {
+    match PersistentObject::open(
+        ObjectStorageConstants::Private,
+        &mut certificates_id(dh),
+        DataFlag::ACCESS_READ,
+    ) {
+        Ok(object) => {
+            let mut chain = vec![0u8; object.info()?.data_size()];
+            let read = object.read(&mut chain)? as usize;
+            chain.truncate(read);
+            Ok(chain)
+        }
+        Err(e) if e.kind() == ErrorKind::ItemNotFound => Ok(vec![]),
+        Err(e) => Err(e),
+    }
+}
+
+fn certificates_id(dh: &dyn Dh) -> Vec<u8> {
+    format!("patat-certificates-{}", dh.name()).into_bytes()
+}
+
+/// Read the secret stored under `id`, or store the one `create` returns if
+/// there is none yet.
+fn load_or_store(
+    id: &str,
+    create: impl FnOnce() -> Zeroizing<Vec<u8>>,
+) -> Result<Zeroizing<Vec<u8>>> {
+    let mut object_id = id.as_bytes().to_vec();
+
+    match PersistentObject::open(
+        ObjectStorageConstants::Private,
+        &mut object_id,
+        DataFlag::ACCESS_READ,
+    ) {
+        Ok(object) => {
+            let mut secret = Zeroizing::new(vec![0u8; object.info()?.data_size()]);
+            let read = object.read(&mut secret)? as usize;
+            secret.truncate(read);
+            Ok(secret)
+        }
+        Err(e) if e.kind() == ErrorKind::ItemNotFound => {
+            let secret = create();
+            PersistentObject::create(
+                ObjectStorageConstants::Private,
+                &mut object_id,
+                DataFlag::ACCESS_READ | DataFlag::ACCESS_WRITE,
+                None,
+                &secret,
+            )?;
+            Ok(secret)
+        }
+        Err(e) => Err(e),
+    }
+}
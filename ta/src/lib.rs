@@ -1,6 +1,27 @@
+pub mod certificate;
+pub mod dh;
+pub mod ed25519;
 pub mod evidence;
 pub mod hasher;
+pub mod identity;
+pub mod log_store;
 pub mod noise;
 pub mod patat_participant;
 pub mod random;
+pub mod selftest;
+pub mod signed_evidence;
+#[cfg(feature = "sim")]
+mod sim;
+mod tee;
+pub mod ticket;
+pub mod timing;
 pub mod x25519;
+
+#[cfg(test)]
+mod noise_vectors;
+#[cfg(all(test, feature = "sim"))]
+mod sim_attestation;
+#[cfg(test)]
+mod snow_interop;
+#[cfg(test)]
+mod test_support;
@@ -0,0 +1,52 @@
+// TEE
+use crate::tee::{DataFlag, ObjectStorageConstants, PersistentObject};
+use crate::tee::{Error, ErrorKind, Result};
+
+// TA Code
+use crate::evidence::EvidenceLog;
+use crate::hasher::NoiseHash;
+
+/// Every hash function has its own log.
+fn object_id<H: NoiseHash>() -> Vec<u8> {
+    format!("patat-evidence-log-{}", H::NAME).into_bytes()
+}
+
+/// Load the evidence log for `H` from secure storage, or an empty log if none
+/// was stored yet.
+///
+/// The log outlives sessions and restarts of the TA, so a verifier that
+/// keeps the size and root of the last update it accepted can check every
+/// later update against them. Fails with `ErrorKind::Generic` if the stored
+/// log cannot be parsed.
+pub fn load<H: NoiseHash>() -> Result<EvidenceLog<H>> {
+    let object = match PersistentObject::open(
+        ObjectStorageConstants::Private,
+        &mut object_id::<H>(),
+        DataFlag::ACCESS_READ,
+    ) {
+        Ok(object) => object,
+        Err(e) if e.kind() == ErrorKind::ItemNotFound => return Ok(EvidenceLog::new()),
+        Err(e) => return Err(e),
+    };
+    let mut bytes = vec![0u8; object.info()?.data_size()];
+    let read = object.read(&mut bytes)? as usize;
+    EvidenceLog::from_bytes(&bytes[..read]).ok_or_else(|| Error::new(ErrorKind::Generic))
+}
+
+/// Store `log` at the state of its last report, replacing the stored one.
+///
+/// Store the log before sending the update of that report, so the TA never
+/// reports a root it could lose.
+pub fn store<H: NoiseHash>(log: &EvidenceLog<H>) -> Result<()> {
+    PersistentObject::create(
+        ObjectStorageConstants::Private,
+        &mut object_id::<H>(),
+        DataFlag::ACCESS_READ
+            | DataFlag::ACCESS_WRITE
+            | DataFlag::ACCESS_WRITE_META
+            | DataFlag::OVERWRITE,
+        None,
+        &Vec::from(log),
+    )?;
+    Ok(())
+}
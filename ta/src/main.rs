@@ -22,59 +22,413 @@ use optee_utee::{
     ta_close_session, ta_create, ta_destroy, ta_invoke_command, ta_open_session, trace_println,
 };
 use optee_utee::{Error, ErrorKind, Parameters, Result};
-use proto::Command;
+use proto::{
+    Command, DhFunction, HashFunction, Phase, Verifier, EVIDENCE_NONCE_LEN,
+    MAX_CERTIFICATE_CHAIN_LEN, MAX_MESSAGE_LEN, TIMINGS_LEN,
+};
 
 // std
-use std::convert::TryInto;
+use std::io;
 
 // libraries
 use rand_core::RngCore;
 
 // TA Code
+use ta::dh::{self, Dh, Ffdhe2048};
 use ta::evidence::get_evidence;
-use ta::patat_participant::PatatTA;
+use ta::hasher::{Blake2s, NoiseHash, Sha256, Sha512};
+use ta::identity;
+use ta::log_store;
+use ta::patat_participant::{ConnectError, PatatTA, ResumeError};
 use ta::random::PatatRng;
-use ta::x25519::{PublicKey, StaticSecret};
-
-fn simulate_evidence_fetching(iterations: u32) -> Vec<[u8; 32]> {
-    let mut return_value = vec![];
-    let mut rng = PatatRng {};
+use ta::selftest;
+use ta::signed_evidence::SignedEvidence;
+use ta::ticket::{self, Ticket};
+use ta::timing::{self, PhaseTimer};
+use ta::x25519::StaticSecret;
 
-    for _ in 0..iterations {
-	let mut data1 = [0u8; 32];
-	rng.fill_bytes(&mut data1);
+fn simulate_evidence_fetching<H: NoiseHash>(iterations: u32) -> impl Iterator<Item = Vec<u8>> {
+    (0..iterations).flat_map(|_| {
+        let mut data1 = vec![0u8; H::HASHLEN];
+        PatatRng.fill_bytes(&mut data1);
 
-	let version = [0u8; 32];
-	let manufacturer_hash = [0u8; 32];
-	let hardware_revision = [0u8; 32];
-	let file_hash = [0u8; 32];
+        let version = vec![0u8; H::HASHLEN];
+        let manufacturer_hash = vec![0u8; H::HASHLEN];
+        let hardware_revision = vec![0u8; H::HASHLEN];
+        let file_hash = vec![0u8; H::HASHLEN];
 
-	let mut iteration = vec![
+        vec![
             data1,
             version,
             manufacturer_hash,
             hardware_revision,
             file_hash,
-	];
+        ]
+    })
+}
+
+fn dh_function(value: u32) -> Result<Box<dyn Dh>> {
+    match DhFunction::from(value) {
+        DhFunction::Curve25519 => Ok(dh::x25519()),
+        DhFunction::Ffdhe2048 => Ok(Box::new(Ffdhe2048)),
+        DhFunction::Unknown => Err(Error::new(ErrorKind::BadParameters)),
+    }
+}
+
+/// An attested channel, for the hash function it was set up with.
+enum Channel {
+    Sha256(PatatTA<Sha256>),
+    Sha512(PatatTA<Sha512>),
+    Blake2s(PatatTA<Blake2s>),
+}
+
+/// What the channel of a session was set up with, which identifies the
+/// tickets for resuming it.
+struct Peer {
+    verifier: Verifier,
+    /// The `DhFunction`.
+    dh: u32,
+}
+
+/// State of a client session, from `open_session` until `close_session`.
+#[derive(Default)]
+struct Session {
+    channel: Option<(Channel, Peer)>,
+}
+
+fn communication_error(e: io::Error) -> Error {
+    trace_println!("[-] Attested channel failed: {}", e);
+    Error::new(ErrorKind::Communication)
+}
 
-	return_value.append(&mut iteration);
+/// The number of features to build evidence from; the evidence tree needs at
+/// least one leaf.
+fn feature_count(value: u32) -> Result<u32> {
+    match value {
+        0 => Err(Error::new(ErrorKind::BadParameters)),
+        features => Ok(features),
     }
-    return_value
 }
 
-fn attest(params: &mut Parameters) {
-    let ta_secret = StaticSecret::new(PatatRng);
-    let key_bytes: [u8; 32] = "very-secure-password-for-frieten"
-        .as_bytes()
-        .try_into()
-        .unwrap();
-    let server_secret = StaticSecret::from(key_bytes);
-    let pubkey = PublicKey::from(&server_secret);
+/// `Command::RunAttested`, or `Command::RunResumed` if `resumed`.
+///
+/// All parameters are checked before the verifier is contacted.
+fn attest(session: &mut Session, params: &mut Parameters, resumed: bool) -> Result<()> {
+    let values = unsafe { params.0.as_value()? };
+    let features = feature_count(unsafe { params.1.as_value()? }.a())?;
+    let dh = dh_function(values.b())?;
+    let hash = HashFunction::from(values.a());
+    if let HashFunction::Unknown = hash {
+        return Err(Error::new(ErrorKind::BadParameters));
+    }
+    let mut timings = unsafe { params.2.as_memref()? };
+    if timings.buffer().len() < TIMINGS_LEN {
+        return Err(Error::new(ErrorKind::ShortBuffer));
+    }
+    let mut verifier_bytes = unsafe { params.3.as_memref()? };
+    let mut verifier = match Verifier::from_bytes(verifier_bytes.buffer()) {
+        Some(verifier) => verifier,
+        None => return Err(Error::new(ErrorKind::BadFormat)),
+    };
+
+    let mut timer = PhaseTimer::start();
+    if verifier.public_key.is_empty() {
+        let server_keypair = dh.keypair_from_secret("very-secure-password-for-frieten".as_bytes());
+        verifier.public_key = server_keypair.public().to_vec();
+    }
+    let peer = Peer {
+        verifier: verifier.clone(),
+        dh: values.b(),
+    };
+
+    // Only one connection to a verifier is open per session.
+    session.channel = None;
+    let timer = &mut timer;
+    let channel = match hash {
+        HashFunction::Sha256 => {
+            attest_with(dh, features, verifier, resumed, timer, Channel::Sha256)?
+        }
+        HashFunction::Sha512 => {
+            attest_with(dh, features, verifier, resumed, timer, Channel::Sha512)?
+        }
+        HashFunction::Blake2s => {
+            attest_with(dh, features, verifier, resumed, timer, Channel::Blake2s)?
+        }
+        HashFunction::Unknown => unreachable!(),
+    };
+    session.channel = Some((channel, peer));
+
+    timings.buffer()[..TIMINGS_LEN].copy_from_slice(&timer.to_bytes());
+    timings.set_updated_size(TIMINGS_LEN);
+    Ok(())
+}
 
-    let mut ta = PatatTA::connect(ta_secret, pubkey);
+fn attest_with<H: NoiseHash>(
+    dh: Box<dyn Dh>,
+    features: u32,
+    verifier: Verifier,
+    resumed: bool,
+    timer: &mut PhaseTimer,
+    channel: fn(PatatTA<H>) -> Channel,
+) -> Result<Channel> {
+    let mut ta = if resumed {
+        resume(dh, &verifier, timer)?
+    } else {
+        connect(dh, verifier, timer)?
+    };
 
-    let evidence = get_evidence(simulate_evidence_fetching(15));
-    ta.send_evidence(evidence);
+    let evidence = get_evidence(simulate_evidence_fetching::<H>(features), &mut PatatRng);
+    timer.record(Phase::EvidenceTree);
+    ta.send_evidence(evidence).map_err(communication_error)?;
+    timer.record(Phase::EvidenceSend);
+    Ok(channel(ta))
+}
+
+fn connect<H: NoiseHash>(
+    dh: Box<dyn Dh>,
+    verifier: Verifier,
+    timer: &mut PhaseTimer,
+) -> Result<PatatTA<H>> {
+    let ta_keypair = identity::load_or_create(dh.as_ref())?;
+    let certificates = identity::load_certificates(dh.as_ref())?;
+    timer.record(Phase::KeyGeneration);
+
+    PatatTA::connect(dh, ta_keypair, &certificates, verifier, timer).map_err(|e| match e {
+        ConnectError::Io(e) => communication_error(e),
+        ConnectError::Noise(e) => {
+            trace_println!("[-] Handshake aborted: {:?}", e);
+            Error::new(ErrorKind::Security)
+        }
+    })
+}
+
+fn resume<H: NoiseHash>(
+    dh: Box<dyn Dh>,
+    verifier: &Verifier,
+    timer: &mut PhaseTimer,
+) -> Result<PatatTA<H>> {
+    let ticket = ticket::take::<H>(verifier, dh.as_ref())?;
+    timer.record(Phase::KeyGeneration);
+
+    PatatTA::resume(dh, &ticket, verifier, timer).map_err(|e| match e {
+        ResumeError::Connect(e) => communication_error(e),
+        e => {
+            trace_println!("[-] Resumption failed: {:?}", e);
+            Error::new(ErrorKind::Security)
+        }
+    })
+}
+
+/// Run `send` on the channel of `session`, closing the channel if the
+/// connection to the verifier fails.
+fn with_channel<T>(
+    session: &mut Session,
+    send: impl FnOnce(&mut Channel) -> Result<T>,
+) -> Result<T> {
+    let channel = match session.channel.as_mut() {
+        Some((channel, _)) => channel,
+        None => return Err(Error::new(ErrorKind::BadState)),
+    };
+    let result = send(channel);
+    if let Err(e) = &result {
+        if e.kind() == ErrorKind::Communication {
+            session.channel = None;
+        }
+    }
+    result
+}
+
+fn evidence_round<H: NoiseHash>(ta: &mut PatatTA<H>, features: u32) -> Result<()> {
+    ta.send_evidence(get_evidence(
+        simulate_evidence_fetching::<H>(features),
+        &mut PatatRng,
+    ))
+    .map_err(communication_error)
+}
+
+fn send_evidence(session: &mut Session, params: &mut Parameters) -> Result<()> {
+    let features = feature_count(unsafe { params.0.as_value()? }.a())?;
+    with_channel(session, |channel| match channel {
+        Channel::Sha256(ta) => evidence_round(ta, features),
+        Channel::Sha512(ta) => evidence_round(ta, features),
+        Channel::Blake2s(ta) => evidence_round(ta, features),
+    })
+}
+
+/// Append to the stored log for `H` and report it, storing the log before
+/// the update is sent.
+fn log_round<H: NoiseHash>(ta: &mut PatatTA<H>, features: u32) -> Result<u64> {
+    let mut log = log_store::load::<H>()?;
+    for measurement in simulate_evidence_fetching::<H>(features) {
+        log.append(&measurement);
+    }
+    if let Some(update) = log.report() {
+        log_store::store(&log)?;
+        ta.send_evidence_update(update).map_err(communication_error)?;
+    }
+    Ok(log.len())
+}
+
+fn append_evidence(session: &mut Session, params: &mut Parameters) -> Result<()> {
+    let mut values = unsafe { params.0.as_value()? };
+    let features = feature_count(values.a())?;
+    let size = with_channel(session, |channel| match channel {
+        Channel::Sha256(ta) => log_round(ta, features),
+        Channel::Sha512(ta) => log_round(ta, features),
+        Channel::Blake2s(ta) => log_round(ta, features),
+    })?;
+    values.set_b(size as u32);
+    Ok(())
+}
+
+fn exchange<H: NoiseHash>(ta: &mut PatatTA<H>, message: &[u8]) -> Result<Vec<u8>> {
+    ta.send(message).map_err(communication_error)?;
+    ta.receive().map_err(communication_error)
+}
+
+fn send_message(session: &mut Session, params: &mut Parameters) -> Result<()> {
+    let mut message = unsafe { params.0.as_memref()? };
+    let message = message.buffer();
+    if message.len() > MAX_MESSAGE_LEN {
+        return Err(Error::new(ErrorKind::BadParameters));
+    }
+    let reply = with_channel(session, |channel| match channel {
+        Channel::Sha256(ta) => exchange(ta, message),
+        Channel::Sha512(ta) => exchange(ta, message),
+        Channel::Blake2s(ta) => exchange(ta, message),
+    })?;
+
+    let mut output = unsafe { params.1.as_memref()? };
+    if output.buffer().len() < reply.len() {
+        return Err(Error::new(ErrorKind::ShortBuffer));
+    }
+    output.buffer()[..reply.len()].copy_from_slice(&reply);
+    output.set_updated_size(reply.len());
+    Ok(())
+}
+
+fn request_ticket(session: &mut Session, params: &mut Parameters) -> Result<()> {
+    let ticket = with_channel(session, |channel| {
+        match channel {
+            Channel::Sha256(ta) => ta.request_ticket(),
+            Channel::Sha512(ta) => ta.request_ticket(),
+            Channel::Blake2s(ta) => ta.request_ticket(),
+        }
+        .map_err(communication_error)
+    })?;
+    let lifetime = match ticket {
+        Some(ticket) => {
+            store_ticket(session, &ticket)?;
+            ticket.expires().saturating_sub(ticket::now())
+        }
+        None => 0,
+    };
+
+    let mut values = unsafe { params.0.as_value()? };
+    values.set_a(lifetime as u32);
+    Ok(())
+}
+
+fn store_ticket(session: &Session, ticket: &Ticket) -> Result<()> {
+    let (channel, peer) = match session.channel.as_ref() {
+        Some(channel) => channel,
+        None => return Err(Error::new(ErrorKind::BadState)),
+    };
+    let dh = dh_function(peer.dh)?;
+    match channel {
+        Channel::Sha256(_) => ticket::store::<Sha256>(&peer.verifier, dh.as_ref(), ticket),
+        Channel::Sha512(_) => ticket::store::<Sha512>(&peer.verifier, dh.as_ref(), ticket),
+        Channel::Blake2s(_) => ticket::store::<Blake2s>(&peer.verifier, dh.as_ref(), ticket),
+    }
+}
+
+fn report_dh_backend(params: &mut Parameters) -> Result<()> {
+    let mut values = unsafe { params.0.as_value()? };
+    values.set_a(dh::x25519().backend() as u32);
+    Ok(())
+}
+
+fn export_key(params: &mut Parameters) -> Result<()> {
+    let dh = dh_function(unsafe { params.0.as_value()? }.a())?;
+    let keypair = identity::load_or_create(dh.as_ref())?;
+
+    let mut key = unsafe { params.1.as_memref()? };
+    let public = keypair.public();
+    if key.buffer().len() < public.len() {
+        return Err(Error::new(ErrorKind::ShortBuffer));
+    }
+    key.buffer()[..public.len()].copy_from_slice(public);
+    key.set_updated_size(public.len());
+    Ok(())
+}
+
+fn install_certificates(params: &mut Parameters) -> Result<()> {
+    let dh = dh_function(unsafe { params.0.as_value()? }.a())?;
+    let mut chain = unsafe { params.1.as_memref()? };
+    if chain.buffer().len() > MAX_CERTIFICATE_CHAIN_LEN {
+        return Err(Error::new(ErrorKind::BadParameters));
+    }
+    identity::store_certificates(dh.as_ref(), chain.buffer())
+}
+
+fn signed_evidence<H: NoiseHash>(
+    features: u32,
+    nonce: [u8; EVIDENCE_NONCE_LEN],
+    key: &StaticSecret,
+) -> Vec<u8> {
+    let evidence = simulate_evidence_fetching::<H>(features);
+    let proof = get_evidence::<H, _, _>(evidence, &mut PatatRng);
+    SignedEvidence::sign::<Sha512, _>(proof, nonce, ticket::now(), key, &mut PatatRng).into()
+}
+
+fn sign_evidence(params: &mut Parameters) -> Result<()> {
+    let values = unsafe { params.0.as_value()? };
+    let features = feature_count(values.b())?;
+    let mut nonce_buffer = unsafe { params.1.as_memref()? };
+    if nonce_buffer.buffer().len() != EVIDENCE_NONCE_LEN {
+        return Err(Error::new(ErrorKind::BadParameters));
+    }
+    let mut nonce = [0u8; EVIDENCE_NONCE_LEN];
+    nonce.copy_from_slice(nonce_buffer.buffer());
+
+    let key = identity::load_or_create_signing_secret()?;
+    let bundle = match HashFunction::from(values.a()) {
+        HashFunction::Sha256 => signed_evidence::<Sha256>(features, nonce, &key),
+        HashFunction::Sha512 => signed_evidence::<Sha512>(features, nonce, &key),
+        HashFunction::Blake2s => signed_evidence::<Blake2s>(features, nonce, &key),
+        HashFunction::Unknown => return Err(Error::new(ErrorKind::BadParameters)),
+    };
+
+    let mut output = unsafe { params.2.as_memref()? };
+    if output.buffer().len() < bundle.len() {
+        return Err(Error::new(ErrorKind::ShortBuffer));
+    }
+    output.buffer()[..bundle.len()].copy_from_slice(&bundle);
+    output.set_updated_size(bundle.len());
+    Ok(())
+}
+
+fn run_selftest(params: &mut Parameters) -> Result<()> {
+    let mut values = unsafe { params.0.as_value()? };
+    let (passed, failed) = selftest::run();
+    values.set_a(passed);
+    values.set_b(failed);
+    Ok(())
+}
+
+fn bench_hmac(params: &mut Parameters) -> Result<()> {
+    let values = unsafe { params.0.as_value()? };
+    let handshakes = values.b();
+    let (fresh, reused) = match HashFunction::from(values.a()) {
+        HashFunction::Sha256 => timing::bench_handshakes::<Sha256>(handshakes),
+        HashFunction::Sha512 => timing::bench_handshakes::<Sha512>(handshakes),
+        HashFunction::Blake2s => timing::bench_handshakes::<Blake2s>(handshakes),
+        HashFunction::Unknown => return Err(Error::new(ErrorKind::BadParameters)),
+    };
+    let mut result = unsafe { params.1.as_value()? };
+    result.set_a(fresh);
+    result.set_b(reused);
+    Ok(())
 }
 
 #[ta_create]
@@ -84,13 +438,14 @@ fn create() -> Result<()> {
 }
 
 #[ta_open_session]
-fn open_session(_params: &mut Parameters) -> Result<()> {
+fn open_session(_params: &mut Parameters, _session: &mut Session) -> Result<()> {
     trace_println!("[+] TA open session");
     Ok(())
 }
 
+/// Dropping the session closes its attested channel.
 #[ta_close_session]
-fn close_session() {
+fn close_session(_session: &mut Session) {
     trace_println!("[+] TA close session");
 }
 
@@ -100,14 +455,26 @@ fn destroy() {
 }
 
 #[ta_invoke_command]
-fn invoke_command(cmd_id: u32, params: &mut Parameters) -> Result<()> {
+fn invoke_command(session: &mut Session, cmd_id: u32, params: &mut Parameters) -> Result<()> {
     trace_println!("[+] TA invoke command");
     match Command::from(cmd_id) {
-        Command::RunAttested => {
-            attest(params);
-            Ok(())
-        } 
+        Command::RunAttested => attest(session, params, false),
         Command::RunWithoutAttestation => Ok(()),
+        Command::GetDhBackend => report_dh_backend(params),
+        Command::ExportKey => export_key(params),
+        Command::SelfTest => run_selftest(params),
+        Command::SendEvidence => send_evidence(session, params),
+        Command::SendMessage => send_message(session, params),
+        Command::CloseChannel => {
+            session.channel = None;
+            Ok(())
+        }
+        Command::RequestTicket => request_ticket(session, params),
+        Command::RunResumed => attest(session, params, true),
+        Command::AppendEvidence => append_evidence(session, params),
+        Command::BenchHmac => bench_hmac(params),
+        Command::SignEvidence => sign_evidence(params),
+        Command::InstallCertificates => install_certificates(params),
         _ => Err(Error::new(ErrorKind::BadParameters)),
     }
 }
@@ -1,47 +1,50 @@
-use optee_utee::{
-    trace_println, AlgorithmId, AttributeId, AttributeMemref, Mac, TransientObject,
-    TransientObjectType,
-};
-
-use proto::{DHLEN, HASHLEN};
-
 use std::borrow::BorrowMut;
 use std::convert::TryInto;
-use std::hash::Hasher;
 
 use chacha20poly1305::aead::{Aead, NewAead, Payload};
 use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
-use merkle_light::hash::Algorithm;
+use rand_core::{CryptoRng, RngCore};
+use zeroize::{Zeroize, Zeroizing};
 
-use crate::hasher::PatatHashAlgorithm;
+use crate::dh::{Dh, DhError, Keypair};
+use crate::hasher::{NoiseHash, Sha256};
 use crate::random::PatatRng;
-use crate::x25519::{PublicKey, ReusableSecret, StaticSecret};
-
-pub fn hmac(key: &[u8; HASHLEN], data: &[u8]) -> [u8; HASHLEN] {
-    let mut out = [0u8; HASHLEN];
-
-    match Mac::allocate(AlgorithmId::HmacSha256, HASHLEN * 8) {
-        Err(e) => panic!("{}", e),
-        Ok(mac) => {
-            match TransientObject::allocate(TransientObjectType::HmacSha256, key.len() * 8) {
-                Err(e) => panic!("{}", e),
-                Ok(mut key_object) => {
-                    let attr = AttributeMemref::from_ref(AttributeId::SecretValue, key);
-                    key_object.populate(&[attr.into()]).unwrap();
-                    mac.set_key(&key_object).unwrap();
-                }
-            };
-            mac.init(&[0u8; 0]);
-            mac.compute_final(&data, &mut out).unwrap();
-        }
-    };
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum NoiseError {
+    /// One of the DH operations of the handshake was rejected.
+    Dh(DhError),
+    /// A message is too short for the tokens it should carry.
+    Truncated,
+    /// A ciphertext failed authentication.
+    Decrypt,
+}
+
+impl From<DhError> for NoiseError {
+    fn from(value: DhError) -> Self {
+        NoiseError::Dh(value)
+    }
+}
+
+/// HMAC with a fresh `H`, whose operations are allocated for this MAC only.
+pub fn hmac<H: NoiseHash>(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0u8; H::HASHLEN];
+    H::default().hmac(key, data, &mut out);
+    out
+}
+
+pub fn hash<H: NoiseHash>(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0u8; H::HASHLEN];
+    let mut hasher = H::default();
+    hasher.input(data);
+    hasher.result(&mut out);
     out
 }
 
-pub fn hash(data: &[u8]) -> [u8; HASHLEN] {
-    let mut hasher = PatatHashAlgorithm::new();
-    hasher.write(data);
-    hasher.hash()
+/// Cipher keys are 32 bytes, so when `HASHLEN` is 64 only the first 32 bytes
+/// of the HKDF output are used.
+fn cipher_key(hkdf_output: &[u8]) -> [u8; 32] {
+    hkdf_output[..32].try_into().unwrap()
 }
 
 pub struct CipherState {
@@ -62,8 +65,8 @@ impl CipherState {
 
     pub fn encrypt_with_ad(&mut self, ad: &[u8], plaintext: &[u8]) -> Vec<u8> {
         match self.k {
-            Some(k) => {
-                let key = Key::from_slice(&k);
+            Some(ref k) => {
+                let key = Key::from_slice(k);
                 let cipher = ChaCha20Poly1305::new(&key);
                 let nonce = &self.n.to_le_bytes();
 
@@ -88,10 +91,11 @@ impl CipherState {
         }
     }
 
-    pub fn decrypt_with_ad(&mut self, ad: &[u8], ciphertext: &[u8]) -> Vec<u8> {
+    /// Decrypt `ciphertext`; the nonce only advances if it authenticates.
+    pub fn decrypt_with_ad(&mut self, ad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, NoiseError> {
         match self.k {
-            Some(k) => {
-                let key = Key::from_slice(&k);
+            Some(ref k) => {
+                let key = Key::from_slice(k);
                 let cipher = ChaCha20Poly1305::new(&key);
 
                 // Bit awkward, but must do it, because chacha needs to nonce to be 12 bits
@@ -104,56 +108,86 @@ impl CipherState {
                     msg: ciphertext,
                     aad: ad,
                 };
-                let plaintext = cipher.decrypt(&nonce, payload).unwrap();
+                let plaintext = cipher
+                    .decrypt(&nonce, payload)
+                    .map_err(|_| NoiseError::Decrypt)?;
                 self.n += 1;
-                plaintext
+                Ok(plaintext)
             }
             None => {
                 let mut ret = vec![];
                 ret.extend_from_slice(ciphertext);
                 self.n += 1;
-                ret
+                Ok(ret)
             }
         }
     }
 }
 
-pub struct SymmetricState {
+impl Drop for CipherState {
+    fn drop(&mut self) {
+        if let Some(k) = self.k.as_mut() {
+            k.zeroize();
+        }
+    }
+}
+
+pub struct SymmetricState<H: NoiseHash = Sha256> {
     cipher_state: CipherState,
-    ck: [u8; 32],
-    h: [u8; HASHLEN],
+    ck: Zeroizing<Vec<u8>>,
+    h: Vec<u8>,
+    /// Used for every hash and HMAC of the handshake, so that its TEE
+    /// operations are allocated once rather than for every HKDF output.
+    hasher: H,
 }
 
-impl SymmetricState {
+impl<H: NoiseHash> SymmetricState<H> {
     pub fn initialize_symmetric(protocol_name: &str) -> Self {
-        let mut h = [0u8; HASHLEN];
         let name_bytes = protocol_name.as_bytes();
-        h[0..name_bytes.len()].copy_from_slice(name_bytes);
-        let mut ck = [0u8; HASHLEN];
-        ck.copy_from_slice(&h);
+        let mut hasher = H::default();
+        let h = if name_bytes.len() <= H::HASHLEN {
+            let mut h = vec![0u8; H::HASHLEN];
+            h[0..name_bytes.len()].copy_from_slice(name_bytes);
+            h
+        } else {
+            let mut h = vec![0u8; H::HASHLEN];
+            hasher.input(name_bytes);
+            hasher.result(&mut h);
+            h
+        };
+        let ck = Zeroizing::new(h.clone());
         let cipher_state = CipherState::initialize_key(None);
         Self {
             cipher_state,
             ck,
             h,
+            hasher,
         }
     }
 
     pub fn mix_key(&mut self, input_key_material: &[u8]) {
         let (ck, temp_k) = self.hkdf_2(input_key_material);
         self.ck = ck;
-        self.cipher_state = CipherState::initialize_key(Some(temp_k));
+        self.cipher_state = CipherState::initialize_key(Some(cipher_key(&temp_k)));
+    }
+
+    /// Mix a pre-shared key into both the chaining key and the handshake
+    /// hash.
+    pub fn mix_key_and_hash(&mut self, input_key_material: &[u8]) {
+        let (ck, temp_h, temp_k) = self.hkdf_3(input_key_material);
+        self.ck = ck;
+        self.mix_hash(&temp_h);
+        self.cipher_state = CipherState::initialize_key(Some(cipher_key(&temp_k)));
     }
 
     pub fn mix_hash(&mut self, data: &[u8]) {
-        let mut concatenation = vec![];
-        concatenation.extend_from_slice(&self.h);
-        concatenation.extend_from_slice(data);
-        self.h = hash(&concatenation);
+        self.hasher.input(&self.h);
+        self.hasher.input(data);
+        self.hasher.result(&mut self.h);
     }
 
-    // pub fn get_handshake_hash(&self) -> [u8; HASHLEN] {
-    //     self.h
+    // pub fn get_handshake_hash(&self) -> &[u8] {
+    //     &self.h
     // }
 
     pub fn encrypt_and_hash(&mut self, plaintext: &[u8]) -> Vec<u8> {
@@ -162,75 +196,108 @@ impl SymmetricState {
         ciphertext
     }
 
-    pub fn decrypt_and_hash(&mut self, ciphertext: &[u8]) -> Vec<u8> {
-        let plaintext = self.cipher_state.decrypt_with_ad(&self.h, ciphertext);
+    pub fn decrypt_and_hash(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, NoiseError> {
+        let plaintext = self.cipher_state.decrypt_with_ad(&self.h, ciphertext)?;
         self.mix_hash(&ciphertext);
-        plaintext
+        Ok(plaintext)
+    }
+
+    fn hmac(&mut self, key: &[u8], data: &[u8]) -> Zeroizing<Vec<u8>> {
+        let mut out = Zeroizing::new(vec![0u8; H::HASHLEN]);
+        self.hasher.hmac(key, data, &mut out);
+        out
     }
 
-    fn hkdf_2(&self, input_key_material: &[u8]) -> ([u8; HASHLEN], [u8; HASHLEN]) {
-        let temp_key = hmac(&self.ck, input_key_material);
-        let output1 = hmac(&temp_key, &[0x01]);
+    fn hkdf_2(&mut self, input_key_material: &[u8]) -> (Zeroizing<Vec<u8>>, Zeroizing<Vec<u8>>) {
+        let ck = self.ck.clone();
+        let temp_key = self.hmac(&ck, input_key_material);
+        let output1 = self.hmac(&temp_key, &[0x01]);
 
-        let mut next_input = vec![0x02; HASHLEN + 1];
-        next_input[..HASHLEN].copy_from_slice(&output1);
-        let output2 = hmac(&temp_key, &next_input);
+        let mut next_input = Zeroizing::new(vec![0x02; H::HASHLEN + 1]);
+        next_input[..H::HASHLEN].copy_from_slice(&output1);
+        let output2 = self.hmac(&temp_key, &next_input);
 
         (output1, output2)
     }
 
-    fn hkdf_3(&self, input_key_material: &[u8]) -> ([u8; HASHLEN], [u8; HASHLEN], [u8; HASHLEN]) {
-        let temp_key = hmac(&self.ck, input_key_material);
-        let output1 = hmac(&temp_key, &[0x01]);
+    fn hkdf_3(
+        &mut self,
+        input_key_material: &[u8],
+    ) -> (Zeroizing<Vec<u8>>, Zeroizing<Vec<u8>>, Zeroizing<Vec<u8>>) {
+        let ck = self.ck.clone();
+        let temp_key = self.hmac(&ck, input_key_material);
+        let output1 = self.hmac(&temp_key, &[0x01]);
 
-        let mut next_input = vec![0x02; HASHLEN + 1];
-        next_input[..HASHLEN].copy_from_slice(&output1);
-        let output2 = hmac(&temp_key, &next_input);
+        let mut next_input = Zeroizing::new(vec![0x02; H::HASHLEN + 1]);
+        next_input[..H::HASHLEN].copy_from_slice(&output1);
+        let output2 = self.hmac(&temp_key, &next_input);
 
-        let mut next_input = vec![0x03; HASHLEN + 1];
-        next_input[..HASHLEN].copy_from_slice(&output2);
-        let output3 = hmac(&temp_key, &next_input);
+        let mut next_input = Zeroizing::new(vec![0x03; H::HASHLEN + 1]);
+        next_input[..H::HASHLEN].copy_from_slice(&output2);
+        let output3 = self.hmac(&temp_key, &next_input);
 
         (output1, output2, output3)
     }
 
-    pub fn split(&self) -> (CipherState, CipherState) {
+    pub fn split(&mut self) -> (CipherState, CipherState) {
         let (temp_k1, temp_k2) = self.hkdf_2(&[]);
         (
-            CipherState::initialize_key(Some(temp_k1)),
-            CipherState::initialize_key(Some(temp_k2)),
+            CipherState::initialize_key(Some(cipher_key(&temp_k1))),
+            CipherState::initialize_key(Some(cipher_key(&temp_k2))),
         )
     }
+
+    /// Wipe the chaining key and handshake cipher once the handshake is over.
+    pub fn clear(&mut self) {
+        self.ck.zeroize();
+        self.cipher_state = CipherState::initialize_key(None);
+    }
 }
 
-pub struct HandshakeState {
-    symmetric_state: SymmetricState,
-    s: StaticSecret,
-    e: Option<ReusableSecret>,
-    rs: Option<PublicKey>,
-    re: Option<PublicKey>,
+pub struct HandshakeState<H: NoiseHash = Sha256, R: RngCore + CryptoRng = PatatRng> {
+    symmetric_state: SymmetricState<H>,
+    dh: Box<dyn Dh>,
+    rng: R,
+    initiator: bool,
+    /// Whether this is a `psk` handshake, in which `e` is mixed into the key.
+    psk: bool,
+    s: Option<Keypair>,
+    e: Option<Keypair>,
+    rs: Option<Vec<u8>>,
+    re: Option<Vec<u8>>,
     transport_mode_states: Option<(CipherState, CipherState)>,
 }
 
-impl HandshakeState {
-    pub fn initialize(s: StaticSecret, rs: Option<PublicKey>) -> Self {
-        let mut symmetric_state =
-            SymmetricState::initialize_symmetric("Noise_XK_25519_ChaChaPoly_SHA256");
+impl<H: NoiseHash, R: RngCore + CryptoRng> HandshakeState<H, R> {
+    /// Start a handshake; ephemeral keys are generated from `rng`.
+    ///
+    /// Both parties must pass the same `prologue` or the handshake fails.
+    pub fn initialize(
+        dh: Box<dyn Dh>,
+        rng: R,
+        prologue: &[u8],
+        s: Keypair,
+        rs: Option<Vec<u8>>,
+    ) -> Self {
+        let protocol_name = format!("Noise_XK_{}_ChaChaPoly_{}", dh.name(), H::NAME);
+        let mut symmetric_state = SymmetricState::initialize_symmetric(&protocol_name);
 
         // MixHash(prologue)
-        symmetric_state.mix_hash(&[0u8; 0]);
+        symmetric_state.mix_hash(prologue);
         // MixHash(rs) -> pre-messages
         match rs {
-            Some(rs) => symmetric_state.mix_hash(rs.as_bytes()),
-            None => {
-                let s_pub = PublicKey::from(&s);
-                symmetric_state.mix_hash(s_pub.as_bytes());
-            }
+            Some(ref rs) => symmetric_state.mix_hash(rs),
+            None => symmetric_state.mix_hash(s.public()),
         };
 
         HandshakeState {
             symmetric_state,
-            s,
+            dh,
+            rng,
+            // In XK only the initiator knows the responder's static key up front
+            initiator: rs.is_some(),
+            psk: false,
+            s: Some(s),
             e: None,
             rs,
             re: None,
@@ -238,161 +305,250 @@ impl HandshakeState {
         }
     }
 
+    /// Start a resumed handshake with the `NNpsk0` pattern, keyed with the
+    /// `psk` from a resumption ticket:
+    ///
+    /// ```text
+    /// -> psk, e
+    /// <- e, ee
+    /// ```
+    ///
+    /// The first message is `write_resumption_message_1`, the second is the
+    /// same as in XK, so it is `write_message_2` and `read_message_2`.
+    pub fn initialize_resumption(
+        dh: Box<dyn Dh>,
+        rng: R,
+        prologue: &[u8],
+        psk: &[u8],
+        initiator: bool,
+    ) -> Self {
+        let protocol_name = format!("Noise_NNpsk0_{}_ChaChaPoly_{}", dh.name(), H::NAME);
+        let mut symmetric_state = SymmetricState::initialize_symmetric(&protocol_name);
+        symmetric_state.mix_hash(prologue);
+        // psk
+        symmetric_state.mix_key_and_hash(psk);
+
+        HandshakeState {
+            symmetric_state,
+            dh,
+            rng,
+            initiator,
+            psk: true,
+            s: None,
+            e: None,
+            rs: None,
+            re: None,
+            transport_mode_states: None,
+        }
+    }
+
+    /// -> psk, e
+    ///
+    /// The `psk` token was already processed by `initialize_resumption`.
+    pub fn write_resumption_message_1(&mut self, payload: &[u8]) -> Vec<u8> {
+        let mut payload_buffer = vec![];
+
+        // e
+        let e = self.dh.generate_keypair(&mut self.rng);
+        self.mix_ephemeral(e.public());
+        payload_buffer.extend_from_slice(e.public());
+        self.e = Some(e);
+
+        // encrypt payload
+        let ciphertext = self.symmetric_state.encrypt_and_hash(payload);
+        payload_buffer.extend_from_slice(&ciphertext);
+
+        payload_buffer
+    }
+
+    pub fn read_resumption_message_1(&mut self, payload: &[u8]) -> Result<Vec<u8>, NoiseError> {
+        let dh_len = self.dh.dh_len();
+        if payload.len() < dh_len {
+            return Err(NoiseError::Truncated);
+        }
+
+        // e
+        let re = payload[0..dh_len].to_vec();
+        self.mix_ephemeral(&re);
+        self.re = Some(re);
+
+        // decrypt payload
+        self.symmetric_state.decrypt_and_hash(&payload[dh_len..])
+    }
+
+    /// The `e` token; handshakes with a `psk` also mix it into the key.
+    fn mix_ephemeral(&mut self, public: &[u8]) {
+        self.symmetric_state.mix_hash(public);
+        if self.psk {
+            self.symmetric_state.mix_key(public);
+        }
+    }
+
     /// -> e, es
-    pub fn write_message_1(&mut self, payload: &[u8]) -> Vec<u8> {
+    pub fn write_message_1(&mut self, payload: &[u8]) -> Result<Vec<u8>, NoiseError> {
         let mut payload_buffer = vec![];
 
         // e
-        let e = ReusableSecret::new(PatatRng);
-        let e_pub = PublicKey::from(&e);
+        let e = self.dh.generate_keypair(&mut self.rng);
+        self.symmetric_state.mix_hash(e.public());
+        payload_buffer.extend_from_slice(e.public());
         self.e = Some(e);
-        let e_pub_bytes = e_pub.to_bytes();
-        self.symmetric_state.mix_hash(&e_pub_bytes);
-        payload_buffer.extend_from_slice(&e_pub_bytes);
 
         // es
-        self.symmetric_state.mix_key(
-            self.e
-                .as_ref()
-                .unwrap()
-                .diffie_hellman(&self.rs.unwrap())
-                .as_bytes(),
-        );
+        let es = self
+            .dh
+            .dh(self.e.as_ref().unwrap(), self.rs.as_ref().unwrap())?;
+        self.symmetric_state.mix_key(&es);
 
         // encrypt payload
         let ciphertext = self.symmetric_state.encrypt_and_hash(payload);
         payload_buffer.extend_from_slice(&ciphertext);
 
-        payload_buffer
+        Ok(payload_buffer)
     }
 
     /// -> e, es
     /// But now from the responder's side
-    pub fn read_message_1(&mut self, payload: &[u8]) -> Vec<u8> {
+    pub fn read_message_1(&mut self, payload: &[u8]) -> Result<Vec<u8>, NoiseError> {
         let mut payload_buffer = vec![];
+        let dh_len = self.dh.dh_len();
+
+        if payload.len() < dh_len {
+            return Err(NoiseError::Truncated);
+        }
 
         // e
-        let re_bytes: [u8; 32] = payload[0..DHLEN].try_into().unwrap();
-        let re: PublicKey = re_bytes.into();
-        self.symmetric_state.mix_hash(re.as_bytes());
+        let re = payload[0..dh_len].to_vec();
+        self.symmetric_state.mix_hash(&re);
         self.re = Some(re);
 
         // es
-        self.symmetric_state
-            .mix_key(self.s.diffie_hellman(&self.re.unwrap()).as_bytes());
+        let es = self
+            .dh
+            .dh(self.s.as_ref().unwrap(), self.re.as_ref().unwrap())?;
+        self.symmetric_state.mix_key(&es);
 
         // decrypt payload
-        let plaintext = self.symmetric_state.decrypt_and_hash(&payload[DHLEN..]);
+        let plaintext = self.symmetric_state.decrypt_and_hash(&payload[dh_len..])?;
         payload_buffer.extend_from_slice(&plaintext);
 
-        payload_buffer
+        Ok(payload_buffer)
     }
 
     /// <- e, ee
-    pub fn write_message_2(&mut self, payload: &[u8]) -> Vec<u8> {
+    pub fn write_message_2(&mut self, payload: &[u8]) -> Result<Vec<u8>, NoiseError> {
         let mut payload_buffer = vec![];
 
         // e
-        let e = ReusableSecret::new(PatatRng);
-        let e_pub = PublicKey::from(&e);
+        let e = self.dh.generate_keypair(&mut self.rng);
+        self.mix_ephemeral(e.public());
+        payload_buffer.extend_from_slice(e.public());
         self.e = Some(e);
-        let e_pub_bytes = e_pub.to_bytes();
-        self.symmetric_state.mix_hash(&e_pub_bytes);
-        payload_buffer.extend_from_slice(&e_pub_bytes);
 
         // ee
-        self.symmetric_state.mix_key(
-            self.e
-                .as_ref()
-                .unwrap()
-                .diffie_hellman(&self.re.unwrap())
-                .as_bytes(),
-        );
+        let ee = self
+            .dh
+            .dh(self.e.as_ref().unwrap(), self.re.as_ref().unwrap())?;
+        self.symmetric_state.mix_key(&ee);
 
         // encrypt payload
         let ciphertext = self.symmetric_state.encrypt_and_hash(payload);
         payload_buffer.extend_from_slice(&ciphertext);
 
-        payload_buffer
+        Ok(payload_buffer)
     }
 
-    pub fn read_message_2(&mut self, payload: &[u8]) -> Vec<u8> {
+    pub fn read_message_2(&mut self, payload: &[u8]) -> Result<Vec<u8>, NoiseError> {
         let mut payload_buffer = vec![];
+        let dh_len = self.dh.dh_len();
+        if payload.len() < dh_len {
+            return Err(NoiseError::Truncated);
+        }
 
         // e
-        let re_bytes: [u8; 32] = payload[0..DHLEN].try_into().unwrap();
-        let re: PublicKey = re_bytes.into();
-        self.symmetric_state.mix_hash(re.as_bytes());
+        let re = payload[0..dh_len].to_vec();
+        self.mix_ephemeral(&re);
         self.re = Some(re);
 
         // ee
-        self.symmetric_state.mix_key(
-            self.e
-                .as_ref()
-                .unwrap()
-                .diffie_hellman(&self.re.unwrap())
-                .as_bytes(),
-        );
+        let ee = self
+            .dh
+            .dh(self.e.as_ref().unwrap(), self.re.as_ref().unwrap())?;
+        self.symmetric_state.mix_key(&ee);
 
         // decrypt payload
-        let plaintext = self.symmetric_state.decrypt_and_hash(&payload[DHLEN..]);
+        let plaintext = self.symmetric_state.decrypt_and_hash(&payload[dh_len..])?;
         payload_buffer.extend_from_slice(&plaintext);
 
-        payload_buffer
+        Ok(payload_buffer)
     }
 
     /// -> s, se
-    pub fn write_message_3(&mut self, payload: &[u8]) -> Vec<u8> {
+    pub fn write_message_3(&mut self, payload: &[u8]) -> Result<Vec<u8>, NoiseError> {
         let mut payload_buffer = vec![];
 
         // s
-        let s_pub = PublicKey::from(&self.s);
-        let encrypted_key = self.symmetric_state.encrypt_and_hash(s_pub.as_bytes());
+        let encrypted_key = self
+            .symmetric_state
+            .encrypt_and_hash(self.s.as_ref().unwrap().public());
         payload_buffer.extend_from_slice(&encrypted_key);
 
         // se
-        self.symmetric_state
-            .mix_key(self.s.diffie_hellman(&self.re.unwrap()).as_bytes());
+        let se = self
+            .dh
+            .dh(self.s.as_ref().unwrap(), self.re.as_ref().unwrap())?;
+        self.symmetric_state.mix_key(&se);
 
         // encrypt payload
         let ciphertext = self.symmetric_state.encrypt_and_hash(payload);
         payload_buffer.extend_from_slice(&ciphertext);
 
-        payload_buffer
+        Ok(payload_buffer)
     }
 
-    pub fn read_message_3(&mut self, payload: &[u8]) -> Vec<u8> {
+    pub fn read_message_3(&mut self, payload: &[u8]) -> Result<Vec<u8>, NoiseError> {
         let mut payload_buffer = vec![];
+        let dh_len = self.dh.dh_len();
+        if payload.len() < dh_len + 16 {
+            return Err(NoiseError::Truncated);
+        }
 
         // s
-        let rs_bytes: [u8; 32] = self
+        let rs = self
             .symmetric_state
-            .decrypt_and_hash(&payload[0..DHLEN + 16])
-            .try_into()
-            .unwrap();
-        let rs: PublicKey = rs_bytes.into();
+            .decrypt_and_hash(&payload[0..dh_len + 16])?;
         self.rs = Some(rs);
 
         // se
-        self.symmetric_state.mix_key(
-            self.e
-                .as_ref()
-                .unwrap()
-                .diffie_hellman(&self.rs.unwrap())
-                .as_bytes(),
-        );
+        let se = self
+            .dh
+            .dh(self.e.as_ref().unwrap(), self.rs.as_ref().unwrap())?;
+        self.symmetric_state.mix_key(&se);
 
         // decrypt payload
         let plaintext = self
             .symmetric_state
-            .decrypt_and_hash(&payload[DHLEN + 16..]);
+            .decrypt_and_hash(&payload[dh_len + 16..])?;
         payload_buffer.extend_from_slice(&plaintext);
 
-        payload_buffer
+        Ok(payload_buffer)
     }
 
+    /// The handshake hash `h`, which is unique to this session and can be
+    /// used for channel binding once the handshake is finished.
+    pub fn handshake_hash(&self) -> &[u8] {
+        &self.symmetric_state.h
+    }
+
+    /// Split into the transport ciphers and drop every key that is only
+    /// needed during the handshake.
     pub fn to_transport_mode(&mut self) {
-        self.transport_mode_states = Some(self.symmetric_state.split());
+        // The initiator sends with the first cipher, the responder with the second
+        let (c1, c2) = self.symmetric_state.split();
+        self.transport_mode_states = Some(if self.initiator { (c1, c2) } else { (c2, c1) });
+        self.symmetric_state.clear();
+        self.s = None;
+        self.e = None;
     }
 
     pub fn encrypt(&mut self, payload: &[u8]) -> Vec<u8> {
@@ -405,7 +561,9 @@ impl HandshakeState {
         ret
     }
 
-    pub fn decrypt(&mut self, payload: &[u8]) -> Vec<u8> {
+    /// Decrypt a transport message; a message that fails authentication is
+    /// rejected without advancing the nonce.
+    pub fn decrypt(&mut self, payload: &[u8]) -> Result<Vec<u8>, NoiseError> {
         if self.transport_mode_states.is_none() {
             panic!()
         }
@@ -415,3 +573,129 @@ impl HandshakeState {
         ret
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dh::X25519Dalek;
+    use crate::hasher::Blake2s;
+    use rand_chacha::ChaCha20Rng;
+    use rand_core::SeedableRng;
+
+    fn handshake_pair(
+        seed: u8,
+    ) -> (
+        HandshakeState<Blake2s, ChaCha20Rng>,
+        HandshakeState<Blake2s, ChaCha20Rng>,
+    ) {
+        let initiator_keypair = X25519Dalek.keypair_from_secret(&[0x01; 32]);
+        let responder_keypair = X25519Dalek.keypair_from_secret(&[0x02; 32]);
+        let initiator = HandshakeState::initialize(
+            Box::new(X25519Dalek),
+            ChaCha20Rng::from_seed([seed; 32]),
+            &[],
+            initiator_keypair,
+            Some(responder_keypair.public().to_vec()),
+        );
+        let responder = HandshakeState::initialize(
+            Box::new(X25519Dalek),
+            ChaCha20Rng::from_seed([seed + 1; 32]),
+            &[],
+            responder_keypair,
+            None,
+        );
+        (initiator, responder)
+    }
+
+    fn run_handshake(seed: u8) -> Vec<Vec<u8>> {
+        let (mut initiator, mut responder) = handshake_pair(seed);
+
+        let message_1 = initiator.write_message_1(b"one").unwrap();
+        assert_eq!(responder.read_message_1(&message_1).unwrap(), b"one");
+        let message_2 = responder.write_message_2(b"two").unwrap();
+        assert_eq!(initiator.read_message_2(&message_2).unwrap(), b"two");
+        let message_3 = initiator.write_message_3(b"three").unwrap();
+        assert_eq!(responder.read_message_3(&message_3).unwrap(), b"three");
+
+        initiator.to_transport_mode();
+        responder.to_transport_mode();
+        let transport = initiator.encrypt(b"four");
+        assert_eq!(responder.decrypt(&transport).unwrap(), b"four");
+
+        vec![message_1, message_2, message_3, transport]
+    }
+
+    #[test]
+    fn seeded_handshakes_are_reproducible() {
+        assert_eq!(run_handshake(7), run_handshake(7));
+        assert_ne!(run_handshake(7), run_handshake(8));
+    }
+
+    #[test]
+    fn responder_rejects_low_order_ephemeral() {
+        let responder_keypair = X25519Dalek.keypair_from_secret(&[0x01; 32]);
+        let mut responder: HandshakeState<Blake2s, ChaCha20Rng> = HandshakeState::initialize(
+            Box::new(X25519Dalek),
+            ChaCha20Rng::from_seed([0; 32]),
+            &[],
+            responder_keypair,
+            None,
+        );
+
+        // An all-zero `e` followed by an empty, unauthenticated payload
+        let mut message = vec![0u8; 32];
+        message.extend_from_slice(&[0u8; 16]);
+
+        assert_eq!(
+            responder.read_message_1(&message),
+            Err(NoiseError::Dh(DhError::NonContributory))
+        );
+    }
+
+    #[test]
+    fn tampered_transport_messages_are_rejected() {
+        let (mut initiator, mut responder) = handshake_pair(7);
+        let message_1 = initiator.write_message_1(b"one").unwrap();
+        responder.read_message_1(&message_1).unwrap();
+        let message_2 = responder.write_message_2(b"two").unwrap();
+        initiator.read_message_2(&message_2).unwrap();
+        let message_3 = initiator.write_message_3(b"three").unwrap();
+        responder.read_message_3(&message_3).unwrap();
+        initiator.to_transport_mode();
+        responder.to_transport_mode();
+
+        let transport = initiator.encrypt(b"four");
+        let mut tampered = transport.clone();
+        tampered[0] ^= 1;
+        assert_eq!(responder.decrypt(&tampered), Err(NoiseError::Decrypt));
+        assert_eq!(responder.decrypt(&transport).unwrap(), b"four");
+    }
+
+    #[test]
+    fn initiator_rejects_truncated_message_2() {
+        let (mut initiator, mut responder) = handshake_pair(7);
+        let message_1 = initiator.write_message_1(b"one").unwrap();
+        responder.read_message_1(&message_1).unwrap();
+        let message_2 = responder.write_message_2(b"two").unwrap();
+
+        assert_eq!(
+            initiator.read_message_2(&message_2[..31]),
+            Err(NoiseError::Truncated)
+        );
+    }
+
+    #[test]
+    fn initiator_rejects_tampered_message_2() {
+        let (mut initiator, mut responder) = handshake_pair(7);
+        let message_1 = initiator.write_message_1(b"one").unwrap();
+        responder.read_message_1(&message_1).unwrap();
+        let mut message_2 = responder.write_message_2(b"two").unwrap();
+        let last = message_2.len() - 1;
+        message_2[last] ^= 1;
+
+        assert_eq!(
+            initiator.read_message_2(&message_2),
+            Err(NoiseError::Decrypt)
+        );
+    }
+}
@@ -0,0 +1,197 @@
+//! Conformance tests for `ta::noise` against the published Noise test vectors
+//! for `Noise_XK_25519_ChaChaPoly_SHA256`.
+//!
+//! The vectors are taken from the cacophony and snow test suites. Ephemeral
+//! keys are fixed by feeding them to the handshake through the RNG, and every
+//! handshake and transport message is checked byte for byte in both
+//! directions.
+//!
+//! With the `sim` backend the handshake runs on the production `Sha256`. The
+//! default OP-TEE stub has no digest operations, so there a software SHA-256
+//! stands in for it.
+
+use rand_core::{impls, CryptoRng, Error, RngCore};
+
+use crate::dh::{Dh, X25519Dalek};
+use crate::noise::HandshakeState;
+use crate::test_support::{hex, Sha256};
+
+/// Hands out a fixed ephemeral secret the first time it is asked for bytes.
+struct FixedRng(Vec<u8>);
+
+impl RngCore for FixedRng {
+    fn next_u32(&mut self) -> u32 {
+        impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        assert!(dest.len() <= self.0.len(), "fixed RNG exhausted");
+        let rest = self.0.split_off(dest.len());
+        dest.copy_from_slice(&self.0);
+        self.0 = rest;
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for FixedRng {}
+
+struct Message {
+    payload: &'static str,
+    ciphertext: &'static str,
+}
+
+struct Vector {
+    prologue: &'static str,
+    init_static: &'static str,
+    init_ephemeral: &'static str,
+    resp_static: &'static str,
+    resp_ephemeral: &'static str,
+    handshake_hash: Option<&'static str>,
+    messages: &'static [Message],
+}
+
+const CACOPHONY: Vector = Vector {
+    prologue: "4a6f686e2047616c74",
+    init_static: "e61ef9919cde45dd5f82166404bd08e38bceb5dfdfded0a34c8df7ed542214d1",
+    init_ephemeral: "893e28b9dc6ca8d611ab664754b8ceb7bac5117349a4439a6b0569da977c464a",
+    resp_static: "4a3acbfdb163dec651dfa3194dece676d437029c62a408b4c5ea9114246e4893",
+    resp_ephemeral: "bbdb4cdbd309f1a1f2e1456967fe288cadd6f712d65dc7b7793d5e63da6b375b",
+    handshake_hash: Some("cefffc5d1074126cc980ebfe902587ff36ba61dc77d4447ebe0f96dc22ae59d7"),
+    messages: &[
+        Message {
+            payload: "4c756477696720766f6e204d69736573",
+            ciphertext: "ca35def5ae56cec33dc2036731ab14896bc4c75dbb07a61f879f8e3afa4c7944a3785af283c991bab613473804356ef6931f83acf64f99c274b93570857cfc5e",
+        },
+        Message {
+            payload: "4d757272617920526f746862617264",
+            ciphertext: "95ebc60d2b1fa672c1f46a8aa265ef51bfe38e7ccb39ec5be34069f1448088433a4534805fa9fe4eb8343ace6609160c767ad9b832e8eea1d9b7a2111818dd",
+        },
+        Message {
+            payload: "462e20412e20486179656b",
+            ciphertext: "5d8e67b9c1b8e36f5dc674bc5cd2ce243fb5d1710fa57de0370da7cc979015398eaad94603b05498ba9a613d2fd923dcaa6fd4288dfd8d70f419bf737efb4cd37f5da37ebb728849318c82",
+        },
+        Message {
+            payload: "4361726c204d656e676572",
+            ciphertext: "3205e1265f809505e6edc092839d3156745d2abafbfd946b261e41",
+        },
+        Message {
+            payload: "4a65616e2d426170746973746520536179",
+            ciphertext: "470bcb1ae099555ff0d729500df550418d6ee5149d9e40bd2f4c6b3d263cc818d5",
+        },
+        Message {
+            payload: "457567656e2042f6686d20766f6e2042617765726b",
+            ciphertext: "d7187ed9d217ba6e91cf596e4871012ccedf7b5bed0d4cb8f7affb020fa17a95a23371e0f6",
+        },
+    ],
+};
+
+const SNOW: Vector = Vector {
+    prologue: "5468657265206973206e6f20726967687420616e642077726f6e672e2054686572652773206f6e6c792066756e20616e6420626f72696e672e",
+    init_static: "8177a2019b376397e89468ac58205fb9991af849996dd6bf0ebe831b962ce607",
+    init_ephemeral: "a442b0f4eb04b6f9987d68388229ac97867de0ac3fad744fbceb97a13769ed00",
+    resp_static: "92a53f9ca53c0acd7094b95501d77bb2bf7debefa7f7e8d6e472a5f6d7291fce",
+    resp_ephemeral: "b914ec989bcb032a9ec7e45cde83f8c08643e7432b6f136f59150bf88effd44d",
+    handshake_hash: None,
+    messages: &[
+        Message {
+            payload: "2c2051dc2a5f7771b5e921c51fbcb20d0bad45781a5674186435863e93454573",
+            ciphertext: "991683977eacbc696b55a72fe510b4e6faab1b502999a5af89726a62246a4d4fc2f4cd239846697e3b7ad715970bfb6ad5c0affa72782103db23b33e6a84757ea2b39a1f2c40634d12264090f20cf9ec",
+        },
+        Message {
+            payload: "74ccc0e2571171bcd6e7ea742b7fe9bcc720ec18ca6f79eb8c57cc368b594b21",
+            ciphertext: "66a5e73b6328a3e8febc6f1765ea19e708b34c0638f9ff90c2ed0f730efdc70f80fb08c3a7f1829d550c32169b3cebe57a426af2a76ea8379c6eccafbe4774862226a692e2ec66ecd8ff9221ca337933",
+        },
+        Message {
+            payload: "304f90545918fffe07bfaa3b0d5b687dbba0ba12d1d8a05b79de1f3fcc3afe79",
+            ciphertext: "812bb1b030afa2a53d78cd7ac584ac29e3038236de2d5016a3fdb10f5288f133fcd01c2f7e619c84f1954e1c060e9a983ce030cf1d1ac3409907d78aef83bb5bdbc45c81332fd84a27d1a7866fe29fbb552d6dca0a44bd9ee31a95baa0ca87a3",
+        },
+    ],
+};
+
+fn check_vector(vector: &Vector) {
+    let prologue = hex(vector.prologue);
+    let init_static = X25519Dalek.keypair_from_secret(&hex(vector.init_static));
+    let resp_static = X25519Dalek.keypair_from_secret(&hex(vector.resp_static));
+
+    let mut initiator: HandshakeState<Sha256, FixedRng> = HandshakeState::initialize(
+        Box::new(X25519Dalek),
+        FixedRng(hex(vector.init_ephemeral)),
+        &prologue,
+        init_static,
+        Some(resp_static.public().to_vec()),
+    );
+    let mut responder: HandshakeState<Sha256, FixedRng> = HandshakeState::initialize(
+        Box::new(X25519Dalek),
+        FixedRng(hex(vector.resp_ephemeral)),
+        &prologue,
+        resp_static,
+        None,
+    );
+
+    for (i, message) in vector.messages.iter().enumerate() {
+        let payload = hex(message.payload);
+        let (ciphertext, plaintext) = match i {
+            0 => {
+                let ciphertext = initiator.write_message_1(&payload).unwrap();
+                (
+                    ciphertext.clone(),
+                    responder.read_message_1(&ciphertext).unwrap(),
+                )
+            }
+            1 => {
+                let ciphertext = responder.write_message_2(&payload).unwrap();
+                (
+                    ciphertext.clone(),
+                    initiator.read_message_2(&ciphertext).unwrap(),
+                )
+            }
+            2 => {
+                let ciphertext = initiator.write_message_3(&payload).unwrap();
+                let plaintext = responder.read_message_3(&ciphertext).unwrap();
+
+                if let Some(handshake_hash) = vector.handshake_hash {
+                    assert_eq!(initiator.handshake_hash(), &hex(handshake_hash)[..]);
+                }
+                assert_eq!(initiator.handshake_hash(), responder.handshake_hash());
+                initiator.to_transport_mode();
+                responder.to_transport_mode();
+
+                (ciphertext, plaintext)
+            }
+            i if i % 2 == 1 => {
+                let ciphertext = responder.encrypt(&payload);
+                (ciphertext.clone(), initiator.decrypt(&ciphertext).unwrap())
+            }
+            _ => {
+                let ciphertext = initiator.encrypt(&payload);
+                (ciphertext.clone(), responder.decrypt(&ciphertext).unwrap())
+            }
+        };
+
+        assert_eq!(
+            ciphertext,
+            hex(message.ciphertext),
+            "ciphertext of message {}",
+            i
+        );
+        assert_eq!(plaintext, payload, "payload of message {}", i);
+    }
+}
+
+#[test]
+fn cacophony_xk_25519_chachapoly_sha256() {
+    check_vector(&CACOPHONY);
+}
+
+#[test]
+fn snow_xk_25519_chachapoly_sha256() {
+    check_vector(&SNOW);
+}
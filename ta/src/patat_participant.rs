@@ -1,11 +1,9 @@
-// OP-TEE
-use optee_utee::net::TcpStream;
-use optee_utee::net::UdpSocket;
-use optee_utee::trace_println;
-use proto::HASHLEN;
+// TEE
+use crate::tee::net::TcpStream;
+use crate::tee::trace_println;
 
 // std
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 use std::iter::FromIterator;
 
 // libraries
@@ -13,76 +11,216 @@ use merkle_light::hash::Algorithm;
 use merkle_light::merkle::MerkleTree;
 
 // TA Code
-use crate::evidence::EvidenceProof;
-use crate::noise::HandshakeState;
+use crate::dh::{Dh, Keypair};
+use crate::evidence::{EvidenceProof, EvidenceUpdate, EVIDENCE_UPDATE};
+use crate::hasher::{NoiseHash, Sha256};
+use crate::noise::{HandshakeState, NoiseError};
 use crate::random::PatatRng;
-use crate::x25519::{PublicKey, StaticSecret};
+use crate::ticket::{self, Ticket, TICKET_REQUEST};
+use crate::timing::PhaseTimer;
 
-pub struct PatatTA {
+// Protocol
+use proto::{Phase, Verifier};
+
+/// Why an XK handshake failed.
+#[derive(Debug)]
+pub enum ConnectError {
+    /// The verifier could not be reached or the connection broke.
+    Io(io::Error),
+    Noise(NoiseError),
+}
+
+impl From<io::Error> for ConnectError {
+    fn from(value: io::Error) -> Self {
+        ConnectError::Io(value)
+    }
+}
+
+impl From<NoiseError> for ConnectError {
+    fn from(value: NoiseError) -> Self {
+        ConnectError::Noise(value)
+    }
+}
+
+/// Why a resumed handshake failed.
+#[derive(Debug)]
+pub enum ResumeError {
+    /// The verifier could not be reached.
+    Connect(io::Error),
+    /// The connection failed later on; a verifier that rejects the ticket
+    /// closes it.
+    Io(io::Error),
+    Noise(NoiseError),
+}
+
+impl From<io::Error> for ResumeError {
+    fn from(value: io::Error) -> Self {
+        ResumeError::Io(value)
+    }
+}
+
+impl From<NoiseError> for ResumeError {
+    fn from(value: NoiseError) -> Self {
+        ResumeError::Noise(value)
+    }
+}
+
+pub struct PatatTA<H: NoiseHash = Sha256> {
     stream: TcpStream,
-    handshake_state: HandshakeState,
+    handshake_state: HandshakeState<H>,
 }
 
-impl PatatTA {
-    pub fn connect(ta_secret: StaticSecret, server_pubkey: PublicKey) -> Self {
-        // The address of the Host in QEMU is 10.0.2.2
-        let mut stream = TcpStream::connect("10.0.2.2", 65432).unwrap();
+impl<H: NoiseHash> PatatTA<H> {
+    /// Attest to `verifier` with an XK handshake.
+    ///
+    /// `certificates` is the encoded `CertificateChain` for the static key in
+    /// `ta_keypair`, sent as the payload of message 3 together with that key.
+    /// It is empty if the TA has no certificates.
+    pub fn connect(
+        dh: Box<dyn Dh>,
+        ta_keypair: Keypair,
+        certificates: &[u8],
+        verifier: Verifier,
+        timer: &mut PhaseTimer,
+    ) -> Result<Self, ConnectError> {
+        let mut stream = TcpStream::connect(&verifier.host, verifier.port)?;
         trace_println!("Connecting to the server");
+        timer.record(Phase::Connect);
 
         // Handshake start
-        let mut handshake_state = HandshakeState::initialize(ta_secret, Some(server_pubkey));
+        let mut handshake_state = HandshakeState::initialize(
+            dh,
+            PatatRng,
+            &[],
+            ta_keypair,
+            Some(verifier.public_key),
+        );
         trace_println!("Handshake started");
 
         // Message 1
-        let payload = handshake_state.write_message_1("test".as_bytes());
+        let payload = handshake_state.write_message_1("test".as_bytes())?;
         trace_println!("Got payload");
-        Self::send_message(&mut stream, &payload);
+        Self::send_message(&mut stream, &payload)?;
         trace_println!("Sent 1");
+        timer.record(Phase::Message1);
 
         // Message 2
-        let payload = Self::receive_message(&mut stream);
-        let decrypted = handshake_state.read_message_2(&payload);
+        let payload = Self::receive_message(&mut stream)?;
+        let decrypted = handshake_state.read_message_2(&payload)?;
         trace_println!("Received 2");
+        timer.record(Phase::Message2);
 
         // Message 3
-        let payload = handshake_state.write_message_3("test".as_bytes());
-        Self::send_message(&mut stream, &payload);
+        let payload = handshake_state.write_message_3(certificates)?;
+        Self::send_message(&mut stream, &payload)?;
+        timer.record(Phase::Message3);
 
         // Send message in transport state
         handshake_state.to_transport_mode();
         let payload = handshake_state.encrypt(b"test");
-        Self::send_message(&mut stream, &payload);
+        Self::send_message(&mut stream, &payload)?;
         trace_println!("Sent 3");
 
         trace_println!("Waiting to receive message");
         // Receive in transport state
-        let payload = Self::receive_message(&mut stream);
-        let decrypted = handshake_state.decrypt(&payload);
+        let payload = Self::receive_message(&mut stream)?;
+        let decrypted = handshake_state.decrypt(&payload)?;
         trace_println!("Message \"{}\"", String::from_utf8_lossy(&decrypted));
-        PatatTA {
+        timer.record(Phase::Confirmation);
+        Ok(PatatTA {
+            stream,
+            handshake_state,
+        })
+    }
+
+    /// Reconnect to `verifier` with a resumed handshake instead of XK.
+    ///
+    /// The TA announces resumption with an empty frame, which XK never sends,
+    /// followed by a frame with the ticket. The ticket is also the prologue of
+    /// the handshake, which binds the handshake to it.
+    pub fn resume(
+        dh: Box<dyn Dh>,
+        ticket: &Ticket,
+        verifier: &Verifier,
+        timer: &mut PhaseTimer,
+    ) -> Result<Self, ResumeError> {
+        let mut stream =
+            TcpStream::connect(&verifier.host, verifier.port).map_err(ResumeError::Connect)?;
+        timer.record(Phase::Connect);
+
+        let mut handshake_state = HandshakeState::initialize_resumption(
+            dh,
+            PatatRng,
+            ticket.ticket(),
+            ticket.psk(),
+            true,
+        );
+
+        // Message 1
+        Self::send_message(&mut stream, &[])?;
+        Self::send_message(&mut stream, ticket.ticket())?;
+        let payload = handshake_state.write_resumption_message_1(&[]);
+        Self::send_message(&mut stream, &payload)?;
+        timer.record(Phase::Message1);
+
+        // Message 2
+        let payload = Self::receive_message(&mut stream)?;
+        handshake_state.read_message_2(&payload)?;
+        timer.record(Phase::Message2);
+
+        handshake_state.to_transport_mode();
+        Ok(PatatTA {
             stream,
             handshake_state,
-        }
+        })
+    }
+
+    /// Ask the verifier for a resumption ticket; `None` if it declined.
+    pub fn request_ticket(&mut self) -> io::Result<Option<Ticket>> {
+        self.send(TICKET_REQUEST)?;
+        let reply = self.receive()?;
+        Ok(Ticket::from_reply(&reply, ticket::now()))
     }
 
-    pub fn send_evidence(&mut self, evidence: EvidenceProof) {
+    pub fn send_evidence(&mut self, evidence: EvidenceProof<H>) -> io::Result<()> {
         let evidence_bytes: Vec<u8> = evidence.into();
-        let payload = self.handshake_state.encrypt(&evidence_bytes);
-        Self::send_message(&mut self.stream, &payload);
+        self.send(&evidence_bytes)
+    }
+
+    /// Report the root of the evidence log, prefixed with `EVIDENCE_UPDATE`.
+    pub fn send_evidence_update(&mut self, update: EvidenceUpdate<H>) -> io::Result<()> {
+        let mut message = EVIDENCE_UPDATE.to_vec();
+        message.append(&mut update.into());
+        self.send(&message)
+    }
+
+    /// Encrypt `payload` with the transport keys and send it to the verifier.
+    pub fn send(&mut self, payload: &[u8]) -> io::Result<()> {
+        let payload = self.handshake_state.encrypt(payload);
+        Self::send_message(&mut self.stream, &payload)
+    }
+
+    /// Receive the next message from the verifier and decrypt it. A message
+    /// that fails to decrypt is reported as `io::ErrorKind::InvalidData`.
+    pub fn receive(&mut self) -> io::Result<Vec<u8>> {
+        let payload = Self::receive_message(&mut self.stream)?;
+        self.handshake_state
+            .decrypt(&payload)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))
     }
 
-    fn receive_message(stream: &mut TcpStream) -> Vec<u8> {
+    fn receive_message(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
         let mut receive_buffer = [0u8; 2];
-        stream.read_exact(&mut receive_buffer).unwrap();
+        stream.read_exact(&mut receive_buffer)?;
         let message_length = ((receive_buffer[0] as u32) << 8) + (receive_buffer[1] as u32);
         let mut payload = vec![0u8; message_length as usize];
-        stream.read_exact(&mut payload).unwrap();
-        payload
+        stream.read_exact(&mut payload)?;
+        Ok(payload)
     }
 
-    fn send_message(stream: &mut TcpStream, payload: &[u8]) {
+    fn send_message(stream: &mut TcpStream, payload: &[u8]) -> io::Result<()> {
         let message_length_buffer = [(payload.len() >> 8) as u8, (payload.len() & 0xff) as u8];
-        stream.write_all(&message_length_buffer).unwrap();
-        stream.write_all(&payload).unwrap();
+        stream.write_all(&message_length_buffer)?;
+        stream.write_all(&payload)
     }
 }
@@ -0,0 +1,128 @@
+// TEE
+use crate::tee::trace_println;
+
+// TA Code
+use crate::dh;
+use crate::hasher::{Blake2s, NoiseHash, Sha256, Sha512};
+use crate::noise::{HandshakeState, NoiseError};
+use crate::random::PatatRng;
+
+/// A named check that returns whether it passed.
+type Check = (&'static str, fn() -> bool);
+
+const CHECKS: [Check; 6] = [
+    ("SHA-256", sha256_known_answer),
+    ("HMAC-SHA-256", hmac_sha256_known_answer),
+    ("X25519", x25519_known_answer),
+    ("Noise with SHA-256", noise_loopback::<Sha256>),
+    ("Noise with SHA-512", noise_loopback::<Sha512>),
+    ("Noise with BLAKE2s", noise_loopback::<Blake2s>),
+];
+
+/// Run every check and return the number of passed and failed checks.
+pub fn run() -> (u32, u32) {
+    let mut passed = 0;
+    let mut failed = 0;
+    for (name, check) in CHECKS.iter() {
+        if check() {
+            passed += 1;
+        } else {
+            trace_println!("[-] Self-test failed: {}", name);
+            failed += 1;
+        }
+    }
+    (passed, failed)
+}
+
+/// Decode the hex string `s`, which must be valid.
+pub(crate) fn hex(s: &str) -> Vec<u8> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+fn sha256_known_answer() -> bool {
+    let mut hash = Sha256::default();
+    let mut out = [0u8; 32];
+    hash.input(b"abc");
+    hash.result(&mut out);
+    out[..] == hex("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")[..]
+}
+
+/// Test case AUTH256-1 of [IETF RFC 4868](https://www.rfc-editor.org/rfc/rfc4868#section-2.7.2.2)
+/// and test case 4 of [IETF RFC 4231](https://www.rfc-editor.org/rfc/rfc4231),
+/// with the same operation, so that rekeying it is covered as well. The first
+/// has a key of `HASHLEN` bytes, as Noise uses. OP-TEE rejects HMAC-SHA-256
+/// keys shorter than 192 bits, so the 160-bit key of RFC 4231 test case 1
+/// cannot be used.
+fn hmac_sha256_known_answer() -> bool {
+    let mut hasher = Sha256::default();
+    let mut first = [0u8; 32];
+    let mut second = [0u8; 32];
+    let key: Vec<u8> = (0x01..=0x19).collect();
+    hasher.try_hmac(&[0x0b; 32], b"Hi There", &mut first).is_ok()
+        && hasher.try_hmac(&key, &[0xcd; 50], &mut second).is_ok()
+        && first[..] == hex("198a607eb44bfbc69903a0f1cf2bbdc5ba0aa3f3d9ae3c1c7a3b1696a0b68cf7")[..]
+        && second[..] == hex("82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b")[..]
+}
+
+/// Section 6.1 of [IETF RFC 7748](https://www.rfc-editor.org/rfc/rfc7748),
+/// run on the active X25519 backend.
+fn x25519_known_answer() -> bool {
+    let dh = dh::x25519();
+    let alice = dh.keypair_from_secret(&hex(
+        "77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a",
+    ));
+    let bob_public = hex("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f");
+    match dh.dh(&alice, &bob_public) {
+        Ok(shared) => {
+            shared[..]
+                == hex("4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742")[..]
+        }
+        Err(_) => false,
+    }
+}
+
+/// A full XK handshake between two instances inside the TA, followed by a
+/// transport message in each direction.
+fn noise_loopback<H: NoiseHash>() -> bool {
+    let dh = dh::x25519();
+    let initiator_keypair = dh.generate_keypair(&mut PatatRng);
+    let responder_keypair = dh.generate_keypair(&mut PatatRng);
+    let responder_public = responder_keypair.public().to_vec();
+
+    let mut initiator: HandshakeState<H> = HandshakeState::initialize(
+        dh::x25519(),
+        PatatRng,
+        b"selftest",
+        initiator_keypair,
+        Some(responder_public),
+    );
+    let mut responder: HandshakeState<H> =
+        HandshakeState::initialize(dh::x25519(), PatatRng, b"selftest", responder_keypair, None);
+
+    if handshake(&mut initiator, &mut responder) != Ok(true) {
+        return false;
+    }
+
+    initiator.to_transport_mode();
+    responder.to_transport_mode();
+    let to_responder = initiator.encrypt(b"four");
+    let to_initiator = responder.encrypt(b"five");
+    responder.decrypt(&to_responder) == Ok(b"four".to_vec())
+        && initiator.decrypt(&to_initiator) == Ok(b"five".to_vec())
+}
+
+fn handshake<H: NoiseHash>(
+    initiator: &mut HandshakeState<H>,
+    responder: &mut HandshakeState<H>,
+) -> Result<bool, NoiseError> {
+    let message = initiator.write_message_1(b"one")?;
+    let one = responder.read_message_1(&message)?;
+    let message = responder.write_message_2(b"two")?;
+    let two = initiator.read_message_2(&message)?;
+    let message = initiator.write_message_3(b"three")?;
+    let three = responder.read_message_3(&message)?;
+    Ok(one == b"one" && two == b"two" && three == b"three")
+}
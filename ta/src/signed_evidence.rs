@@ -0,0 +1,195 @@
+//! Evidence signed with the TA's X25519 identity key.
+//!
+//! Evidence sent during an attestation is only authenticated by the Noise
+//! session it travels in. A `SignedEvidence` bundle carries its own XEdDSA
+//! signature over the evidence root, a nonce chosen by whoever asked for it
+//! and the time it was made, so it can be stored, forwarded and checked later
+//! against the static key enrolled for the device.
+
+use std::convert::TryInto;
+
+use rand_core::{CryptoRng, RngCore};
+
+use crate::ed25519::{Sha512Hash, SIGNATURE_LEN};
+use crate::evidence::EvidenceProof;
+use crate::hasher::{NoiseHash, Sha256};
+use crate::x25519::{PublicKey, StaticSecret};
+
+/// Length of the nonce in a bundle.
+pub const NONCE_LEN: usize = proto::EVIDENCE_NONCE_LEN;
+
+/// Prefix of the signed messages, so that a signature by the identity key
+/// over evidence cannot be taken for one over anything else.
+const CONTEXT: &[u8] = b"patat-signed-evidence";
+
+/// A proof for the first measurement, with the signature over its root.
+///
+/// The timestamp is the REE time, which the normal world sets, so the host
+/// can backdate or postdate a bundle. It is only informational: a bundle is
+/// fresh if its nonce is one the verifier chose for it.
+///
+/// Encoded as the length of the name of the hash function of the evidence
+/// tree in one byte, that name, the timestamp as a 64-bit big-endian number,
+/// the nonce, the signature and finally the encoded `EvidenceProof`.
+pub struct SignedEvidence<H: NoiseHash = Sha256> {
+    proof: EvidenceProof<H>,
+    nonce: [u8; NONCE_LEN],
+    timestamp: u64,
+    signature: [u8; SIGNATURE_LEN],
+}
+
+/// The message that is signed: `CONTEXT`, the length and name of the hash
+/// function, the root, the nonce and the timestamp.
+fn message<H: NoiseHash>(root: &[u8], nonce: &[u8], timestamp: u64) -> Vec<u8> {
+    let mut message = CONTEXT.to_vec();
+    message.push(H::NAME.len() as u8);
+    message.extend_from_slice(H::NAME.as_bytes());
+    message.extend_from_slice(root);
+    message.extend_from_slice(nonce);
+    message.extend_from_slice(&timestamp.to_be_bytes());
+    message
+}
+
+/// The name of the hash function of an encoded bundle, to pick `H` for
+/// `SignedEvidence::from_bytes`.
+pub fn hash_name(bundle: &[u8]) -> Option<&str> {
+    let length = *bundle.first()? as usize;
+    std::str::from_utf8(bundle.get(1..1 + length)?).ok()
+}
+
+impl<H: NoiseHash> SignedEvidence<H> {
+    /// Sign the root of `proof`, which must have one, with the XEdDSA key
+    /// `key` and SHA-512 as `D`.
+    ///
+    /// `timestamp` is in seconds; the TA uses the REE time, as for tickets,
+    /// which is not authenticated. `csprng` randomizes the signature.
+    pub fn sign<D: Sha512Hash, T: RngCore + CryptoRng>(
+        proof: EvidenceProof<H>,
+        nonce: [u8; NONCE_LEN],
+        timestamp: u64,
+        key: &StaticSecret,
+        csprng: T,
+    ) -> Self {
+        let root = proof.root().expect("evidence proof without a root");
+        let message = message::<H>(root, &nonce, timestamp);
+        let signature = key.xeddsa_sign::<D, _>(&message, csprng);
+        SignedEvidence {
+            proof,
+            nonce,
+            timestamp,
+            signature,
+        }
+    }
+
+    pub fn proof(&self) -> &EvidenceProof<H> {
+        &self.proof
+    }
+
+    pub fn nonce(&self) -> &[u8; NONCE_LEN] {
+        &self.nonce
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// Check the signature with the enrolled X25519 `key` and then the proof.
+    ///
+    /// The caller still has to compare the nonce with the one it asked for,
+    /// which is what makes the bundle fresh; the timestamp is not trusted.
+    pub fn verify<D: Sha512Hash>(self, key: &PublicKey) -> bool {
+        let message = match self.proof.root() {
+            Some(root) => message::<H>(root, &self.nonce, self.timestamp),
+            None => return false,
+        };
+        key.xeddsa_verify::<D>(&message, &self.signature) && self.proof.valid()
+    }
+
+    /// Parse an encoded bundle, `None` if it is malformed or for another hash
+    /// function than `H`.
+    pub fn from_bytes(bundle: &[u8]) -> Option<Self> {
+        if hash_name(bundle)? != H::NAME {
+            return None;
+        }
+        let rest = &bundle[1 + H::NAME.len()..];
+        if rest.len() <= 8 + NONCE_LEN + SIGNATURE_LEN {
+            return None;
+        }
+        let (timestamp, rest) = rest.split_at(8);
+        let (nonce, rest) = rest.split_at(NONCE_LEN);
+        let (signature, proof) = rest.split_at(SIGNATURE_LEN);
+        Some(SignedEvidence {
+            proof: EvidenceProof::from_bytes(proof)?,
+            nonce: nonce.try_into().unwrap(),
+            timestamp: u64::from_be_bytes(timestamp.try_into().unwrap()),
+            signature: signature.try_into().unwrap(),
+        })
+    }
+}
+
+impl<H: NoiseHash> From<SignedEvidence<H>> for Vec<u8> {
+    fn from(value: SignedEvidence<H>) -> Self {
+        let mut bundle = vec![H::NAME.len() as u8];
+        bundle.extend_from_slice(H::NAME.as_bytes());
+        bundle.extend_from_slice(&value.timestamp.to_be_bytes());
+        bundle.extend_from_slice(&value.nonce);
+        bundle.extend_from_slice(&value.signature);
+        bundle.append(&mut value.proof.into());
+        bundle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evidence::get_evidence;
+    use crate::hasher::Blake2s;
+    use crate::test_support::SoftSha512;
+    use rand_chacha::ChaCha20Rng;
+    use rand_core::SeedableRng;
+
+    fn bundle(key: &StaticSecret) -> Vec<u8> {
+        let mut rng = ChaCha20Rng::from_seed([7; 32]);
+        let evidence = (0..10u8).map(|i| vec![i; Blake2s::HASHLEN]);
+        let proof = get_evidence::<Blake2s, _, _>(evidence, &mut rng);
+        SignedEvidence::sign::<SoftSha512, _>(proof, [3; NONCE_LEN], 1_700_000_000, key, &mut rng)
+            .into()
+    }
+
+    fn verify(bundle: &[u8], key: &StaticSecret) -> bool {
+        SignedEvidence::<Blake2s>::from_bytes(bundle).map_or(false, |signed| {
+            signed.verify::<SoftSha512>(&PublicKey::from(key))
+        })
+    }
+
+    #[test]
+    fn bundles_verify_offline() {
+        let key = StaticSecret::from([1; 32]);
+        let bundle = bundle(&key);
+        assert_eq!(hash_name(&bundle), Some("BLAKE2s"));
+
+        let signed = SignedEvidence::<Blake2s>::from_bytes(&bundle).unwrap();
+        assert_eq!(signed.nonce(), &[3; NONCE_LEN]);
+        assert_eq!(signed.timestamp(), 1_700_000_000);
+        assert_eq!(signed.proof().measurement(), Some(&[0; 32][..]));
+        assert!(verify(&bundle, &key));
+
+        let other = StaticSecret::from([2; 32]);
+        assert!(!verify(&bundle, &other));
+        assert!(SignedEvidence::<Sha256>::from_bytes(&bundle).is_none());
+        assert!(SignedEvidence::<Blake2s>::from_bytes(&bundle[..100]).is_none());
+    }
+
+    #[test]
+    fn signed_fields_cannot_be_changed() {
+        let key = StaticSecret::from([1; 32]);
+        let bundle = bundle(&key);
+        // The timestamp, the nonce, the signature and the root.
+        let offset = 1 + "BLAKE2s".len();
+        for i in [offset + 7, offset + 8, offset + 40, bundle.len() - 1] {
+            let mut tampered = bundle.clone();
+            tampered[i] ^= 1;
+            assert!(!verify(&tampered, &key));
+        }
+    }
+}
@@ -0,0 +1,383 @@
+//! std-based stand-ins for the `optee_utee` APIs in `crate::tee`.
+//!
+//! They keep the signatures of the originals, so the TA code compiles
+//! unchanged. Secure storage only lives as long as the thread that uses it,
+//! so every test starts out with empty storage.
+
+// std
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::BitOr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// libraries
+use hmac::{Hmac, Mac as _};
+use sha2::Digest as _;
+use zeroize::Zeroizing;
+
+macro_rules! trace_println {
+    ($($arg:tt)*) => {
+        eprintln!($($arg)*)
+    };
+}
+pub(crate) use trace_println;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    Generic,
+    AccessConflict,
+    BadParameters,
+    ItemNotFound,
+    NotSupported,
+    ShortBuffer,
+}
+
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind) -> Error {
+        Error { kind }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.kind)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlgorithmId {
+    Sha256,
+    Sha512,
+    HmacSha256,
+    HmacSha512,
+}
+
+enum DigestState {
+    Sha256(sha2::Sha256),
+    Sha512(sha2::Sha512),
+}
+
+pub struct Digest {
+    state: RefCell<DigestState>,
+}
+
+impl Digest {
+    pub fn allocate(algo: AlgorithmId) -> Result<Self> {
+        let state = match algo {
+            AlgorithmId::Sha256 => DigestState::Sha256(sha2::Sha256::new()),
+            AlgorithmId::Sha512 => DigestState::Sha512(sha2::Sha512::new()),
+            _ => return Err(Error::new(ErrorKind::NotSupported)),
+        };
+        Ok(Digest {
+            state: RefCell::new(state),
+        })
+    }
+
+    pub fn update(&self, chunk: &[u8]) {
+        match &mut *self.state.borrow_mut() {
+            DigestState::Sha256(state) => state.update(chunk),
+            DigestState::Sha512(state) => state.update(chunk),
+        }
+    }
+
+    /// Like `TEE_DigestDoFinal`, this resets the digest for the next message.
+    pub fn do_final(&self, chunk: &[u8], hash: &mut [u8]) -> Result<usize> {
+        self.update(chunk);
+        let output = match &mut *self.state.borrow_mut() {
+            DigestState::Sha256(state) => state.finalize_reset().to_vec(),
+            DigestState::Sha512(state) => state.finalize_reset().to_vec(),
+        };
+        if hash.len() < output.len() {
+            return Err(Error::new(ErrorKind::ShortBuffer));
+        }
+        hash[..output.len()].copy_from_slice(&output);
+        Ok(output.len())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttributeId {
+    SecretValue,
+}
+
+pub struct AttributeMemref<'attrref> {
+    id: AttributeId,
+    buffer: &'attrref [u8],
+}
+
+impl<'attrref> AttributeMemref<'attrref> {
+    pub fn from_ref(id: AttributeId, buffer: &'attrref [u8]) -> Self {
+        AttributeMemref { id, buffer }
+    }
+}
+
+pub struct Attribute {
+    id: AttributeId,
+    value: Zeroizing<Vec<u8>>,
+}
+
+impl<'attrref> From<AttributeMemref<'attrref>> for Attribute {
+    fn from(attribute: AttributeMemref) -> Self {
+        Attribute {
+            id: attribute.id,
+            value: Zeroizing::new(attribute.buffer.to_vec()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransientObjectType {
+    HmacSha256,
+    HmacSha512,
+}
+
+/// A secret-value key object.
+pub struct TransientObject {
+    secret: Zeroizing<Vec<u8>>,
+}
+
+impl TransientObject {
+    pub fn allocate(_object_type: TransientObjectType, _max_object_size: usize) -> Result<Self> {
+        Ok(TransientObject {
+            secret: Zeroizing::new(Vec::new()),
+        })
+    }
+
+    pub fn populate(&mut self, attrs: &[Attribute]) -> Result<()> {
+        match attrs
+            .iter()
+            .find(|attr| attr.id == AttributeId::SecretValue)
+        {
+            Some(attr) => {
+                self.secret = attr.value.clone();
+                Ok(())
+            }
+            None => Err(Error::new(ErrorKind::BadParameters)),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.secret = Zeroizing::new(Vec::new());
+    }
+}
+
+pub struct Mac {
+    algorithm: AlgorithmId,
+    key: RefCell<Zeroizing<Vec<u8>>>,
+}
+
+impl Mac {
+    pub fn allocate(algo: AlgorithmId, _max_key_size: usize) -> Result<Self> {
+        match algo {
+            AlgorithmId::HmacSha256 | AlgorithmId::HmacSha512 => Ok(Mac {
+                algorithm: algo,
+                key: RefCell::new(Zeroizing::new(Vec::new())),
+            }),
+            _ => Err(Error::new(ErrorKind::NotSupported)),
+        }
+    }
+
+    pub fn set_key(&self, object: &TransientObject) -> Result<()> {
+        *self.key.borrow_mut() = object.secret.clone();
+        Ok(())
+    }
+
+    pub fn init(&self, _iv: &[u8]) {}
+
+    pub fn compute_final(&self, message: &[u8], mac: &mut [u8]) -> Result<usize> {
+        let key = self.key.borrow();
+        let output = match self.algorithm {
+            AlgorithmId::HmacSha256 => Hmac::<sha2::Sha256>::new_from_slice(&key)
+                .unwrap()
+                .chain_update(message)
+                .finalize()
+                .into_bytes()
+                .to_vec(),
+            _ => Hmac::<sha2::Sha512>::new_from_slice(&key)
+                .unwrap()
+                .chain_update(message)
+                .finalize()
+                .into_bytes()
+                .to_vec(),
+        };
+        if mac.len() < output.len() {
+            return Err(Error::new(ErrorKind::ShortBuffer));
+        }
+        mac[..output.len()].copy_from_slice(&output);
+        Ok(output.len())
+    }
+}
+
+pub struct Random;
+
+impl Random {
+    pub fn generate(res_buffer: &mut [u8]) {
+        getrandom::getrandom(res_buffer).expect("the OS RNG failed");
+    }
+}
+
+pub struct Time {
+    pub seconds: u32,
+    pub millis: u32,
+}
+
+impl Time {
+    pub fn new() -> Self {
+        Time {
+            seconds: 0,
+            millis: 0,
+        }
+    }
+
+    pub fn system_time(&mut self) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        self.seconds = now.as_secs() as u32;
+        self.millis = now.subsec_millis();
+    }
+
+    /// Outside of OP-TEE there is no separate REE clock.
+    pub fn ree_time(&mut self) {
+        self.system_time();
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum ObjectStorageConstants {
+    Private,
+}
+
+#[derive(Clone, Copy)]
+pub struct DataFlag(u32);
+
+impl DataFlag {
+    pub const ACCESS_READ: DataFlag = DataFlag(0x1);
+    pub const ACCESS_WRITE: DataFlag = DataFlag(0x2);
+    pub const ACCESS_WRITE_META: DataFlag = DataFlag(0x4);
+    pub const OVERWRITE: DataFlag = DataFlag(0x400);
+
+    fn contains(self, other: DataFlag) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for DataFlag {
+    type Output = DataFlag;
+
+    fn bitor(self, rhs: DataFlag) -> DataFlag {
+        DataFlag(self.0 | rhs.0)
+    }
+}
+
+pub struct ObjectInfo {
+    data_size: usize,
+}
+
+impl ObjectInfo {
+    pub fn data_size(&self) -> usize {
+        self.data_size
+    }
+}
+
+thread_local! {
+    /// Persistent objects by object id.
+    static STORAGE: RefCell<BTreeMap<Vec<u8>, Vec<u8>>> = RefCell::new(BTreeMap::new());
+}
+
+pub struct PersistentObject {
+    id: Vec<u8>,
+    data: Zeroizing<Vec<u8>>,
+}
+
+impl PersistentObject {
+    pub fn open(
+        _storage_id: ObjectStorageConstants,
+        object_id: &mut [u8],
+        _flags: DataFlag,
+    ) -> Result<Self> {
+        match STORAGE.with(|storage| storage.borrow().get(&object_id[..]).cloned()) {
+            Some(data) => Ok(PersistentObject {
+                id: object_id.to_vec(),
+                data: Zeroizing::new(data),
+            }),
+            None => Err(Error::new(ErrorKind::ItemNotFound)),
+        }
+    }
+
+    pub fn create(
+        _storage_id: ObjectStorageConstants,
+        object_id: &mut [u8],
+        flags: DataFlag,
+        _attributes: Option<&TransientObject>,
+        initial_data: &[u8],
+    ) -> Result<Self> {
+        STORAGE.with(|storage| {
+            let mut storage = storage.borrow_mut();
+            if storage.contains_key(&object_id[..]) && !flags.contains(DataFlag::OVERWRITE) {
+                return Err(Error::new(ErrorKind::AccessConflict));
+            }
+            storage.insert(object_id.to_vec(), initial_data.to_vec());
+            Ok(())
+        })?;
+        Ok(PersistentObject {
+            id: object_id.to_vec(),
+            data: Zeroizing::new(initial_data.to_vec()),
+        })
+    }
+
+    pub fn info(&self) -> Result<ObjectInfo> {
+        Ok(ObjectInfo {
+            data_size: self.data.len(),
+        })
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> Result<u32> {
+        let length = buf.len().min(self.data.len());
+        buf[..length].copy_from_slice(&self.data[..length]);
+        Ok(length as u32)
+    }
+
+    pub fn close_and_delete(&mut self) -> Result<()> {
+        STORAGE.with(|storage| storage.borrow_mut().remove(&self.id));
+        Ok(())
+    }
+}
+
+pub mod net {
+    use std::io::{self, Read, Write};
+
+    pub struct TcpStream(std::net::TcpStream);
+
+    impl TcpStream {
+        pub fn connect(host: &str, port: u16) -> io::Result<Self> {
+            std::net::TcpStream::connect((host, port)).map(TcpStream)
+        }
+    }
+
+    impl Read for TcpStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for TcpStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.flush()
+        }
+    }
+}
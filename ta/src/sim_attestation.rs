@@ -0,0 +1,419 @@
+//! End-to-end attestation against a verifier on localhost, run on the `sim`
+//! backend.
+//!
+//! The verifier is a `snow` responder speaking the same length-prefixed
+//! framing as the Python verifier, so the TA side runs exactly the code that
+//! runs in OP-TEE.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+
+use proto::Verifier;
+use rand_core::RngCore;
+
+use crate::certificate::{Certificate, CertificateChain};
+use crate::dh::{self, Dh, X25519Dalek};
+use crate::ed25519::{SigningKey, VerifyingKey, SEED_LEN};
+use crate::evidence::{get_evidence, EvidenceLog, EvidenceProof, EvidenceUpdate, EVIDENCE_UPDATE};
+use crate::hasher::{NoiseHash, Sha256, Sha512};
+use crate::identity;
+use crate::log_store;
+use crate::patat_participant::{ConnectError, PatatTA, ResumeError};
+use crate::random::PatatRng;
+use crate::selftest;
+use crate::tee::ErrorKind;
+use crate::ticket::{self, Ticket, PSK_LEN, TICKET_REQUEST};
+use crate::timing::PhaseTimer;
+
+const VERIFIER_SECRET: [u8; 32] = [0x5a; 32];
+const MAX_MESSAGE_LEN: usize = 65535;
+
+fn receive(stream: &mut TcpStream) -> Vec<u8> {
+    let mut length = [0u8; 2];
+    stream.read_exact(&mut length).unwrap();
+    let mut message = vec![0u8; u16::from_be_bytes(length) as usize];
+    stream.read_exact(&mut message).unwrap();
+    message
+}
+
+fn send(stream: &mut TcpStream, message: &[u8]) {
+    stream
+        .write_all(&(message.len() as u16).to_be_bytes())
+        .unwrap();
+    stream.write_all(message).unwrap();
+}
+
+/// The verifier's end of an attested channel.
+struct Channel {
+    stream: TcpStream,
+    transport: snow::TransportState,
+}
+
+impl Channel {
+    fn read(&mut self) -> Vec<u8> {
+        let mut buffer = vec![0u8; MAX_MESSAGE_LEN];
+        let len = self
+            .transport
+            .read_message(&receive(&mut self.stream), &mut buffer)
+            .unwrap();
+        buffer.truncate(len);
+        buffer
+    }
+
+    fn write(&mut self, payload: &[u8]) {
+        let mut buffer = vec![0u8; MAX_MESSAGE_LEN];
+        let len = self.transport.write_message(payload, &mut buffer).unwrap();
+        send(&mut self.stream, &buffer[..len]);
+    }
+}
+
+/// Accept one attestation and return the channel together with the payloads
+/// of message 1, message 3 and the first transport message.
+fn accept(listener: &TcpListener) -> (Channel, Vec<Vec<u8>>) {
+    let (mut stream, _) = listener.accept().unwrap();
+    let params = format!("Noise_XK_25519_ChaChaPoly_{}", Sha256::NAME);
+    let mut responder = snow::Builder::new(params.parse().unwrap())
+        .local_private_key(&VERIFIER_SECRET)
+        .build_responder()
+        .unwrap();
+    let mut buffer = vec![0u8; MAX_MESSAGE_LEN];
+    let mut payloads = vec![];
+
+    let len = responder
+        .read_message(&receive(&mut stream), &mut buffer)
+        .unwrap();
+    payloads.push(buffer[..len].to_vec());
+    let len = responder.write_message(b"", &mut buffer).unwrap();
+    send(&mut stream, &buffer[..len]);
+    let len = responder
+        .read_message(&receive(&mut stream), &mut buffer)
+        .unwrap();
+    payloads.push(buffer[..len].to_vec());
+
+    let mut channel = Channel {
+        stream,
+        transport: responder.into_transport_mode().unwrap(),
+    };
+    payloads.push(channel.read());
+    channel.write(b"ok");
+    (channel, payloads)
+}
+
+/// Listen on localhost for the verifier.
+fn bind() -> (TcpListener, Verifier) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let verifier = Verifier {
+        host: "127.0.0.1".to_string(),
+        port: listener.local_addr().unwrap().port(),
+        public_key: X25519Dalek
+            .keypair_from_secret(&VERIFIER_SECRET)
+            .public()
+            .to_vec(),
+    };
+    (listener, verifier)
+}
+
+/// Run a verifier on localhost that accepts one attestation and then hands
+/// the channel to `session`.
+fn start_verifier<T: Send + 'static>(
+    session: impl FnOnce(&mut Channel) -> T + Send + 'static,
+) -> (Verifier, JoinHandle<(Vec<Vec<u8>>, T)>) {
+    let (listener, verifier) = bind();
+    let handle = thread::spawn(move || {
+        let (mut channel, payloads) = accept(&listener);
+        (payloads, session(&mut channel))
+    });
+    (verifier, handle)
+}
+
+/// Accept a resumed handshake with `psk` and return the channel together
+/// with the ticket the TA sent.
+fn accept_resumption(listener: &TcpListener, psk: &[u8]) -> (Channel, Vec<u8>) {
+    let (mut stream, _) = listener.accept().unwrap();
+    assert!(receive(&mut stream).is_empty());
+    let ticket = receive(&mut stream);
+
+    let params = format!("Noise_NNpsk0_25519_ChaChaPoly_{}", Sha256::NAME);
+    let mut responder = snow::Builder::new(params.parse().unwrap())
+        .prologue(&ticket)
+        .psk(0, psk)
+        .build_responder()
+        .unwrap();
+    let mut buffer = vec![0u8; MAX_MESSAGE_LEN];
+    responder
+        .read_message(&receive(&mut stream), &mut buffer)
+        .unwrap();
+    let len = responder.write_message(b"", &mut buffer).unwrap();
+    send(&mut stream, &buffer[..len]);
+
+    let channel = Channel {
+        stream,
+        transport: responder.into_transport_mode().unwrap(),
+    };
+    (channel, ticket)
+}
+
+fn connect(verifier: Verifier) -> PatatTA<Sha256> {
+    let dh = dh::x25519();
+    let ta_keypair = identity::load_or_create(dh.as_ref()).unwrap();
+    let certificates = identity::load_certificates(dh.as_ref()).unwrap();
+    let mut timer = PhaseTimer::start();
+    PatatTA::connect(dh, ta_keypair, &certificates, verifier, &mut timer).unwrap()
+}
+
+fn evidence() -> EvidenceProof<Sha256> {
+    let measurements: Vec<Vec<u8>> = (0..8)
+        .map(|_| {
+            let mut measurement = vec![0u8; Sha256::HASHLEN];
+            PatatRng.fill_bytes(&mut measurement);
+            measurement
+        })
+        .collect();
+    get_evidence(measurements, &mut PatatRng)
+}
+
+fn valid(evidence: &[u8]) -> bool {
+    EvidenceProof::<Sha256>::from_bytes(evidence).map_or(false, EvidenceProof::valid)
+}
+
+#[test]
+fn attestation_over_tcp() {
+    let (verifier, verifier_thread) = start_verifier(|channel| channel.read());
+
+    let mut ta = connect(verifier);
+    ta.send_evidence(evidence()).unwrap();
+
+    let (payloads, evidence) = verifier_thread.join().unwrap();
+    // Without certificates, message 3 has an empty payload.
+    assert_eq!(payloads, vec![b"test".to_vec(), vec![], b"test".to_vec()]);
+    assert!(valid(&evidence));
+}
+
+#[test]
+fn unreachable_verifier_is_a_connection_error() {
+    let (listener, verifier) = bind();
+    drop(listener);
+
+    let dh = dh::x25519();
+    let ta_keypair = identity::load_or_create(dh.as_ref()).unwrap();
+    let mut timer = PhaseTimer::start();
+    let result = PatatTA::<Sha256>::connect(dh, ta_keypair, &[], verifier, &mut timer);
+    assert!(matches!(result, Err(ConnectError::Io(_))));
+}
+
+#[test]
+fn unreachable_verifier_is_a_connection_error_on_resumption() {
+    let (listener, verifier) = bind();
+    drop(listener);
+
+    let dh = dh::x25519();
+    let mut reply = 3600u32.to_be_bytes().to_vec();
+    reply.extend_from_slice(&[0; PSK_LEN]);
+    reply.extend_from_slice(b"sealed");
+    let ticket = Ticket::from_reply(&reply, ticket::now()).unwrap();
+
+    let mut timer = PhaseTimer::start();
+    let result = PatatTA::<Sha256>::resume(dh, &ticket, &verifier, &mut timer);
+    assert!(matches!(result, Err(ResumeError::Connect(_))));
+}
+
+#[test]
+fn certificates_are_sent_in_message_3() {
+    let dh = dh::x25519();
+    let keypair = identity::load_or_create(dh.as_ref()).unwrap();
+    let manufacturer = SigningKey::<Sha512>::from_seed(&[1; SEED_LEN]);
+    let device = Certificate::issue(
+        dh.name(),
+        keypair.public(),
+        "simulated device",
+        0,
+        u64::MAX,
+        &manufacturer,
+    );
+    let chain: Vec<u8> = CertificateChain::new(vec![device]).into();
+    identity::store_certificates(dh.as_ref(), &chain).unwrap();
+
+    let (verifier, verifier_thread) =
+        start_verifier(|channel| channel.transport.get_remote_static().unwrap().to_vec());
+    connect(verifier);
+
+    let (payloads, static_key) = verifier_thread.join().unwrap();
+    let chain = CertificateChain::from_bytes(&payloads[1]).unwrap();
+    let anchors = [VerifyingKey::from(&manufacturer)];
+    assert_eq!(
+        chain.validate(&anchors, "25519", &static_key, ticket::now()),
+        Ok("simulated device")
+    );
+}
+
+#[test]
+fn certificates_for_another_key_are_rejected() {
+    let dh = dh::x25519();
+    let keypair = identity::load_or_create(dh.as_ref()).unwrap();
+    let manufacturer = SigningKey::<Sha512>::from_seed(&[1; SEED_LEN]);
+    let issue = |key_type: &str, public_key: &[u8]| -> Vec<u8> {
+        let device = Certificate::issue(key_type, public_key, "device", 0, u64::MAX, &manufacturer);
+        CertificateChain::new(vec![device]).into()
+    };
+
+    let other = dh.generate_keypair(&mut PatatRng);
+    for chain in [
+        issue(dh.name(), other.public()),
+        issue("FFDHE2048", keypair.public()),
+        b"not a chain".to_vec(),
+    ]
+    .iter()
+    {
+        let result = identity::store_certificates(dh.as_ref(), chain);
+        assert_eq!(
+            result.err().map(|e| e.kind()),
+            Some(ErrorKind::BadParameters)
+        );
+    }
+    assert!(identity::load_certificates(dh.as_ref()).unwrap().is_empty());
+
+    let chain = issue(dh.name(), keypair.public());
+    identity::store_certificates(dh.as_ref(), &chain).unwrap();
+    assert_eq!(identity::load_certificates(dh.as_ref()).unwrap(), chain);
+}
+
+#[test]
+fn channel_is_reused_after_attestation() {
+    let (verifier, verifier_thread) = start_verifier(|channel| {
+        let rounds = vec![channel.read(), channel.read()];
+        let message = channel.read();
+        channel.write(b"pong");
+        (rounds, message)
+    });
+
+    let mut ta = connect(verifier);
+    ta.send_evidence(evidence()).unwrap();
+    ta.send_evidence(evidence()).unwrap();
+    ta.send(b"ping").unwrap();
+    assert_eq!(ta.receive().unwrap(), b"pong");
+
+    let (_, (rounds, message)) = verifier_thread.join().unwrap();
+    assert!(rounds.iter().all(|evidence| valid(evidence)));
+    assert_eq!(message, b"ping");
+}
+
+#[test]
+fn evidence_log_updates_over_the_channel() {
+    let (verifier, verifier_thread) = start_verifier(|channel| {
+        channel.read();
+        let mut previous: Option<(u64, Vec<u8>)> = None;
+        for _ in 0..2 {
+            let message = channel.read();
+            assert!(message.starts_with(EVIDENCE_UPDATE));
+            let update =
+                EvidenceUpdate::<Sha256>::from_bytes(&message[EVIDENCE_UPDATE.len()..]).unwrap();
+            let expected = previous
+                .as_ref()
+                .map(|(size, root)| (*size, root.as_slice()));
+            assert!(update.valid(expected));
+            previous = Some((update.size(), update.root().to_vec()));
+        }
+        previous.unwrap().0
+    });
+
+    let mut ta = connect(verifier);
+    ta.send_evidence(evidence()).unwrap();
+    let mut log = EvidenceLog::<Sha256>::new();
+    for round in 0..2u8 {
+        for i in 0..5 {
+            log.append(&[round, i]);
+        }
+        ta.send_evidence_update(log.report().unwrap()).unwrap();
+    }
+
+    let (_, size) = verifier_thread.join().unwrap();
+    assert_eq!(size, 10);
+}
+
+#[test]
+fn evidence_log_outlives_the_session() {
+    let mut previous: Option<(u64, Vec<u8>)> = None;
+    for round in 0..3u8 {
+        // Every round loads the log anew, as a new session would.
+        let mut log = log_store::load::<Sha512>().unwrap();
+        assert_eq!(log.len(), 4 * round as u64);
+        for i in 0..4 {
+            log.append(&[round, i]);
+        }
+        let update = log.report().unwrap();
+        log_store::store(&log).unwrap();
+
+        let expected = previous
+            .as_ref()
+            .map(|(size, root)| (*size, root.as_slice()));
+        assert!(update.valid(expected));
+        previous = Some((update.size(), update.root().to_vec()));
+    }
+}
+
+#[test]
+fn resumption_with_a_ticket() {
+    const PSK: [u8; PSK_LEN] = [0x77; PSK_LEN];
+    let (listener, verifier) = bind();
+    let verifier_thread = thread::spawn(move || {
+        let (mut channel, _) = accept(&listener);
+        channel.read();
+        assert_eq!(channel.read(), TICKET_REQUEST);
+        let mut reply = 3600u32.to_be_bytes().to_vec();
+        reply.extend_from_slice(&PSK);
+        reply.extend_from_slice(b"sealed");
+        channel.write(&reply);
+
+        let (mut channel, ticket) = accept_resumption(&listener, &PSK);
+        (ticket, channel.read())
+    });
+
+    let dh = dh::x25519();
+    let mut ta = connect(verifier.clone());
+    ta.send_evidence(evidence()).unwrap();
+    let ticket = ta.request_ticket().unwrap().unwrap();
+    ticket::store::<Sha256>(&verifier, dh.as_ref(), &ticket).unwrap();
+
+    let ticket = ticket::take::<Sha256>(&verifier, dh.as_ref()).unwrap();
+    let reuse = ticket::take::<Sha256>(&verifier, dh.as_ref());
+    assert_eq!(reuse.err().map(|e| e.kind()), Some(ErrorKind::ItemNotFound));
+
+    let mut timer = PhaseTimer::start();
+    let mut ta = PatatTA::<Sha256>::resume(dh, &ticket, &verifier, &mut timer).unwrap();
+    ta.send_evidence(evidence()).unwrap();
+
+    let (sent_ticket, evidence) = verifier_thread.join().unwrap();
+    assert_eq!(sent_ticket, b"sealed");
+    assert!(valid(&evidence));
+}
+
+#[test]
+fn expired_tickets_are_not_used() {
+    let (_, verifier) = bind();
+    let dh = dh::x25519();
+    let mut reply = 1u32.to_be_bytes().to_vec();
+    reply.extend_from_slice(&[0; PSK_LEN]);
+    reply.extend_from_slice(b"sealed");
+    let ticket = Ticket::from_reply(&reply, 0).unwrap();
+
+    ticket::store::<Sha256>(&verifier, dh.as_ref(), &ticket).unwrap();
+    let expired = ticket::take::<Sha256>(&verifier, dh.as_ref());
+    assert_eq!(
+        expired.err().map(|e| e.kind()),
+        Some(ErrorKind::ItemNotFound)
+    );
+}
+
+#[test]
+fn identity_persists_across_loads() {
+    let dh = dh::x25519();
+    let first = identity::load_or_create(dh.as_ref()).unwrap();
+    let second = identity::load_or_create(dh.as_ref()).unwrap();
+    assert_eq!(first.public(), second.public());
+}
+
+#[test]
+fn selftests_pass() {
+    assert_eq!(selftest::run(), (6, 0));
+}
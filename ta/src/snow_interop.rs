@@ -0,0 +1,280 @@
+//! Interoperability tests between `ta::noise` and the `snow` crate.
+//!
+//! Both sides run in-process and exchange messages over an in-memory pipe.
+//! Each hash function is tested with our implementation as initiator and as
+//! responder, followed by a long run of transport messages in both
+//! directions so that nonce handling is exercised well past the handshake.
+//! The SHA-2 tests use the production hashers under the `sim` backend.
+
+use std::collections::VecDeque;
+
+use rand_chacha::ChaCha20Rng;
+use rand_core::SeedableRng;
+
+use crate::dh::{Dh, Keypair, X25519Dalek};
+use crate::hasher::{Blake2s, NoiseHash};
+use crate::noise::HandshakeState;
+use crate::test_support::{Sha256, Sha512};
+
+const PROLOGUE: &[u8] = b"patat interop";
+const TRANSPORT_MESSAGES: usize = 500;
+const MAX_MESSAGE_LEN: usize = 65535;
+
+/// Two one-way queues standing in for the TCP connection.
+#[derive(Default)]
+struct Pipe {
+    to_responder: VecDeque<Vec<u8>>,
+    to_initiator: VecDeque<Vec<u8>>,
+}
+
+fn static_keys() -> (Keypair, Keypair) {
+    (
+        X25519Dalek.keypair_from_secret(&[0x11; 32]),
+        X25519Dalek.keypair_from_secret(&[0x22; 32]),
+    )
+}
+
+fn snow_params<H: NoiseHash>() -> snow::params::NoiseParams {
+    format!("Noise_XK_25519_ChaChaPoly_{}", H::NAME)
+        .parse()
+        .unwrap()
+}
+
+/// Payload of transport message `i`; the lengths vary so that empty and
+/// multi-block messages are covered.
+fn payload(i: usize) -> Vec<u8> {
+    (0..(i * 7) % 300).map(|j| (i + j) as u8).collect()
+}
+
+fn snow_write(state: &mut snow::HandshakeState, payload: &[u8]) -> Vec<u8> {
+    let mut buffer = vec![0u8; MAX_MESSAGE_LEN];
+    let len = state.write_message(payload, &mut buffer).unwrap();
+    buffer.truncate(len);
+    buffer
+}
+
+fn snow_read(state: &mut snow::HandshakeState, message: &[u8]) -> Vec<u8> {
+    let mut buffer = vec![0u8; MAX_MESSAGE_LEN];
+    let len = state.read_message(message, &mut buffer).unwrap();
+    buffer.truncate(len);
+    buffer
+}
+
+fn snow_encrypt(state: &mut snow::TransportState, payload: &[u8]) -> Vec<u8> {
+    let mut buffer = vec![0u8; MAX_MESSAGE_LEN];
+    let len = state.write_message(payload, &mut buffer).unwrap();
+    buffer.truncate(len);
+    buffer
+}
+
+fn snow_decrypt(state: &mut snow::TransportState, message: &[u8]) -> Vec<u8> {
+    let mut buffer = vec![0u8; MAX_MESSAGE_LEN];
+    let len = state.read_message(message, &mut buffer).unwrap();
+    buffer.truncate(len);
+    buffer
+}
+
+const PSK: [u8; 32] = [0x33; 32];
+
+/// The resumed handshake, with our implementation as initiator and as
+/// responder.
+fn resumption<H: NoiseHash>() {
+    let params: snow::params::NoiseParams = format!("Noise_NNpsk0_25519_ChaChaPoly_{}", H::NAME)
+        .parse()
+        .unwrap();
+
+    let mut initiator: HandshakeState<H, ChaCha20Rng> = HandshakeState::initialize_resumption(
+        Box::new(X25519Dalek),
+        ChaCha20Rng::from_seed([3; 32]),
+        PROLOGUE,
+        &PSK,
+        true,
+    );
+    let mut responder = snow::Builder::new(params.clone())
+        .prologue(PROLOGUE)
+        .psk(0, &PSK)
+        .build_responder()
+        .unwrap();
+
+    let message = initiator.write_resumption_message_1(b"one");
+    assert_eq!(snow_read(&mut responder, &message), b"one");
+    let message = snow_write(&mut responder, b"two");
+    assert_eq!(initiator.read_message_2(&message).unwrap(), b"two");
+    assert_eq!(initiator.handshake_hash(), responder.get_handshake_hash());
+
+    initiator.to_transport_mode();
+    let mut responder = responder.into_transport_mode().unwrap();
+    let message = initiator.encrypt(b"three");
+    assert_eq!(snow_decrypt(&mut responder, &message), b"three");
+    let message = snow_encrypt(&mut responder, b"four");
+    assert_eq!(initiator.decrypt(&message).unwrap(), b"four");
+
+    let mut initiator = snow::Builder::new(params)
+        .prologue(PROLOGUE)
+        .psk(0, &PSK)
+        .build_initiator()
+        .unwrap();
+    let mut responder: HandshakeState<H, ChaCha20Rng> = HandshakeState::initialize_resumption(
+        Box::new(X25519Dalek),
+        ChaCha20Rng::from_seed([4; 32]),
+        PROLOGUE,
+        &PSK,
+        false,
+    );
+
+    let message = snow_write(&mut initiator, b"one");
+    assert_eq!(responder.read_resumption_message_1(&message).unwrap(), b"one");
+    let message = responder.write_message_2(b"two").unwrap();
+    assert_eq!(snow_read(&mut initiator, &message), b"two");
+    assert_eq!(responder.handshake_hash(), initiator.get_handshake_hash());
+
+    let mut initiator = initiator.into_transport_mode().unwrap();
+    responder.to_transport_mode();
+    let message = snow_encrypt(&mut initiator, b"three");
+    assert_eq!(responder.decrypt(&message).unwrap(), b"three");
+    let message = responder.encrypt(b"four");
+    assert_eq!(snow_decrypt(&mut initiator, &message), b"four");
+}
+
+fn patat_initiator_snow_responder<H: NoiseHash>() {
+    let (initiator_keypair, responder_keypair) = static_keys();
+    let mut pipe = Pipe::default();
+
+    let mut initiator: HandshakeState<H, ChaCha20Rng> = HandshakeState::initialize(
+        Box::new(X25519Dalek),
+        ChaCha20Rng::from_seed([1; 32]),
+        PROLOGUE,
+        initiator_keypair,
+        Some(responder_keypair.public().to_vec()),
+    );
+    let mut responder = snow::Builder::new(snow_params::<H>())
+        .prologue(PROLOGUE)
+        .local_private_key(responder_keypair.secret())
+        .build_responder()
+        .unwrap();
+
+    pipe.to_responder
+        .push_back(initiator.write_message_1(b"one").unwrap());
+    let message = pipe.to_responder.pop_front().unwrap();
+    assert_eq!(snow_read(&mut responder, &message), b"one");
+
+    pipe.to_initiator
+        .push_back(snow_write(&mut responder, b"two"));
+    let message = pipe.to_initiator.pop_front().unwrap();
+    assert_eq!(initiator.read_message_2(&message).unwrap(), b"two");
+
+    pipe.to_responder
+        .push_back(initiator.write_message_3(b"three").unwrap());
+    let message = pipe.to_responder.pop_front().unwrap();
+    assert_eq!(snow_read(&mut responder, &message), b"three");
+
+    assert_eq!(initiator.handshake_hash(), responder.get_handshake_hash());
+    initiator.to_transport_mode();
+    let mut responder = responder.into_transport_mode().unwrap();
+
+    for i in 0..TRANSPORT_MESSAGES {
+        pipe.to_responder.push_back(initiator.encrypt(&payload(i)));
+        pipe.to_initiator
+            .push_back(snow_encrypt(&mut responder, &payload(i + 1)));
+
+        let message = pipe.to_responder.pop_front().unwrap();
+        assert_eq!(snow_decrypt(&mut responder, &message), payload(i));
+        let message = pipe.to_initiator.pop_front().unwrap();
+        assert_eq!(initiator.decrypt(&message).unwrap(), payload(i + 1));
+    }
+}
+
+fn snow_initiator_patat_responder<H: NoiseHash>() {
+    let (initiator_keypair, responder_keypair) = static_keys();
+    let mut pipe = Pipe::default();
+
+    let mut initiator = snow::Builder::new(snow_params::<H>())
+        .prologue(PROLOGUE)
+        .local_private_key(initiator_keypair.secret())
+        .remote_public_key(responder_keypair.public())
+        .build_initiator()
+        .unwrap();
+    let mut responder: HandshakeState<H, ChaCha20Rng> = HandshakeState::initialize(
+        Box::new(X25519Dalek),
+        ChaCha20Rng::from_seed([2; 32]),
+        PROLOGUE,
+        responder_keypair,
+        None,
+    );
+
+    pipe.to_responder
+        .push_back(snow_write(&mut initiator, b"one"));
+    let message = pipe.to_responder.pop_front().unwrap();
+    assert_eq!(responder.read_message_1(&message).unwrap(), b"one");
+
+    pipe.to_initiator
+        .push_back(responder.write_message_2(b"two").unwrap());
+    let message = pipe.to_initiator.pop_front().unwrap();
+    assert_eq!(snow_read(&mut initiator, &message), b"two");
+
+    pipe.to_responder
+        .push_back(snow_write(&mut initiator, b"three"));
+    let message = pipe.to_responder.pop_front().unwrap();
+    assert_eq!(responder.read_message_3(&message).unwrap(), b"three");
+
+    assert_eq!(responder.handshake_hash(), initiator.get_handshake_hash());
+    let mut initiator = initiator.into_transport_mode().unwrap();
+    responder.to_transport_mode();
+
+    for i in 0..TRANSPORT_MESSAGES {
+        pipe.to_responder
+            .push_back(snow_encrypt(&mut initiator, &payload(i)));
+        pipe.to_initiator
+            .push_back(responder.encrypt(&payload(i + 1)));
+
+        let message = pipe.to_responder.pop_front().unwrap();
+        assert_eq!(responder.decrypt(&message).unwrap(), payload(i));
+        let message = pipe.to_initiator.pop_front().unwrap();
+        assert_eq!(snow_decrypt(&mut initiator, &message), payload(i + 1));
+    }
+}
+
+#[test]
+fn patat_initiator_snow_responder_sha256() {
+    patat_initiator_snow_responder::<Sha256>();
+}
+
+#[test]
+fn snow_initiator_patat_responder_sha256() {
+    snow_initiator_patat_responder::<Sha256>();
+}
+
+#[test]
+fn patat_initiator_snow_responder_sha512() {
+    patat_initiator_snow_responder::<Sha512>();
+}
+
+#[test]
+fn snow_initiator_patat_responder_sha512() {
+    snow_initiator_patat_responder::<Sha512>();
+}
+
+#[test]
+fn patat_initiator_snow_responder_blake2s() {
+    patat_initiator_snow_responder::<Blake2s>();
+}
+
+#[test]
+fn snow_initiator_patat_responder_blake2s() {
+    snow_initiator_patat_responder::<Blake2s>();
+}
+
+#[test]
+fn resumption_sha256() {
+    resumption::<Sha256>();
+}
+
+#[test]
+fn resumption_sha512() {
+    resumption::<Sha512>();
+}
+
+#[test]
+fn resumption_blake2s() {
+    resumption::<Blake2s>();
+}
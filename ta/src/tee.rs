@@ -0,0 +1,18 @@
+//! The parts of `optee_utee` the TA library uses.
+//!
+//! With the `sim` feature they are replaced by the std-based equivalents in
+//! `crate::sim`, so the library runs as a normal Linux process.
+
+#[cfg(not(feature = "sim"))]
+pub(crate) use optee_utee::{
+    net, trace_println, AlgorithmId, AttributeId, AttributeMemref, DataFlag, Digest, Error, ErrorKind,
+    Mac, ObjectStorageConstants, PersistentObject, Random, Result, Time, TransientObject,
+    TransientObjectType,
+};
+
+#[cfg(feature = "sim")]
+pub(crate) use crate::sim::{
+    net, trace_println, AlgorithmId, AttributeId, AttributeMemref, DataFlag, Digest, Error, ErrorKind,
+    Mac, ObjectStorageConstants, PersistentObject, Random, Result, Time, TransientObject,
+    TransientObjectType,
+};
@@ -0,0 +1,72 @@
+//! Helpers shared by the unit tests: software SHA-2 for the stub, which has
+//! no digest operations, and a few ways to build or corrupt test inputs.
+
+use sha2::Digest;
+
+use crate::ed25519::{Sha512Hash, SIGNATURE_LEN};
+use crate::hasher::NoiseHash;
+
+pub(crate) use crate::selftest::hex;
+
+/// The SHA-2 functions the tests run the handshake with.
+#[cfg(feature = "sim")]
+pub(crate) use crate::hasher::{Sha256, Sha512};
+#[cfg(not(feature = "sim"))]
+pub(crate) use {SoftSha256 as Sha256, SoftSha512 as Sha512};
+
+/// SHA-256 computed in software, since the tests run outside of the TEE.
+#[cfg_attr(feature = "sim", allow(dead_code))]
+#[derive(Default)]
+pub(crate) struct SoftSha256 {
+    state: sha2::Sha256,
+}
+
+impl NoiseHash for SoftSha256 {
+    const NAME: &'static str = "SHA256";
+    const HASHLEN: usize = 32;
+    const BLOCKLEN: usize = 64;
+
+    fn input(&mut self, data: &[u8]) {
+        self.state.update(data);
+    }
+
+    fn result(&mut self, out: &mut [u8]) {
+        let state = std::mem::take(&mut self.state);
+        out.copy_from_slice(&state.finalize());
+    }
+}
+
+/// SHA-512 computed in software, for the Ed25519 tests.
+#[derive(Default)]
+pub(crate) struct SoftSha512 {
+    state: sha2::Sha512,
+}
+
+impl NoiseHash for SoftSha512 {
+    const NAME: &'static str = "SHA512";
+    const HASHLEN: usize = 64;
+    const BLOCKLEN: usize = 128;
+
+    fn input(&mut self, data: &[u8]) {
+        self.state.update(data);
+    }
+
+    fn result(&mut self, out: &mut [u8]) {
+        let state = std::mem::take(&mut self.state);
+        out.copy_from_slice(&state.finalize());
+    }
+}
+
+impl Sha512Hash for SoftSha512 {}
+
+/// Copies of `signature` with a bit flipped in the first and last byte of R
+/// and of s, none of which may verify.
+pub(crate) fn tampered_signatures(
+    signature: [u8; SIGNATURE_LEN],
+) -> impl Iterator<Item = [u8; SIGNATURE_LEN]> {
+    [0, 31, 32, 63].iter().map(move |&i| {
+        let mut tampered = signature;
+        tampered[i] ^= 1;
+        tampered
+    })
+}
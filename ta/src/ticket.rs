@@ -0,0 +1,172 @@
+// TEE
+use crate::tee::{DataFlag, ObjectStorageConstants, PersistentObject, Time};
+use crate::tee::{Error, ErrorKind, Result};
+
+// Protocol
+use proto::Verifier;
+
+// std
+use std::convert::TryInto;
+
+// libraries
+use zeroize::Zeroizing;
+
+// TA Code
+use crate::dh::Dh;
+use crate::hasher::NoiseHash;
+use crate::noise::hash;
+
+/// Transport message that asks the verifier for a resumption ticket.
+pub const TICKET_REQUEST: &[u8] = b"patat-ticket-request";
+
+/// Length of the resumption secret, which is the Noise `psk`.
+pub const PSK_LEN: usize = 32;
+
+/// A resumption ticket, issued by the verifier after a successful
+/// attestation.
+///
+/// The ticket itself is opaque to the TA; the verifier seals whatever it needs
+/// to recognize the session under a key of its own.
+pub struct Ticket {
+    /// REE time in seconds after which the verifier rejects the ticket.
+    expires: u64,
+    psk: Zeroizing<Vec<u8>>,
+    ticket: Vec<u8>,
+}
+
+impl Ticket {
+    /// Parse the verifier's reply to `TICKET_REQUEST`: the lifetime in
+    /// seconds as a big-endian `u32`, the resumption secret and the ticket.
+    ///
+    /// A verifier that does not issue a ticket replies with an empty message.
+    pub fn from_reply(reply: &[u8], now: u64) -> Option<Ticket> {
+        if reply.len() <= 4 + PSK_LEN {
+            return None;
+        }
+        let lifetime = u32::from_be_bytes(reply[..4].try_into().unwrap());
+        Some(Ticket {
+            expires: now + lifetime as u64,
+            psk: Zeroizing::new(reply[4..4 + PSK_LEN].to_vec()),
+            ticket: reply[4 + PSK_LEN..].to_vec(),
+        })
+    }
+
+    pub fn expires(&self) -> u64 {
+        self.expires
+    }
+
+    pub fn psk(&self) -> &[u8] {
+        &self.psk
+    }
+
+    pub fn ticket(&self) -> &[u8] {
+        &self.ticket
+    }
+
+    /// Encode for secure storage as the expiry as a big-endian `u64`,
+    /// followed by the resumption secret and the ticket.
+    fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let mut bytes = Zeroizing::new(self.expires.to_be_bytes().to_vec());
+        bytes.extend_from_slice(&self.psk);
+        bytes.extend_from_slice(&self.ticket);
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Ticket> {
+        if bytes.len() <= 8 + PSK_LEN {
+            return None;
+        }
+        Some(Ticket {
+            expires: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            psk: Zeroizing::new(bytes[8..8 + PSK_LEN].to_vec()),
+            ticket: bytes[8 + PSK_LEN..].to_vec(),
+        })
+    }
+}
+
+/// REE time in seconds.
+///
+/// The REE clock is not trusted, but it only serves to skip tickets that the
+/// verifier would reject anyway; the verifier enforces the lifetime itself.
+pub fn now() -> u64 {
+    let mut time = Time::new();
+    time.ree_time();
+    time.seconds as u64
+}
+
+/// Object ids are at most 64 bytes, so the verifier and DH function are
+/// identified by a truncated hash. As the hash is `H`, every hash function
+/// has its own tickets as well.
+fn object_id<H: NoiseHash>(verifier: &Verifier, dh: &dyn Dh) -> Vec<u8> {
+    let mut input = verifier.to_bytes();
+    input.extend_from_slice(dh.name().as_bytes());
+    let digest: String = hash::<H>(&input)[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("patat-ticket-{}", digest).into_bytes()
+}
+
+/// Store `ticket` for resuming with `verifier`, replacing any previous one.
+pub fn store<H: NoiseHash>(verifier: &Verifier, dh: &dyn Dh, ticket: &Ticket) -> Result<()> {
+    PersistentObject::create(
+        ObjectStorageConstants::Private,
+        &mut object_id::<H>(verifier, dh),
+        DataFlag::ACCESS_READ
+            | DataFlag::ACCESS_WRITE
+            | DataFlag::ACCESS_WRITE_META
+            | DataFlag::OVERWRITE,
+        None,
+        &ticket.to_bytes(),
+    )?;
+    Ok(())
+}
+
+/// Remove the ticket for `verifier` from secure storage and return it.
+///
+/// Tickets are used only once, so resumed sessions cannot be linked through
+/// them. Fails with `ErrorKind::ItemNotFound` if there is no ticket or it
+/// has expired.
+pub fn take<H: NoiseHash>(verifier: &Verifier, dh: &dyn Dh) -> Result<Ticket> {
+    let mut object = PersistentObject::open(
+        ObjectStorageConstants::Private,
+        &mut object_id::<H>(verifier, dh),
+        DataFlag::ACCESS_READ | DataFlag::ACCESS_WRITE_META,
+    )?;
+    let mut bytes = Zeroizing::new(vec![0u8; object.info()?.data_size()]);
+    let read = object.read(&mut bytes)? as usize;
+    object.close_and_delete()?;
+
+    match Ticket::from_bytes(&bytes[..read]) {
+        Some(ticket) if ticket.expires > now() => Ok(ticket),
+        _ => Err(Error::new(ErrorKind::ItemNotFound)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reply_is_parsed_relative_to_now() {
+        let mut reply = 3600u32.to_be_bytes().to_vec();
+        reply.extend_from_slice(&[0x11; PSK_LEN]);
+        reply.extend_from_slice(b"sealed");
+
+        let ticket = Ticket::from_reply(&reply, 1000).unwrap();
+        assert_eq!(ticket.expires(), 4600);
+        assert_eq!(ticket.psk(), &[0x11; PSK_LEN][..]);
+        assert_eq!(ticket.ticket(), b"sealed");
+
+        let stored = Ticket::from_bytes(&ticket.to_bytes()).unwrap();
+        assert_eq!(stored.expires(), 4600);
+        assert_eq!(stored.psk(), ticket.psk());
+        assert_eq!(stored.ticket(), ticket.ticket());
+    }
+
+    #[test]
+    fn declined_or_truncated_replies_are_rejected() {
+        assert!(Ticket::from_reply(&[], 0).is_none());
+        assert!(Ticket::from_reply(&[0; 4 + PSK_LEN], 0).is_none());
+    }
+}
@@ -0,0 +1,120 @@
+// TEE
+use crate::tee::{Result, Time};
+
+// TA Code
+use crate::dh;
+use crate::hasher::NoiseHash;
+use crate::noise::HandshakeState;
+use crate::random::PatatRng;
+
+// Protocol
+use proto::{Phase, TIMINGS_LEN};
+
+/// Time spent in every `Phase` of an attestation, measured with
+/// `TEE_GetSystemTime`.
+pub struct PhaseTimer {
+    last: u64,
+    elapsed: [u32; Phase::ALL.len()],
+}
+
+impl PhaseTimer {
+    pub fn start() -> Self {
+        PhaseTimer {
+            last: now(),
+            elapsed: [0; Phase::ALL.len()],
+        }
+    }
+
+    /// Attribute the time since the previous call, or since `start`, to
+    /// `phase`.
+    pub fn record(&mut self, phase: Phase) {
+        let now = now();
+        self.elapsed[phase as usize] += now.saturating_sub(self.last) as u32;
+        self.last = now;
+    }
+
+    /// The elapsed milliseconds per phase in the layout of `TIMINGS_LEN`.
+    pub fn to_bytes(&self) -> [u8; TIMINGS_LEN] {
+        let mut bytes = [0u8; TIMINGS_LEN];
+        for (chunk, elapsed) in bytes.chunks_exact_mut(4).zip(self.elapsed.iter()) {
+            chunk.copy_from_slice(&elapsed.to_le_bytes());
+        }
+        bytes
+    }
+}
+
+/// `H`, but with new TEE operations for every hash and every HMAC, as the
+/// handshake allocated them before `SymmetricState` kept one hash context.
+#[derive(Default)]
+struct FreshOperations<H: NoiseHash>(Option<H>);
+
+impl<H: NoiseHash> NoiseHash for FreshOperations<H> {
+    const NAME: &'static str = H::NAME;
+    const HASHLEN: usize = H::HASHLEN;
+    const BLOCKLEN: usize = H::BLOCKLEN;
+
+    fn input(&mut self, data: &[u8]) {
+        self.0.get_or_insert_with(H::default).input(data);
+    }
+
+    fn result(&mut self, out: &mut [u8]) {
+        self.0.take().unwrap_or_default().result(out);
+    }
+
+    fn try_hmac(&mut self, key: &[u8], data: &[u8], out: &mut [u8]) -> Result<()> {
+        H::default().try_hmac(key, data, out)
+    }
+}
+
+/// Milliseconds that `handshakes` XK handshakes between two parties inside
+/// the TA take, from generating their static keys up to the split. The first
+/// run allocates new TEE operations for every hash and HMAC, the second
+/// reuses them as the TA does.
+pub fn bench_handshakes<H: NoiseHash>(handshakes: u32) -> (u32, u32) {
+    let start = now();
+    for _ in 0..handshakes {
+        handshake::<FreshOperations<H>>();
+    }
+    let fresh = now().saturating_sub(start);
+
+    let start = now();
+    for _ in 0..handshakes {
+        handshake::<H>();
+    }
+    let reused = now().saturating_sub(start);
+
+    (fresh as u32, reused as u32)
+}
+
+fn handshake<H: NoiseHash>() {
+    let dh = dh::x25519();
+    let initiator_keypair = dh.generate_keypair(&mut PatatRng);
+    let responder_keypair = dh.generate_keypair(&mut PatatRng);
+    let responder_public = responder_keypair.public().to_vec();
+
+    let mut initiator: HandshakeState<H> = HandshakeState::initialize(
+        dh::x25519(),
+        PatatRng,
+        b"bench",
+        initiator_keypair,
+        Some(responder_public),
+    );
+    let mut responder: HandshakeState<H> =
+        HandshakeState::initialize(dh, PatatRng, b"bench", responder_keypair, None);
+
+    let message = initiator.write_message_1(&[]).unwrap();
+    responder.read_message_1(&message).unwrap();
+    let message = responder.write_message_2(&[]).unwrap();
+    initiator.read_message_2(&message).unwrap();
+    let message = initiator.write_message_3(&[]).unwrap();
+    responder.read_message_3(&message).unwrap();
+    initiator.to_transport_mode();
+    responder.to_transport_mode();
+}
+
+/// System time in milliseconds.
+fn now() -> u64 {
+    let mut time = Time::new();
+    time.system_time();
+    time.seconds as u64 * 1000 + time.millis as u64
+}
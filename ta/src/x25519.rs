@@ -13,6 +13,10 @@
 //!
 //! This implements x25519 key exchange as specified by Mike Hamburg
 //! and Adam Langley in [RFC7748](https://tools.ietf.org/html/rfc7748).
+//!
+//! [`StaticSecret`] keys can also sign with
+//! [XEdDSA](https://signal.org/docs/specifications/xeddsa/), so that the key
+//! in the Noise `s` token needs no separate signing key next to it.
 
 use curve25519_dalek::constants::ED25519_BASEPOINT_TABLE;
 use curve25519_dalek::montgomery::MontgomeryPoint;
@@ -22,6 +26,10 @@ use curve25519_dalek::traits::IsIdentity;
 use rand_core::CryptoRng;
 use rand_core::RngCore;
 
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::ed25519::{sha512, Sha512Hash, VerifyingKey, SIGNATURE_LEN};
+
 /// A Diffie-Hellman public key, corresponding to an [`EphemeralSecret`] or
 /// [`StaticSecret`] key.
 ///
@@ -52,6 +60,24 @@ impl PublicKey {
     pub fn as_bytes(&self) -> &[u8; 32] {
         self.0.as_bytes()
     }
+
+    /// Check an XEdDSA `signature` on `message` from the [`StaticSecret`] of
+    /// this key, with SHA-512 as `D`.
+    ///
+    /// The key is converted to the Edwards point with sign bit 0, and the
+    /// signature is then checked as an Ed25519 signature. Keys with a
+    /// non-canonical encoding or that lie on the twist are rejected.
+    pub fn xeddsa_verify<D: Sha512Hash>(&self, message: &[u8], signature: &[u8]) -> bool {
+        let edwards = match self.0.to_edwards(0) {
+            Some(edwards) => edwards,
+            None => return false,
+        };
+        if edwards.to_montgomery().as_bytes() != self.0.as_bytes() {
+            return false;
+        }
+        VerifyingKey::<D>::from_bytes(edwards.compress().as_bytes())
+            .map_or(false, |key| key.verify(message, signature))
+    }
 }
 
 /// A short-lived Diffie-Hellman secret key that can only be used to compute a single
@@ -88,6 +114,12 @@ impl<'a> From<&'a EphemeralSecret> for PublicKey {
     }
 }
 
+impl Drop for EphemeralSecret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
 /// A Diffie-Hellman secret key which may be used more than once, but is
 /// purposefully not serialiseable in order to discourage key-reuse.  This is
 /// implemented to facilitate protocols such as Noise (e.g. Noise IK key usage,
@@ -133,6 +165,12 @@ impl<'a> From<&'a ReusableSecret> for PublicKey {
     }
 }
 
+impl Drop for ReusableSecret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
 /// A Diffie-Hellman secret key that can be used to compute multiple [`SharedSecret`]s.
 ///
 /// This type is identical to the [`EphemeralSecret`] type, except that the
@@ -177,6 +215,48 @@ impl StaticSecret {
     pub fn to_bytes(&self) -> [u8; 32] {
         self.0.to_bytes()
     }
+
+    /// Sign `message` with XEdDSA, with SHA-512 as `D`.
+    ///
+    /// `csprng` provides the 64 random bytes that go into the nonce, so two
+    /// signatures on the same message differ. The signature verifies with
+    /// [`PublicKey::xeddsa_verify`] on the public key of this secret.
+    pub fn xeddsa_sign<D: Sha512Hash, T: RngCore + CryptoRng>(
+        &self,
+        message: &[u8],
+        mut csprng: T,
+    ) -> [u8; SIGNATURE_LEN] {
+        // The Edwards key with sign bit 0 is either kB or -kB.
+        let mut public = (&ED25519_BASEPOINT_TABLE * &self.0).compress();
+        let mut a = self.0.reduce();
+        if public.0[31] & 0x80 != 0 {
+            a = -a;
+            public.0[31] &= 0x7f;
+        }
+
+        let mut random = Zeroizing::new([0u8; 64]);
+        csprng.fill_bytes(&mut *random);
+        let mut r = Scalar::from_bytes_mod_order_wide(&sha512::<D>(&[
+            &XEDDSA_HASH_1_PREFIX,
+            a.as_bytes(),
+            message,
+            &*random,
+        ]));
+        let big_r = (&ED25519_BASEPOINT_TABLE * &r).compress();
+        let h = Scalar::from_bytes_mod_order_wide(&sha512::<D>(&[
+            big_r.as_bytes(),
+            public.as_bytes(),
+            message,
+        ]));
+        let s = r + h * a;
+        r.zeroize();
+        a.zeroize();
+
+        let mut signature = [0u8; SIGNATURE_LEN];
+        signature[..32].copy_from_slice(big_r.as_bytes());
+        signature[32..].copy_from_slice(s.as_bytes());
+        signature
+    }
 }
 
 impl From<[u8; 32]> for StaticSecret {
@@ -193,6 +273,12 @@ impl<'a> From<&'a StaticSecret> for PublicKey {
     }
 }
 
+impl Drop for StaticSecret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
 /// The result of a Diffie-Hellman key exchange.
 ///
 /// Each party computes this using their [`EphemeralSecret`] or [`StaticSecret`] and their
@@ -251,6 +337,20 @@ impl SharedSecret {
     }
 }
 
+impl Drop for SharedSecret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// The input that separates the nonce hash of XEdDSA from the challenge hash:
+/// 2^256 - 2 in little-endian, the prefix of `hash_1` in the specification.
+const XEDDSA_HASH_1_PREFIX: [u8; 32] = {
+    let mut prefix = [0xff; 32];
+    prefix[0] = 0xfe;
+    prefix
+};
+
 /// "Decode" a scalar from a 32-byte array.
 ///
 /// By "decode" here, what is really meant is applying key clamping by twiddling
@@ -327,3 +427,66 @@ impl From<AllowUnreducedScalarBytes> for Scalar {
         clamp_scalar(bytes.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{tampered_signatures, SoftSha512};
+    use rand_chacha::ChaCha20Rng;
+    use rand_core::SeedableRng;
+
+    #[test]
+    fn xeddsa_signatures_verify() {
+        let mut rng = ChaCha20Rng::from_seed([7; 32]);
+        let mut sign_bits = [false; 2];
+        for _ in 0..16 {
+            let secret = StaticSecret::new(&mut rng);
+            let public = PublicKey::from(&secret);
+            let edwards = (&ED25519_BASEPOINT_TABLE * &secret.0).compress();
+            sign_bits[(edwards.as_bytes()[31] >> 7) as usize] = true;
+
+            let signature = secret.xeddsa_sign::<SoftSha512, _>(b"evidence root", &mut rng);
+            assert!(public.xeddsa_verify::<SoftSha512>(b"evidence root", &signature));
+            let again = secret.xeddsa_sign::<SoftSha512, _>(b"evidence root", &mut rng);
+            assert_ne!(signature, again);
+            assert!(public.xeddsa_verify::<SoftSha512>(b"evidence root", &again));
+        }
+        // Both the keys that are negated and the ones that are not.
+        assert_eq!(sign_bits, [true, true]);
+    }
+
+    #[test]
+    fn xeddsa_rejects_tampering() {
+        let mut rng = ChaCha20Rng::from_seed([7; 32]);
+        let secret = StaticSecret::new(&mut rng);
+        let public = PublicKey::from(&secret);
+        let signature = secret.xeddsa_sign::<SoftSha512, _>(b"statement", &mut rng);
+        assert!(!public.xeddsa_verify::<SoftSha512>(b"statemenu", &signature));
+        assert!(!public.xeddsa_verify::<SoftSha512>(b"statement", &signature[..63]));
+        for tampered in tampered_signatures(signature) {
+            assert!(!public.xeddsa_verify::<SoftSha512>(b"statement", &tampered));
+        }
+
+        let other = PublicKey::from(&StaticSecret::new(&mut rng));
+        assert!(!other.xeddsa_verify::<SoftSha512>(b"statement", &signature));
+
+        // The same u with the unused top bit set is not a canonical key.
+        let mut high = public.to_bytes();
+        high[31] |= 0x80;
+        assert!(!PublicKey::from(high).xeddsa_verify::<SoftSha512>(b"statement", &signature));
+    }
+
+    /// Any Ed25519 verifier accepts the signatures, with the Edwards form of
+    /// the key that has sign bit 0.
+    #[test]
+    fn xeddsa_signatures_are_ed25519_signatures() {
+        let mut rng = ChaCha20Rng::from_seed([7; 32]);
+        let secret = StaticSecret::new(&mut rng);
+        let signature = secret.xeddsa_sign::<SoftSha512, _>(b"root", &mut rng);
+
+        let mut edwards = (&ED25519_BASEPOINT_TABLE * &secret.0).compress();
+        edwards.0[31] &= 0x7f;
+        let key = VerifyingKey::<SoftSha512>::from_bytes(edwards.as_bytes()).unwrap();
+        assert!(key.verify(b"root", &signature));
+    }
+}